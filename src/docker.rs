@@ -1,4 +1,60 @@
+use std::{
+	collections::BTreeSet,
+	process::{Command, Stdio},
+	sync::Mutex,
+};
+
+use tracing::warn;
+
 /// This image is explicitly has nothing in it:
 /// https://hub.docker.com/layers/0lach/empty/latest/images/sha256-f84a7c75637bea6fd2f816d4ec4f5b662baedff95eb940658cc1009da06ee5f2?tab=layers
 pub const EMPTY_IMAGE: &str =
 	"0lach/empty:latest@sha256:f84a7c75637bea6fd2f816d4ec4f5b662baedff95eb940658cc1009da06ee5f2";
+
+/// If `$DOCKER_HOST` points to a non-local daemon, returns its address
+///
+/// Bind mounts refer to paths on the daemon host, so they can't be used with such daemons.
+pub fn remote_docker_host() -> Option<String> {
+	let host = std::env::var("DOCKER_HOST").ok()?;
+	if host.is_empty() || host.starts_with("unix://") || host.starts_with("npipe://") {
+		return None;
+	}
+	Some(host)
+}
+
+/// Names of containers, which were started by baedeker and are still running
+static RUNNING: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Registers container as running until dropped
+pub struct RunningContainer(String);
+impl RunningContainer {
+	/// Generate unique container name, and register it
+	pub fn register() -> Self {
+		let name = format!("bdk-{:016x}", rand::random::<u64>());
+		RUNNING.lock().expect("not poisoned").insert(name.clone());
+		Self(name)
+	}
+	pub fn name(&self) -> &str {
+		&self.0
+	}
+}
+impl Drop for RunningContainer {
+	fn drop(&mut self) {
+		RUNNING.lock().expect("not poisoned").remove(&self.0);
+	}
+}
+
+/// Stop every container, which is still running, used on interruption
+pub fn stop_running() {
+	let running = std::mem::take(&mut *RUNNING.lock().expect("not poisoned"));
+	if running.is_empty() {
+		return;
+	}
+	warn!("stopping {} running container(s)", running.len());
+	let _ = Command::new("docker")
+		.arg("stop")
+		.args(&running)
+		.stdout(Stdio::null())
+		.stderr(Stdio::null())
+		.status();
+}
@@ -0,0 +1,60 @@
+use crate::keystore::SecretBackend;
+use crate::spec_builder::NamedSpecBackend;
+
+/// Outcome of a single probe, printed as a report line
+struct Probe {
+	name: String,
+	error: Option<String>,
+	hint: &'static str,
+}
+impl Probe {
+	fn new<E: ToString>(name: String, result: Result<(), E>, hint: &'static str) -> Self {
+		Self {
+			name,
+			error: result.err().map(|e| e.to_string()),
+			hint,
+		}
+	}
+}
+
+/// Probe configured spec and secret backends, print report, and return whether all probes passed
+pub fn run(spec: &[NamedSpecBackend], secret: &SecretBackend) -> bool {
+	let mut probes = vec![];
+	if spec.is_empty() {
+		probes.push(Probe::new(
+			"spec".to_owned(),
+			Err("no spec backend configured"),
+			"pass --spec docker",
+		));
+	}
+	for backend in spec {
+		let name = match &backend.name {
+			Some(name) => format!("spec {name}"),
+			None => "spec".to_owned(),
+		};
+		probes.push(Probe::new(
+			name,
+			backend.backend.self_test(),
+			"check that docker is installed and its daemon is reachable by the current user; \
+			 for remote daemons use --spec docker,remote=true",
+		));
+	}
+	probes.push(Probe::new(
+		"secret".to_owned(),
+		secret.self_test(),
+		"pass --secret file=<dir> pointing to the writable directory",
+	));
+
+	let mut ok = true;
+	for probe in probes {
+		match probe.error {
+			None => println!("OK   {}", probe.name),
+			Some(error) => {
+				ok = false;
+				println!("FAIL {}: {error}", probe.name);
+				println!("     hint: {}", probe.hint);
+			}
+		}
+	}
+	ok
+}
@@ -0,0 +1,66 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use jrsonnet_evaluator::runtime_error;
+
+/// Process exit codes, distinguishing failure classes
+#[derive(Clone, Copy)]
+#[repr(u8)]
+pub enum FailureClass {
+	Generic = 1,
+	/// Config/jsonnet evaluation error
+	Config = 2,
+	/// Spec builder (docker) failure
+	SpecBuild = 3,
+	/// Keystore access failure
+	Keystore = 4,
+	/// RPC call to the running node failure
+	Rpc = 5,
+	/// Output filesystem operation failure
+	Io = 6,
+}
+impl FailureClass {
+	pub fn exit_code(self) -> i32 {
+		self as i32
+	}
+}
+
+pub const EXIT_CODES_HELP: &str = "Exit codes:
+  1  generic failure
+  2  config evaluation failure
+  3  spec build failure
+  4  keystore failure
+  5  rpc failure
+  6  output i/o failure
+  130/143  interrupted by SIGINT/SIGTERM";
+
+const UNSET: u8 = 0;
+
+/// Class of the last error, which was recorded at its origin, jsonnet errors are
+/// not catchable, so the last recorded one is the one which has failed the evaluation
+static LAST: AtomicU8 = AtomicU8::new(UNSET);
+/// Class of the errors, which were not recorded at their origin (i.e plain jsonnet errors),
+/// depends on the phase of the run
+static DEFAULT: AtomicU8 = AtomicU8::new(FailureClass::Generic as u8);
+
+pub fn record(class: FailureClass) {
+	LAST.store(class as u8, Ordering::Relaxed);
+}
+
+/// Set class for the unrecorded errors of the current phase
+pub fn set_default(class: FailureClass) {
+	DEFAULT.store(class as u8, Ordering::Relaxed);
+}
+
+/// Evaluation error for the failed filesystem operation
+pub fn io_error(message: String) -> jrsonnet_evaluator::Error {
+	record(FailureClass::Io);
+	runtime_error!("{message}")
+}
+
+/// Exit code for the failed evaluation
+pub fn evaluation_exit_code() -> i32 {
+	match LAST.load(Ordering::Relaxed) {
+		UNSET => i32::from(DEFAULT.load(Ordering::Relaxed)),
+		class => i32::from(class),
+	}
+}
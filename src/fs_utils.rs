@@ -1,4 +1,9 @@
-use std::{fs::DirBuilder, io, os::unix::fs::DirBuilderExt, path::Path};
+use std::{
+	fs::{DirBuilder, OpenOptions, Permissions},
+	io::{self, Write},
+	os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt},
+	path::Path,
+};
 
 /// Recursively create a directory and all of its parent components if they
 /// are missing with given permissions.
@@ -12,3 +17,22 @@ pub fn create_dir_mode<P: AsRef<Path>>(path: P, mode: u32) -> io::Result<()> {
 		.mode(mode)
 		.create(path.as_ref())
 }
+
+/// Write a file, setting given permissions on it.
+///
+/// Unlike [`std::fs::write`], permissions are also updated if the file already exists.
+///
+/// # Errors
+///
+/// The same as from [`std::fs::write`]
+pub fn write_mode<P: AsRef<Path>>(path: P, data: &[u8], mode: u32) -> io::Result<()> {
+	let mut file = OpenOptions::new()
+		.write(true)
+		.create(true)
+		.truncate(true)
+		.mode(mode)
+		.open(path.as_ref())?;
+	file.set_permissions(Permissions::from_mode(mode))?;
+	file.write_all(data)?;
+	file.flush()
+}
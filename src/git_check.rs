@@ -0,0 +1,116 @@
+use std::{
+	io::ErrorKind,
+	path::Path,
+	process::{Command, Stdio},
+	str::FromStr,
+};
+
+use tracing::{info, warn};
+
+use crate::spec_builder::SpecCache;
+
+/// What to do after checking generated outputs against git
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum GitCheck {
+	/// Only report whether outputs have changed
+	Report,
+	/// Fail the run, if outputs differ from the committed ones
+	FailOnDirty,
+}
+impl FromStr for GitCheck {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"report" => Ok(Self::Report),
+			"fail-on-dirty" => Ok(Self::FailOnDirty),
+			_ => Err(format!(
+				"unknown git check mode {s:?}, expected report or fail-on-dirty"
+			)),
+		}
+	}
+}
+
+enum Status {
+	/// Directory is not a part of git work tree, or git is not available
+	Untracked(String),
+	Clean,
+	/// `git status --porcelain` lines for the changed files
+	Dirty(Vec<String>),
+}
+
+fn git(dir: &Path, args: &[&str]) -> std::io::Result<std::process::Output> {
+	Command::new("git")
+		.arg("-C")
+		.arg(dir)
+		.args(args)
+		.stdin(Stdio::null())
+		.output()
+}
+
+fn status(dir: &Path) -> Result<Status, String> {
+	let inside = match git(dir, &["rev-parse", "--is-inside-work-tree"]) {
+		Ok(out) => out,
+		Err(e) if e.kind() == ErrorKind::NotFound => {
+			return Ok(Status::Untracked("git is not installed".to_owned()))
+		}
+		Err(e) => return Err(format!("failed to run git: {e}")),
+	};
+	if !inside.status.success() || String::from_utf8_lossy(&inside.stdout).trim() != "true" {
+		return Ok(Status::Untracked("not in a git work tree".to_owned()));
+	}
+	// Spec cache lives in the output directory, but is never committed
+	let exclude_cache = format!(":(exclude){}", SpecCache::DIR);
+	let out = git(
+		dir,
+		&[
+			"status",
+			"--porcelain",
+			"--untracked-files=all",
+			"--",
+			".",
+			&exclude_cache,
+		],
+	)
+	.map_err(|e| format!("failed to run git: {e}"))?;
+	if !out.status.success() {
+		return Err(format!(
+			"git status failed: {}",
+			String::from_utf8_lossy(&out.stderr).trim()
+		));
+	}
+	let changes = String::from_utf8_lossy(&out.stdout)
+		.lines()
+		.filter(|l| !l.is_empty())
+		.map(str::to_owned)
+		.collect::<Vec<_>>();
+	Ok(if changes.is_empty() {
+		Status::Clean
+	} else {
+		Status::Dirty(changes)
+	})
+}
+
+/// Report git status of every output directory, returns whether any of them has changes.
+///
+/// Directories outside of git work tree are skipped with a warning.
+pub fn run<'p>(dirs: impl IntoIterator<Item = &'p Path>) -> Result<bool, String> {
+	let mut dirty = false;
+	for dir in dirs {
+		match status(dir)? {
+			Status::Untracked(reason) => {
+				warn!("git check: skipping {}: {reason}", dir.display())
+			}
+			Status::Clean => info!("git check: {} is up to date", dir.display()),
+			Status::Dirty(changes) => {
+				dirty = true;
+				warn!(
+					"git check: {} has uncommitted changes:\n{}",
+					dir.display(),
+					changes.join("\n")
+				);
+			}
+		}
+	}
+	Ok(dirty)
+}
@@ -0,0 +1,298 @@
+use std::str::FromStr;
+
+use chainql_core::address::{address_seed, public_bytes_seed, SignatureSchema};
+use clap::Subcommand;
+use serde_json::json;
+use sp_core::crypto::Ss58AddressFormat;
+
+use crate::keystore::{Error, Result, SecretBackend, SecretStorage, StoredKey};
+use crate::rng::KeyRng;
+
+const SCHEMES: [(&str, SignatureSchema); 3] = [
+	("sr25519", SignatureSchema::Sr25519),
+	("ed25519", SignatureSchema::Ed25519),
+	("ecdsa", SignatureSchema::Ecdsa),
+];
+
+fn parse_scheme(s: &str) -> Result<SignatureSchema, String> {
+	SCHEMES
+		.iter()
+		.find(|(name, _)| name.eq_ignore_ascii_case(s))
+		.map(|(_, schema)| *schema)
+		.ok_or_else(|| format!("unknown scheme: {s}, expected one of sr25519, ed25519, ecdsa"))
+}
+
+/// Scheme isn't stored in the keystore, find the one matching the stored public key
+fn detect_scheme(key: &StoredKey) -> Result<Option<(&'static str, SignatureSchema)>> {
+	for (name, schema) in SCHEMES {
+		if public_bytes_seed(schema, &key.suri)?[..] == key.public[..] {
+			return Ok(Some((name, schema)));
+		}
+	}
+	Ok(None)
+}
+
+/// Key request in `<ty>=<scheme>` form, wallets are prefixed with `_`, like in `bdk.ensureKeys`
+#[derive(Clone)]
+pub struct KeySpec {
+	ty: String,
+	scheme_name: String,
+	scheme: SignatureSchema,
+}
+impl FromStr for KeySpec {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (ty, scheme_name) = s
+			.split_once('=')
+			.ok_or_else(|| format!("expected <ty>=<scheme>, got {s}"))?;
+		Ok(Self {
+			ty: ty.to_owned(),
+			scheme_name: scheme_name.to_ascii_lowercase(),
+			scheme: parse_scheme(scheme_name)?,
+		})
+	}
+}
+
+#[derive(Subcommand)]
+pub enum KeysCommand {
+	/// Generate missing node identity and keys, and print their addresses.
+	///
+	/// Already existing keys are kept as is.
+	Generate {
+		#[arg(long)]
+		node: String,
+		/// Key to generate, in `<ty>=<scheme>` form, i.e `aura=sr25519`, or `_stash=sr25519` for wallets.
+		#[arg(long = "key")]
+		keys: Vec<KeySpec>,
+		/// SS58 prefix used for the printed addresses.
+		#[arg(long, default_value_t = 42)]
+		ss58_format: u16,
+	},
+	/// Print node identity, and types/addresses of all node keys.
+	///
+	/// Secrets are never printed.
+	Inspect {
+		#[arg(long)]
+		node: String,
+		/// SS58 prefix used for the printed addresses.
+		#[arg(long, default_value_t = 42)]
+		ss58_format: u16,
+		/// Output as JSON.
+		#[arg(long)]
+		json: bool,
+	},
+	/// Copy all node identities, keys and wallets from one backend to another.
+	///
+	/// Entries already present in the destination are skipped, entries with different values
+	/// are left untouched and reported as conflicts.
+	Migrate {
+		/// Source backend, in the same format as `--secret`.
+		#[arg(long)]
+		from: SecretBackend,
+		/// Destination backend, in the same format as `--secret`.
+		#[arg(long)]
+		to: SecretBackend,
+	},
+}
+impl KeysCommand {
+	pub fn run(&self, secrets: &dyn SecretStorage, rng: &KeyRng) -> Result<()> {
+		match self {
+			KeysCommand::Generate {
+				node,
+				keys,
+				ss58_format,
+			} => generate(
+				secrets,
+				rng,
+				node,
+				keys,
+				Ss58AddressFormat::custom(*ss58_format),
+			),
+			KeysCommand::Inspect {
+				node,
+				ss58_format,
+				json,
+			} => inspect(
+				secrets,
+				node,
+				Ss58AddressFormat::custom(*ss58_format),
+				*json,
+			),
+			KeysCommand::Migrate { from, to } => migrate(from, to),
+		}
+	}
+}
+
+fn generate(
+	secrets: &dyn SecretStorage,
+	rng: &KeyRng,
+	node: &str,
+	keys: &[KeySpec],
+	format: Ss58AddressFormat,
+) -> Result<()> {
+	if secrets.get_node_id(node)?.is_none() {
+		secrets.store_node_key(node, rng.node_keypair())?;
+	}
+	let node_id = secrets.get_node_id(node)?.expect("just inserted");
+	println!("node identity: {node_id}");
+
+	for key in keys {
+		let address = if let Some(ty) = key.ty.strip_prefix('_') {
+			if secrets.get_wallet(node, ty, key.scheme, format)?.is_none() {
+				let suri = rng.mnemonic(24);
+				secrets.store_wallet(node, ty, key.scheme, &suri, format)?;
+			}
+			secrets
+				.get_wallet(node, ty, key.scheme, format)?
+				.expect("just inserted")
+		} else {
+			if secrets
+				.get_typed(node, &key.ty, key.scheme, format)?
+				.is_none()
+			{
+				let suri = rng.mnemonic(12);
+				secrets.store_typed_key(node, &key.ty, key.scheme, &suri, format)?;
+			}
+			secrets
+				.get_typed(node, &key.ty, key.scheme, format)?
+				.expect("just inserted")
+		};
+		println!("{} ({}): {address}", key.ty, key.scheme_name);
+	}
+	Ok(())
+}
+
+fn inspect(
+	secrets: &dyn SecretStorage,
+	node: &str,
+	format: Ss58AddressFormat,
+	json: bool,
+) -> Result<()> {
+	let node_id = secrets.get_node_id(node)?;
+	let mut keys = Vec::new();
+	for key in secrets.list_keys(node)? {
+		let (scheme, address) = match detect_scheme(&key)? {
+			Some((name, schema)) => (Some(name), Some(address_seed(schema, &key.suri, format)?)),
+			None => (None, None),
+		};
+		let modified = secrets.key_age(node, &key.ty)?;
+		keys.push((key.ty, hex::encode(&key.public), scheme, address, modified));
+	}
+
+	if json {
+		let keys = keys
+			.into_iter()
+			.map(|(ty, public, scheme, address, modified)| {
+				json!({
+					"ty": ty,
+					"public": format!("0x{public}"),
+					"scheme": scheme,
+					"address": address,
+					"modified": modified,
+				})
+			})
+			.collect::<Vec<_>>();
+		let out = json!({
+			"nodeIdentity": node_id,
+			"keys": keys,
+		});
+		println!(
+			"{}",
+			serde_json::to_string_pretty(&out).expect("json is serializable")
+		);
+		return Ok(());
+	}
+
+	println!(
+		"node identity: {}",
+		node_id.as_deref().unwrap_or("<missing>")
+	);
+	for (ty, public, scheme, address, modified) in keys {
+		let modified = modified.map_or_else(String::new, |m| format!(", modified at {m}"));
+		match (scheme, address) {
+			(Some(scheme), Some(address)) => println!("{ty} ({scheme}{modified}): {address}"),
+			_ => println!("{ty} (unknown scheme{modified}): 0x{public}"),
+		}
+	}
+	Ok(())
+}
+
+#[derive(Default)]
+struct MigrationSummary {
+	migrated: usize,
+	skipped: usize,
+	conflicts: Vec<String>,
+}
+impl MigrationSummary {
+	/// Account entry, which has `existing` value in the destination
+	fn entry(
+		&mut self,
+		existing: Option<String>,
+		expected: &str,
+		what: impl FnOnce() -> String,
+		store: impl FnOnce() -> Result<()>,
+	) -> Result<()> {
+		match existing {
+			None => {
+				store()?;
+				self.migrated += 1;
+			}
+			Some(existing) if existing == expected => self.skipped += 1,
+			Some(_) => self.conflicts.push(what()),
+		}
+		Ok(())
+	}
+}
+
+fn migrate(from: &dyn SecretStorage, to: &dyn SecretStorage) -> Result<()> {
+	// Only used for comparison, actual addresses are never printed
+	let format = Ss58AddressFormat::custom(42);
+	let mut summary = MigrationSummary::default();
+
+	for node in from.list_nodes()? {
+		if let Some(pair) = from.get_node_key(&node)? {
+			let id = from.get_node_id(&node)?.expect("node key exists");
+			summary.entry(
+				to.get_node_id(&node)?,
+				&id,
+				|| format!("{node} identity"),
+				|| to.store_node_key(&node, pair),
+			)?;
+		}
+		for key in from.list_keys(&node)? {
+			let Some((_, schema)) = detect_scheme(&key)? else {
+				return Err(Error::UnsupportedKeystoreEntry);
+			};
+			let address = address_seed(schema, &key.suri, format)?;
+			summary.entry(
+				to.get_typed(&node, &key.ty, schema, format)?,
+				&address,
+				|| format!("{node} key {}", key.ty),
+				|| to.store_typed_key(&node, &key.ty, schema, &key.suri, format),
+			)?;
+		}
+	}
+	for wallet in from.list_wallets()? {
+		// Wallet scheme is not stored, but matching sr25519 addresses imply matching SURIs
+		let schema = SignatureSchema::Sr25519;
+		let address = address_seed(schema, &wallet.suri, format)?;
+		summary.entry(
+			to.get_wallet(&wallet.name, &wallet.ty, schema, format)?,
+			&address,
+			|| format!("{} wallet {}", wallet.name, wallet.ty),
+			|| to.store_wallet(&wallet.name, &wallet.ty, schema, &wallet.suri, format),
+		)?;
+	}
+
+	println!(
+		"migrated: {}, skipped: {}, conflicts: {}",
+		summary.migrated,
+		summary.skipped,
+		summary.conflicts.len()
+	);
+	if !summary.conflicts.is_empty() {
+		return Err(Error::Conflict(summary.conflicts.join(", ")));
+	}
+	Ok(())
+}
@@ -1,20 +1,28 @@
 use std::{
+	collections::{BTreeMap, BTreeSet},
 	env,
-	fs::{self, Permissions, create_dir_all},
+	fs::{self, create_dir_all, Permissions},
 	io::{self, ErrorKind, Write},
 	os::unix::fs::PermissionsExt,
-	path::PathBuf,
+	path::{Path, PathBuf},
 	result,
 	str::FromStr,
+	sync::{Arc, Mutex, MutexGuard},
+	time::{SystemTime, UNIX_EPOCH},
 };
 
 use chainql_core::address::{address_seed, public_bytes_seed, SignatureSchema};
 use libp2p::identity::{ed25519, PeerId};
-use sp_core::crypto::{SecretStringError, Ss58AddressFormat};
-use tempfile::{NamedTempFile, PersistError};
-use tracing::info;
+use sp_core::crypto::{SecretStringError, SecretUri, Ss58AddressFormat};
+use tempfile::{NamedTempFile, PersistError, TempDir};
+use tracing::{info, warn};
 
+use crate::failure::{self, FailureClass};
 use crate::fs_utils::create_dir_mode;
+use crate::keystore_dry_run::DryRunSecretStorage;
+use crate::keystore_retry::{RetryOn, RetryingSecretStorage};
+use crate::options::Options;
+use crate::wallet_json;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -24,7 +32,7 @@ pub enum Error {
 	Persist(#[from] PersistError),
 	#[error("decoding libp2p identity: {0}")]
 	IdentityDecoding(#[from] libp2p::identity::DecodingError),
-	#[error("keystore ty should be 4 chars")]
+	#[error("keystore ty should be 4 chars (or any length with allow_any_ty=true)")]
 	InvalidKeystoreTy,
 	#[error("secret string: {0}")]
 	SecretString(#[from] SecretStringError),
@@ -38,11 +46,20 @@ pub enum Error {
 	DuplicateKeyByType(String),
 	#[error("invalid parameter: {0}")]
 	InvalidParameter(&'static str),
+	#[error("conflicting entries: {0}")]
+	Conflict(String),
+	#[error("env secret backend is read-only, set {0} instead")]
+	ReadOnlyEnv(String),
+	#[error("{0}: {1}")]
+	InvalidEnv(String, &'static str),
+	#[error("{0} failed after {1} attempts: {2}")]
+	RetriesExhausted(&'static str, u32, Box<Error>),
 }
-type Result<T, E = Error> = result::Result<T, E>;
+pub type Result<T, E = Error> = result::Result<T, E>;
 
 impl From<Error> for jrsonnet_evaluator::Error {
 	fn from(value: Error) -> Self {
+		failure::record(FailureClass::Keystore);
 		jrsonnet_evaluator::Error::new(jrsonnet_evaluator::RuntimeError(
 			format!("keystore: {value}").into(),
 		))
@@ -52,6 +69,9 @@ impl From<Error> for jrsonnet_evaluator::Error {
 pub trait SecretStorage {
 	fn store_node_key(&self, name: &str, keypair: ed25519::Keypair) -> Result<()>;
 	fn get_node_id(&self, name: &str) -> Result<Option<String>>;
+	fn get_node_key(&self, name: &str) -> Result<Option<ed25519::Keypair>>;
+	/// List all nodes, having either node identity or keystore stored
+	fn list_nodes(&self) -> Result<Vec<String>>;
 
 	fn store_typed_key(
 		&self,
@@ -68,6 +88,11 @@ pub trait SecretStorage {
 		schema: SignatureSchema,
 		format: Ss58AddressFormat,
 	) -> Result<Option<String>>;
+	/// List all typed keys stored for the node
+	fn list_keys(&self, node: &str) -> Result<Vec<StoredKey>>;
+	/// Last modification time of the typed key in seconds since the unix epoch,
+	/// `None` if the key is missing or the backend doesn't track it
+	fn key_age(&self, node: &str, ty: &str) -> Result<Option<u64>>;
 
 	fn store_wallet(
 		&self,
@@ -84,19 +109,147 @@ pub trait SecretStorage {
 		schema: SignatureSchema,
 		format: Ss58AddressFormat,
 	) -> Result<Option<String>>;
+	fn list_wallets(&self) -> Result<Vec<StoredWallet>>;
+
+	/// Export wallet in PolkadotJS encrypted JSON format
+	fn export_wallet_json(
+		&self,
+		name: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		password: &str,
+		format: Ss58AddressFormat,
+	) -> Result<Option<String>>;
 
 	/// If keystore is stored on disk as a directory, return the path to it
 	/// If the keystore for node is empty, should return path to the entry directory instead
 	/// (I.e /var/empty)
 	fn local_keystore_dir(&self, node: &str) -> Result<Option<String>>;
 	fn local_node_file(&self, node: &str) -> Result<Option<String>>;
+
+	/// Return storage, which stores keystore of the specified node in the specified directory
+	fn with_keystore_dir(&self, node: &str, dir: &Path) -> Result<Box<dyn SecretStorage>>;
+
+	/// Stored secrets are not persisted, and values derived from them are placeholders
+	fn is_dry_run(&self) -> bool {
+		false
+	}
+}
+
+/// Typed key, as stored in the keystore
+pub struct StoredKey {
+	pub ty: String,
+	pub public: Vec<u8>,
+	pub suri: String,
+}
+
+/// Wallet, as stored in the keystore
+pub struct StoredWallet {
+	pub name: String,
+	pub ty: String,
+	pub suri: String,
+}
+
+/// Keystore entries may be replaced concurrently (see cleanup in `store_typed_key`),
+/// entry which was listed but removed before read is treated as missing
+fn skip_removed<T>(res: io::Result<T>) -> Result<Option<T>> {
+	match res {
+		Ok(v) => Ok(Some(v)),
+		Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+		Err(e) => Err(e.into()),
+	}
+}
+
+/// Atomically replace `dest` with the secret file, temporary file is created in `dir`,
+/// which should be on the same filesystem as `dest`
+fn write_secret(dir: &Path, dest: &Path, data: &[u8]) -> Result<()> {
+	let mut file = NamedTempFile::new_in(dir)?;
+	file.write_all(data)?;
+	file.as_file_mut()
+		.set_permissions(Permissions::from_mode(0o600))?;
+	// Make sure the content hits the disk before the rename, otherwise crash might leave
+	// the empty secret file in place of the old one
+	file.as_file().sync_all()?;
+	file.persist(dest)?;
+	Ok(())
+}
+
+/// SURI, split into the secret part (phrase/seed with derivation path) and the password,
+/// which follows `///`
+pub struct Suri {
+	pub secret: String,
+	pub password: Option<String>,
+}
+impl Suri {
+	pub fn parse(suri: &str) -> Result<Self> {
+		// Validates the whole SURI, derivation junctions can't contain `///`, so the first one
+		// always starts the password
+		SecretUri::from_str(suri)?;
+		Ok(match suri.split_once("///") {
+			Some((secret, password)) => Self {
+				secret: secret.to_owned(),
+				password: Some(password.to_owned()),
+			},
+			None => Self {
+				secret: suri.to_owned(),
+				password: None,
+			},
+		})
+	}
+	/// Full SURI, as accepted by `address_seed`
+	pub fn join(&self) -> String {
+		match &self.password {
+			Some(password) => format!("{}///{password}", self.secret),
+			None => self.secret.clone(),
+		}
+	}
+}
+
+/// Marker of the non-four-byte key type, followed by the type length byte.
+/// Can't be confused with the stock key type, as 0xff never appears in utf-8.
+const ANY_TY_MARKER: &str = "ff";
+
+/// Hex-encoded key type, as it prefixes the keystore file name
+fn ty_prefix(ty: &str, allow_any_ty: bool) -> Result<String> {
+	if ty.chars().count() == 4 {
+		return Ok(hex::encode(ty));
+	}
+	if !allow_any_ty || ty.is_empty() || ty.len() > u8::MAX as usize {
+		return Err(Error::InvalidKeystoreTy);
+	}
+	Ok(format!(
+		"{ANY_TY_MARKER}{:02x}{}",
+		ty.len(),
+		hex::encode(ty)
+	))
+}
+
+/// Parse keystore file name, returning key type and public key
+fn parse_key_file_name(name: &str) -> Option<(String, Vec<u8>)> {
+	let (ty_hex, public_hex) = if let Some(rest) = name.strip_prefix(ANY_TY_MARKER) {
+		let len = u8::from_str_radix(rest.get(..2)?, 16).ok()? as usize;
+		(rest.get(2..2 + len * 2)?, rest.get(2 + len * 2..)?)
+	} else {
+		(name.get(..8)?, name.get(8..)?)
+	};
+	let ty = String::from_utf8(hex::decode(ty_hex).ok()?).ok()?;
+	let public = hex::decode(public_hex).ok()?;
+	Some((ty, public))
 }
 
 #[derive(Clone)]
 pub struct FileNodeKeys {
 	pub root: PathBuf,
+	/// Allow key types of any length, not only four-byte substrate `KeyTypeId`s.
+	/// Such keys are stored in the format, which is not readable by the stock substrate keystore.
+	pub allow_any_ty: bool,
+	/// Nodes, for which keystore is stored outside of the root
+	pub keystore_overrides: BTreeMap<String, PathBuf>,
 }
 impl FileNodeKeys {
+	fn ty_prefix(&self, ty: &str) -> Result<String> {
+		ty_prefix(ty, self.allow_any_ty)
+	}
 	fn node_keys_dir(&self) -> Result<Option<PathBuf>> {
 		let mut path = self.root.to_path_buf();
 		path.push("node");
@@ -112,8 +265,13 @@ impl FileNodeKeys {
 		Ok(path)
 	}
 	fn keystore_dir(&self, node: &str) -> Result<Option<PathBuf>> {
-		let mut path = self.root.to_path_buf();
-		path.push(format!("keystore/{node}"));
+		let path = if let Some(path) = self.keystore_overrides.get(node) {
+			path.clone()
+		} else {
+			let mut path = self.root.to_path_buf();
+			path.push(format!("keystore/{node}"));
+			path
+		};
 		if !path.is_dir() {
 			return Ok(None);
 		}
@@ -121,6 +279,11 @@ impl FileNodeKeys {
 	}
 
 	fn keystore_dir_create(&self, node: &str) -> Result<PathBuf> {
+		if let Some(path) = self.keystore_overrides.get(node) {
+			create_dir_mode(path, 0o744)?;
+			return Ok(path.clone());
+		}
+
 		let keystore_path = self.root.join("keystore");
 		create_dir_all(&keystore_path)?;
 
@@ -137,6 +300,31 @@ impl FileNodeKeys {
 		}
 		Ok(Some(path))
 	}
+	fn wallet_suri(&self, node: &str, ty: &str) -> Result<Option<String>> {
+		let Some(dir) = self.wallet_dir()? else {
+			return Ok(None);
+		};
+		let mut secret = dir;
+		secret.push(format!("{node}-{ty}"));
+
+		let Some(data) = skip_removed(fs::read_to_string(&secret))? else {
+			return Ok(None);
+		};
+		Ok(Some(self.wallet_with_password(node, ty, &data)?))
+	}
+	/// SURI passwords are stored apart from the wallet files, see [`Self::store_wallet`]
+	fn wallet_password_file(&self, name: &str, ty: &str) -> PathBuf {
+		let mut path = self.root.to_path_buf();
+		path.push("wallet-password");
+		path.push(format!("{name}-{ty}"));
+		path
+	}
+	/// Full SURI from the wallet file contents and the stored password
+	fn wallet_with_password(&self, name: &str, ty: &str, data: &str) -> Result<String> {
+		let secret: String = serde_json::from_str(data)?;
+		let password = skip_removed(fs::read_to_string(self.wallet_password_file(name, ty)))?;
+		Ok(Suri { secret, password }.join())
+	}
 	fn wallet_dir_create(&self) -> Result<PathBuf> {
 		let mut path = self.root.to_path_buf();
 		path.push("wallet");
@@ -150,17 +338,22 @@ impl SecretStorage for FileNodeKeys {
 		let mut path = self.node_keys_dir_create()?;
 		path.push(name);
 
-		let mut temp = NamedTempFile::new_in(&self.root)?;
-		temp.write_all(keypair.secret().as_ref())?;
-		temp.as_file_mut()
-			.set_permissions(Permissions::from_mode(0o600))?;
-		temp.persist(path)?;
+		write_secret(&self.root, &path, keypair.secret().as_ref())?;
 
 		Ok(())
 	}
 
 	fn get_node_id(&self, name: &str) -> Result<Option<String>> {
 		// FIXME: file store should protect secret file, and store public key in other location
+		let Some(pair) = self.get_node_key(name)? else {
+			return Ok(None);
+		};
+
+		let base58 = PeerId::from_public_key(&pair.public().into()).to_base58();
+		Ok(Some(base58))
+	}
+
+	fn get_node_key(&self, name: &str) -> Result<Option<ed25519::Keypair>> {
 		let Some(mut path) = self.node_keys_dir()? else {
 			return Ok(None);
 		};
@@ -173,10 +366,32 @@ impl SecretStorage for FileNodeKeys {
 		};
 
 		let secret = ed25519::SecretKey::try_from_bytes(data)?;
-		let pair = ed25519::Keypair::from(secret);
+		Ok(Some(ed25519::Keypair::from(secret)))
+	}
 
-		let base58 = PeerId::from_public_key(&pair.public().into()).to_base58();
-		Ok(Some(base58))
+	fn list_nodes(&self) -> Result<Vec<String>> {
+		let mut out = BTreeSet::new();
+		let keystore_dir = self.root.join("keystore");
+		for dir in [
+			self.node_keys_dir()?,
+			keystore_dir.is_dir().then_some(keystore_dir),
+		]
+		.into_iter()
+		.flatten()
+		{
+			for entry in dir.read_dir()? {
+				let entry = entry?;
+				let file_name = entry.file_name();
+				let file_name_str = file_name.to_str().ok_or(Error::UnsupportedFileName)?;
+				out.insert(file_name_str.to_owned());
+			}
+		}
+		for node in self.keystore_overrides.keys() {
+			if self.keystore_dir(node)?.is_some() {
+				out.insert(node.clone());
+			}
+		}
+		Ok(out.into_iter().collect())
 	}
 
 	fn store_typed_key(
@@ -187,12 +402,9 @@ impl SecretStorage for FileNodeKeys {
 		suri: &str,
 		_format: Ss58AddressFormat,
 	) -> Result<()> {
-		if ty.chars().count() != 4 {
-			return Err(Error::InvalidKeystoreTy);
-		}
+		let ty_hex = self.ty_prefix(ty)?;
 		let dir = self.keystore_dir_create(node)?;
 
-		let ty_hex = hex::encode(ty);
 		let public_hex = hex::encode(public_bytes_seed(schema, suri)?);
 
 		let name = format!("{ty_hex}{public_hex}");
@@ -200,26 +412,27 @@ impl SecretStorage for FileNodeKeys {
 		let mut secret = dir.to_owned();
 		secret.push(&name);
 
-		{
-			let mut file = NamedTempFile::new_in(&dir)?;
-			file.write_all(serde_json::to_string(&suri).unwrap().as_bytes())?;
-			file.as_file_mut()
-				.set_permissions(Permissions::from_mode(0o600))?;
-			file.persist(&secret)?;
-		}
-
+		// Old keys are removed before the new one appears, so concurrent `get_typed` sees either
+		// the old key, no key, or the new key, but never both of them at once
 		for entry in dir.read_dir()? {
 			let entry = entry?;
 			let file_name = entry.file_name();
 			let file_name_str = file_name.to_str().ok_or(Error::UnsupportedFileName)?;
-			if !entry.metadata()?.is_file() {
+			let Some(metadata) = skip_removed(entry.metadata())? else {
+				continue;
+			};
+			if !metadata.is_file() {
 				return Err(Error::UnsupportedKeystoreEntry);
 			}
 			if file_name_str.starts_with(&ty_hex) && file_name_str != name {
-				fs::remove_file(&entry.path())?;
+				skip_removed(fs::remove_file(entry.path()))?;
 			}
 		}
 
+		// Unlike wallets, keystore files are read by the node, which only knows the keystore-wide
+		// password, so SURI password (if any) is kept in place
+		write_secret(&dir, &secret, serde_json::to_string(&suri)?.as_bytes())?;
+
 		Ok(())
 	}
 
@@ -230,24 +443,26 @@ impl SecretStorage for FileNodeKeys {
 		schema: SignatureSchema,
 		format: Ss58AddressFormat,
 	) -> Result<Option<String>> {
-		if ty.chars().count() != 4 {
-			return Err(Error::InvalidKeystoreTy);
-		}
+		let ty_hex = self.ty_prefix(ty)?;
 		let Some(dir) = self.keystore_dir(node)? else {
 			return Ok(None);
 		};
-		let ty_hex = hex::encode(ty);
 
 		let mut found = None;
 		for entry in dir.read_dir()? {
 			let entry = entry?;
 			let file_name = entry.file_name();
 			let file_name_str = file_name.to_str().ok_or(Error::UnsupportedFileName)?;
-			if !entry.metadata()?.is_file() {
+			let Some(metadata) = skip_removed(entry.metadata())? else {
+				continue;
+			};
+			if !metadata.is_file() {
 				return Err(Error::UnsupportedKeystoreEntry);
 			}
 			if file_name_str.starts_with(&ty_hex) {
-				let data = fs::read_to_string(&entry.path())?;
+				let Some(data) = skip_removed(fs::read_to_string(entry.path()))? else {
+					continue;
+				};
 				let suri: String = serde_json::from_str(&data)?;
 				if found.is_some() {
 					return Err(Error::DuplicateKeyByType(ty.to_string()));
@@ -263,6 +478,61 @@ impl SecretStorage for FileNodeKeys {
 		Ok(Some(public))
 	}
 
+	fn list_keys(&self, node: &str) -> Result<Vec<StoredKey>> {
+		let Some(dir) = self.keystore_dir(node)? else {
+			return Ok(vec![]);
+		};
+		let mut out = Vec::new();
+		for entry in dir.read_dir()? {
+			let entry = entry?;
+			let file_name = entry.file_name();
+			let file_name_str = file_name.to_str().ok_or(Error::UnsupportedFileName)?;
+			let Some(metadata) = skip_removed(entry.metadata())? else {
+				continue;
+			};
+			if !metadata.is_file() {
+				return Err(Error::UnsupportedKeystoreEntry);
+			}
+			let (ty, public) =
+				parse_key_file_name(file_name_str).ok_or(Error::UnsupportedKeystoreEntry)?;
+			let Some(data) = skip_removed(fs::read_to_string(entry.path()))? else {
+				continue;
+			};
+			out.push(StoredKey {
+				ty,
+				public,
+				suri: serde_json::from_str(&data)?,
+			});
+		}
+		out.sort_by(|a, b| a.ty.cmp(&b.ty));
+		Ok(out)
+	}
+
+	fn key_age(&self, node: &str, ty: &str) -> Result<Option<u64>> {
+		let ty_hex = self.ty_prefix(ty)?;
+		let Some(dir) = self.keystore_dir(node)? else {
+			return Ok(None);
+		};
+		for entry in dir.read_dir()? {
+			let entry = entry?;
+			let file_name = entry.file_name();
+			let file_name_str = file_name.to_str().ok_or(Error::UnsupportedFileName)?;
+			if !file_name_str.starts_with(&ty_hex) {
+				continue;
+			}
+			let Some(metadata) = skip_removed(entry.metadata())? else {
+				continue;
+			};
+			let modified = metadata.modified()?;
+			return Ok(Some(
+				modified
+					.duration_since(UNIX_EPOCH)
+					.map_or(0, |d| d.as_secs()),
+			));
+		}
+		Ok(None)
+	}
+
 	fn store_wallet(
 		&self,
 		name: &str,
@@ -272,14 +542,30 @@ impl SecretStorage for FileNodeKeys {
 		_format: Ss58AddressFormat,
 	) -> Result<()> {
 		let dir = self.wallet_dir_create()?;
+		// Wallet file name doesn't include the scheme, so regenerating/changing the scheme
+		// replaces the previous wallet, keeping exactly one file per (name, ty)
 		let mut secret = dir.clone();
 		secret.push(format!("{name}-{ty}"));
 
-		{
-			let file = NamedTempFile::new_in(&dir)?;
-			fs::write(&file, serde_json::to_string(&suri)?)?;
-			file.persist(secret)?;
+		// Password is kept in the separate file, so that the leaked wallet file alone is not
+		// enough to derive the key
+		let suri = Suri::parse(suri)?;
+		let password_file = self.wallet_password_file(name, ty);
+		match &suri.password {
+			Some(password) => {
+				let password_dir = password_file.parent().expect("file in directory");
+				create_dir_mode(password_dir, 0o700)?;
+				write_secret(password_dir, &password_file, password.as_bytes())?;
+			}
+			None => {
+				skip_removed(fs::remove_file(&password_file))?;
+			}
 		}
+		write_secret(
+			&dir,
+			&secret,
+			serde_json::to_string(&suri.secret)?.as_bytes(),
+		)?;
 
 		Ok(())
 	}
@@ -291,23 +577,61 @@ impl SecretStorage for FileNodeKeys {
 		schema: SignatureSchema,
 		format: Ss58AddressFormat,
 	) -> Result<Option<String>> {
-		let Some(dir) = self.wallet_dir()? else {
+		let Some(suri) = self.wallet_suri(node, ty)? else {
 			return Ok(None);
 		};
-		let mut secret = dir;
-		secret.push(format!("{node}-{ty}"));
-
-		let data = match fs::read_to_string(&secret) {
-			Ok(v) => v,
-			Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
-			Err(e) => return Err(e.into()),
-		};
-		let suri: String = serde_json::from_str(&data)?;
 
 		let public = address_seed(schema, &suri, format)?;
 		Ok(Some(public))
 	}
 
+	fn list_wallets(&self) -> Result<Vec<StoredWallet>> {
+		let Some(dir) = self.wallet_dir()? else {
+			return Ok(vec![]);
+		};
+		let mut out = Vec::new();
+		for entry in dir.read_dir()? {
+			let entry = entry?;
+			let file_name = entry.file_name();
+			let file_name_str = file_name.to_str().ok_or(Error::UnsupportedFileName)?;
+			let Some(metadata) = skip_removed(entry.metadata())? else {
+				continue;
+			};
+			if !metadata.is_file() {
+				return Err(Error::UnsupportedKeystoreEntry);
+			}
+			let (name, ty) = file_name_str
+				.rsplit_once('-')
+				.ok_or(Error::UnsupportedKeystoreEntry)?;
+			let Some(data) = skip_removed(fs::read_to_string(entry.path()))? else {
+				continue;
+			};
+			out.push(StoredWallet {
+				name: name.to_owned(),
+				ty: ty.to_owned(),
+				suri: self.wallet_with_password(name, ty, &data)?,
+			});
+		}
+		out.sort_by(|a, b| (&a.name, &a.ty).cmp(&(&b.name, &b.ty)));
+		Ok(out)
+	}
+
+	fn export_wallet_json(
+		&self,
+		name: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		password: &str,
+		format: Ss58AddressFormat,
+	) -> Result<Option<String>> {
+		let Some(suri) = self.wallet_suri(name, ty)? else {
+			return Ok(None);
+		};
+		let wallet =
+			wallet_json::encode_wallet(&format!("{name}-{ty}"), schema, &suri, password, format)?;
+		Ok(Some(serde_json::to_string_pretty(&wallet)?))
+	}
+
 	fn local_keystore_dir(&self, node: &str) -> Result<Option<String>> {
 		if let Some(dir) = self.keystore_dir(node)? {
 			let dir = dir.to_str().ok_or(Error::UnsupportedFileName)?;
@@ -326,38 +650,710 @@ impl SecretStorage for FileNodeKeys {
 			file.to_str().ok_or(Error::UnsupportedFileName)?.to_string(),
 		))
 	}
+
+	fn with_keystore_dir(&self, node: &str, dir: &Path) -> Result<Box<dyn SecretStorage>> {
+		let mut out = self.clone();
+		out.keystore_overrides
+			.insert(node.to_owned(), dir.to_path_buf());
+		Ok(Box::new(out))
+	}
+}
+
+/// Key, stored by the [`MemoryNodeKeys`]
+struct MemoryKey {
+	schema: SignatureSchema,
+	suri: String,
+	format: Ss58AddressFormat,
+	/// Seconds since the unix epoch
+	stored_at: u64,
+}
+
+#[derive(Default)]
+struct MemoryState {
+	node_keys: BTreeMap<String, Vec<u8>>,
+	/// node => ty => key
+	keys: BTreeMap<String, BTreeMap<String, MemoryKey>>,
+	/// (name, ty) => key
+	wallets: BTreeMap<(String, String), MemoryKey>,
+	/// Created on the first request of local paths, removed on exit
+	materialized: Option<TempDir>,
+	/// Node keys, which are not yet written to `materialized`
+	pending_node_keys: BTreeSet<String>,
+	/// node => tys of keys, which are not yet written to `materialized`
+	pending_keys: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// Storage, which keeps secrets in the process memory, for tests.
+///
+/// Local keystore paths are served by writing secrets to the temporary directory on demand,
+/// this directory is removed when baedeker exits.
+#[derive(Clone, Default)]
+pub struct MemoryNodeKeys {
+	state: Arc<Mutex<MemoryState>>,
+	pub allow_any_ty: bool,
+	/// Nodes, for which keystore is stored in the directory, see [`FileNodeKeys::keystore_overrides`]
+	pub keystore_overrides: BTreeMap<String, PathBuf>,
+}
+impl MemoryNodeKeys {
+	fn state(&self) -> MutexGuard<'_, MemoryState> {
+		self.state.lock().expect("not poisoned")
+	}
+	/// File storage for nodes with overridden keystore dir, root is never used for them
+	fn overridden(&self, node: &str) -> Option<FileNodeKeys> {
+		self.keystore_overrides
+			.contains_key(node)
+			.then(|| FileNodeKeys {
+				root: PathBuf::new(),
+				allow_any_ty: self.allow_any_ty,
+				keystore_overrides: self.keystore_overrides.clone(),
+			})
+	}
+	/// File storage over the temporary directory, secrets are written to it by
+	/// [`Self::materialize_node_key`] and [`Self::materialize_keystore`]
+	fn materialized(&self, state: &mut MemoryState) -> Result<FileNodeKeys> {
+		if state.materialized.is_none() {
+			let dir = tempfile::Builder::new()
+				.prefix("bdk-memory-secrets")
+				.tempdir()?;
+			warn!(
+				"memory secrets are written to {}, paths to it are only valid until baedeker exits",
+				dir.path().display()
+			);
+			state.materialized = Some(dir);
+		}
+		Ok(FileNodeKeys {
+			root: state
+				.materialized
+				.as_ref()
+				.expect("set above")
+				.path()
+				.to_owned(),
+			allow_any_ty: self.allow_any_ty,
+			keystore_overrides: BTreeMap::new(),
+		})
+	}
+	/// Write node key to the temporary directory, if it wasn't written since the last store
+	fn materialize_node_key(&self, node: &str) -> Result<FileNodeKeys> {
+		let mut state = self.state();
+		let files = self.materialized(&mut state)?;
+		if state.pending_node_keys.remove(node) {
+			let secret = state.node_keys.get(node).expect("pending key is stored");
+			let secret = ed25519::SecretKey::try_from_bytes(secret.clone())?;
+			files.store_node_key(node, secret.into())?;
+		}
+		Ok(files)
+	}
+	/// Write node typed keys to the temporary directory, skipping the ones written since the last store
+	fn materialize_keystore(&self, node: &str) -> Result<FileNodeKeys> {
+		let mut state = self.state();
+		let files = self.materialized(&mut state)?;
+		for ty in state.pending_keys.remove(node).unwrap_or_default() {
+			let key = &state.keys[node][&ty];
+			files.store_typed_key(node, &ty, key.schema, &key.suri, key.format)?;
+		}
+		Ok(files)
+	}
+}
+
+fn unix_now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_or(0, |d| d.as_secs())
+}
+
+impl SecretStorage for MemoryNodeKeys {
+	fn store_node_key(&self, name: &str, keypair: ed25519::Keypair) -> Result<()> {
+		let mut state = self.state();
+		state
+			.node_keys
+			.insert(name.to_owned(), keypair.secret().as_ref().to_vec());
+		state.pending_node_keys.insert(name.to_owned());
+		Ok(())
+	}
+
+	fn get_node_id(&self, name: &str) -> Result<Option<String>> {
+		let Some(pair) = self.get_node_key(name)? else {
+			return Ok(None);
+		};
+		Ok(Some(
+			PeerId::from_public_key(&pair.public().into()).to_base58(),
+		))
+	}
+
+	fn get_node_key(&self, name: &str) -> Result<Option<ed25519::Keypair>> {
+		let Some(secret) = self.state().node_keys.get(name).cloned() else {
+			return Ok(None);
+		};
+		Ok(Some(ed25519::SecretKey::try_from_bytes(secret)?.into()))
+	}
+
+	fn list_nodes(&self) -> Result<Vec<String>> {
+		let state = self.state();
+		let mut out = state
+			.node_keys
+			.keys()
+			.chain(state.keys.keys())
+			.cloned()
+			.collect::<BTreeSet<_>>();
+		for node in self.keystore_overrides.keys() {
+			if let Some(files) = self.overridden(node) {
+				if files.keystore_dir(node)?.is_some() {
+					out.insert(node.clone());
+				}
+			}
+		}
+		Ok(out.into_iter().collect())
+	}
+
+	fn store_typed_key(
+		&self,
+		node: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		suri: &str,
+		format: Ss58AddressFormat,
+	) -> Result<()> {
+		if let Some(files) = self.overridden(node) {
+			return files.store_typed_key(node, ty, schema, suri, format);
+		}
+		ty_prefix(ty, self.allow_any_ty)?;
+		// Validate suri, as the file storage does
+		public_bytes_seed(schema, suri)?;
+		let mut state = self.state();
+		state.keys.entry(node.to_owned()).or_default().insert(
+			ty.to_owned(),
+			MemoryKey {
+				schema,
+				suri: suri.to_owned(),
+				format,
+				stored_at: unix_now(),
+			},
+		);
+		state
+			.pending_keys
+			.entry(node.to_owned())
+			.or_default()
+			.insert(ty.to_owned());
+		Ok(())
+	}
+
+	fn get_typed(
+		&self,
+		node: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		format: Ss58AddressFormat,
+	) -> Result<Option<String>> {
+		if let Some(files) = self.overridden(node) {
+			return files.get_typed(node, ty, schema, format);
+		}
+		ty_prefix(ty, self.allow_any_ty)?;
+		let Some(suri) = self
+			.state()
+			.keys
+			.get(node)
+			.and_then(|keys| keys.get(ty))
+			.map(|key| key.suri.clone())
+		else {
+			return Ok(None);
+		};
+		Ok(Some(address_seed(schema, &suri, format)?))
+	}
+
+	fn list_keys(&self, node: &str) -> Result<Vec<StoredKey>> {
+		if let Some(files) = self.overridden(node) {
+			return files.list_keys(node);
+		}
+		let state = self.state();
+		let Some(keys) = state.keys.get(node) else {
+			return Ok(vec![]);
+		};
+		keys.iter()
+			.map(|(ty, key)| {
+				Ok(StoredKey {
+					ty: ty.clone(),
+					public: public_bytes_seed(key.schema, &key.suri)?,
+					suri: key.suri.clone(),
+				})
+			})
+			.collect()
+	}
+
+	fn key_age(&self, node: &str, ty: &str) -> Result<Option<u64>> {
+		if let Some(files) = self.overridden(node) {
+			return files.key_age(node, ty);
+		}
+		Ok(self
+			.state()
+			.keys
+			.get(node)
+			.and_then(|keys| keys.get(ty))
+			.map(|key| key.stored_at))
+	}
+
+	fn store_wallet(
+		&self,
+		name: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		suri: &str,
+		format: Ss58AddressFormat,
+	) -> Result<()> {
+		public_bytes_seed(schema, suri)?;
+		self.state().wallets.insert(
+			(name.to_owned(), ty.to_owned()),
+			MemoryKey {
+				schema,
+				suri: suri.to_owned(),
+				format,
+				stored_at: unix_now(),
+			},
+		);
+		Ok(())
+	}
+
+	fn get_wallet(
+		&self,
+		node: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		format: Ss58AddressFormat,
+	) -> Result<Option<String>> {
+		let Some(suri) = self
+			.state()
+			.wallets
+			.get(&(node.to_owned(), ty.to_owned()))
+			.map(|key| key.suri.clone())
+		else {
+			return Ok(None);
+		};
+		Ok(Some(address_seed(schema, &suri, format)?))
+	}
+
+	fn list_wallets(&self) -> Result<Vec<StoredWallet>> {
+		Ok(self
+			.state()
+			.wallets
+			.iter()
+			.map(|((name, ty), key)| StoredWallet {
+				name: name.clone(),
+				ty: ty.clone(),
+				suri: key.suri.clone(),
+			})
+			.collect())
+	}
+
+	fn export_wallet_json(
+		&self,
+		name: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		password: &str,
+		format: Ss58AddressFormat,
+	) -> Result<Option<String>> {
+		let Some(suri) = self
+			.state()
+			.wallets
+			.get(&(name.to_owned(), ty.to_owned()))
+			.map(|key| key.suri.clone())
+		else {
+			return Ok(None);
+		};
+		let wallet =
+			wallet_json::encode_wallet(&format!("{name}-{ty}"), schema, &suri, password, format)?;
+		Ok(Some(serde_json::to_string_pretty(&wallet)?))
+	}
+
+	fn local_keystore_dir(&self, node: &str) -> Result<Option<String>> {
+		if let Some(files) = self.overridden(node) {
+			return files.local_keystore_dir(node);
+		}
+		self.materialize_keystore(node)?.local_keystore_dir(node)
+	}
+
+	fn local_node_file(&self, node: &str) -> Result<Option<String>> {
+		self.materialize_node_key(node)?.local_node_file(node)
+	}
+
+	fn with_keystore_dir(&self, node: &str, dir: &Path) -> Result<Box<dyn SecretStorage>> {
+		let mut out = self.clone();
+		out.keystore_overrides
+			.insert(node.to_owned(), dir.to_path_buf());
+		Ok(Box::new(out))
+	}
+}
+
+/// Read-only storage, which takes secrets from the environment variables:
+/// - `BDK_NODE_KEY_<node>`: hex-encoded ed25519 secret of the node identity
+/// - `BDK_KEY_<node>_<ty>`: SURI of the typed node key
+/// - `BDK_WALLET_<name>_<ty>`: SURI of the wallet
+///
+/// Characters of node/wallet names and key types, which are not allowed in variable names
+/// (anything other than ascii letters, digits and `_`) are replaced with `_`.
+///
+/// Read secrets are remembered, so that local keystore paths are served the same way as by
+/// the [`MemoryNodeKeys`].
+#[derive(Clone, Default)]
+pub struct EnvNodeKeys {
+	read: MemoryNodeKeys,
+}
+impl EnvNodeKeys {
+	pub fn new(allow_any_ty: bool) -> Self {
+		Self {
+			read: MemoryNodeKeys {
+				allow_any_ty,
+				..Default::default()
+			},
+		}
+	}
+	fn var_name(kind: &str, parts: &[&str]) -> String {
+		let mut out = format!("BDK_{kind}");
+		for part in parts {
+			out.push('_');
+			out.extend(part.chars().map(|c| {
+				if c.is_ascii_alphanumeric() || c == '_' {
+					c
+				} else {
+					'_'
+				}
+			}));
+		}
+		out
+	}
+	fn var(name: &str) -> Result<Option<String>> {
+		match env::var(name) {
+			Ok(v) => Ok(Some(v)),
+			Err(env::VarError::NotPresent) => Ok(None),
+			Err(env::VarError::NotUnicode(_)) => {
+				Err(Error::InvalidEnv(name.to_owned(), "value is not utf-8"))
+			}
+		}
+	}
+}
+
+impl SecretStorage for EnvNodeKeys {
+	fn store_node_key(&self, name: &str, _keypair: ed25519::Keypair) -> Result<()> {
+		Err(Error::ReadOnlyEnv(Self::var_name("NODE_KEY", &[name])))
+	}
+
+	fn get_node_id(&self, name: &str) -> Result<Option<String>> {
+		let Some(pair) = self.get_node_key(name)? else {
+			return Ok(None);
+		};
+		Ok(Some(
+			PeerId::from_public_key(&pair.public().into()).to_base58(),
+		))
+	}
+
+	fn get_node_key(&self, name: &str) -> Result<Option<ed25519::Keypair>> {
+		let var = Self::var_name("NODE_KEY", &[name]);
+		let Some(value) = Self::var(&var)? else {
+			return Ok(None);
+		};
+		let secret = hex::decode(value.trim().trim_start_matches("0x"))
+			.map_err(|_| Error::InvalidEnv(var.clone(), "expected hex-encoded secret"))?;
+		let pair: ed25519::Keypair = ed25519::SecretKey::try_from_bytes(secret)
+			.map_err(|_| Error::InvalidEnv(var, "expected 32 byte ed25519 secret"))?
+			.into();
+		self.read.store_node_key(name, pair.clone())?;
+		Ok(Some(pair))
+	}
+
+	/// Only nodes with identity set, and nodes which keys were already read are listed, as
+	/// node name can't be told from key type in `BDK_KEY_<node>_<ty>`
+	fn list_nodes(&self) -> Result<Vec<String>> {
+		let prefix = Self::var_name("NODE_KEY", &[""]);
+		let mut out = env::vars_os()
+			.filter_map(|(k, _)| Some(k.to_str()?.strip_prefix(&prefix)?.to_owned()))
+			.collect::<BTreeSet<_>>();
+		out.extend(self.read.list_nodes()?);
+		Ok(out.into_iter().collect())
+	}
+
+	fn store_typed_key(
+		&self,
+		node: &str,
+		ty: &str,
+		_schema: SignatureSchema,
+		_suri: &str,
+		_format: Ss58AddressFormat,
+	) -> Result<()> {
+		Err(Error::ReadOnlyEnv(Self::var_name("KEY", &[node, ty])))
+	}
+
+	fn get_typed(
+		&self,
+		node: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		format: Ss58AddressFormat,
+	) -> Result<Option<String>> {
+		ty_prefix(ty, self.read.allow_any_ty)?;
+		let Some(suri) = Self::var(&Self::var_name("KEY", &[node, ty]))? else {
+			return Ok(None);
+		};
+		self.read.store_typed_key(node, ty, schema, &suri, format)?;
+		Ok(Some(address_seed(schema, &suri, format)?))
+	}
+
+	/// Only keys, which were already read
+	fn list_keys(&self, node: &str) -> Result<Vec<StoredKey>> {
+		self.read.list_keys(node)
+	}
+
+	fn key_age(&self, _node: &str, _ty: &str) -> Result<Option<u64>> {
+		Ok(None)
+	}
+
+	fn store_wallet(
+		&self,
+		name: &str,
+		ty: &str,
+		_schema: SignatureSchema,
+		_suri: &str,
+		_format: Ss58AddressFormat,
+	) -> Result<()> {
+		Err(Error::ReadOnlyEnv(Self::var_name("WALLET", &[name, ty])))
+	}
+
+	fn get_wallet(
+		&self,
+		node: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		format: Ss58AddressFormat,
+	) -> Result<Option<String>> {
+		let Some(suri) = Self::var(&Self::var_name("WALLET", &[node, ty]))? else {
+			return Ok(None);
+		};
+		self.read.store_wallet(node, ty, schema, &suri, format)?;
+		Ok(Some(address_seed(schema, &suri, format)?))
+	}
+
+	/// Only wallets, which were already read
+	fn list_wallets(&self) -> Result<Vec<StoredWallet>> {
+		self.read.list_wallets()
+	}
+
+	fn export_wallet_json(
+		&self,
+		name: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		password: &str,
+		format: Ss58AddressFormat,
+	) -> Result<Option<String>> {
+		if self.get_wallet(name, ty, schema, format)?.is_none() {
+			return Ok(None);
+		}
+		self.read
+			.export_wallet_json(name, ty, schema, password, format)
+	}
+
+	fn local_keystore_dir(&self, node: &str) -> Result<Option<String>> {
+		self.read.local_keystore_dir(node)
+	}
+
+	fn local_node_file(&self, node: &str) -> Result<Option<String>> {
+		self.get_node_key(node)?;
+		self.read.local_node_file(node)
+	}
+
+	fn with_keystore_dir(&self, node: &str, dir: &Path) -> Result<Box<dyn SecretStorage>> {
+		let mut out = self.clone();
+		out.read
+			.keystore_overrides
+			.insert(node.to_owned(), dir.to_path_buf());
+		Ok(Box::new(out))
+	}
 }
 
 #[derive(Default, Clone)]
 pub enum SecretBackend {
 	File(FileNodeKeys),
+	Memory(MemoryNodeKeys),
+	Env(EnvNodeKeys),
+	/// Any of the above, with writes only kept in memory
+	DryRun(Box<DryRunSecretStorage>),
+	/// Any of the above, with transient failures retried
+	Retrying(Box<RetryingSecretStorage<SecretBackend>>),
 	#[default]
 	Unset,
 }
+impl SecretBackend {
+	/// Forms accepted by [`FromStr`], printed by `--list-secret-backends`
+	pub const FORMS: &'static [&'static str] = &[
+		"file=<dir>[,allow_any_ty=true]",
+		"memory[,allow_any_ty=true]",
+		"env[,allow_any_ty=true]",
+		"any of the above, followed by [,dry_run=true][,retries=<n>[,retry_on=io|all]]",
+	];
+
+	/// Store and read back a throwaway key, without touching the stored secrets
+	pub fn self_test(&self) -> Result<()> {
+		const NODE: &str = "bdk-doctor";
+		let check = |storage: &dyn SecretStorage| -> Result<()> {
+			let format = Ss58AddressFormat::custom(42);
+			let suri = "//BdkDoctor";
+			storage.store_typed_key(NODE, "bdkd", SignatureSchema::Sr25519, suri, format)?;
+			let stored = storage.get_typed(NODE, "bdkd", SignatureSchema::Sr25519, format)?;
+			if stored != Some(address_seed(SignatureSchema::Sr25519, suri, format)?) {
+				return Err(Error::Conflict(
+					"key read back differs from the stored one".to_owned(),
+				));
+			}
+			Ok(())
+		};
+		match self {
+			SecretBackend::File(f) => {
+				// Keystore is redirected to the temporary directory inside of the root, so that
+				// the root writability is checked, but no stray keystore is left behind
+				fs::create_dir_all(&f.root)?;
+				let dir = tempfile::Builder::new()
+					.prefix(".bdk-doctor")
+					.tempdir_in(&f.root)?;
+				check(&*f.with_keystore_dir(NODE, dir.path())?)
+			}
+			SecretBackend::Memory(m) => check(m),
+			// Read-only, there is nothing to store, and any variable may be missing
+			SecretBackend::Env(_) => Ok(()),
+			SecretBackend::DryRun(d) => d.inner().self_test(),
+			SecretBackend::Retrying(r) => r.inner().self_test(),
+			SecretBackend::Unset => Err(Error::InvalidParameter("secret backend is not set")),
+		}
+	}
+
+	fn allow_any_ty(&self) -> bool {
+		match self {
+			SecretBackend::File(f) => f.allow_any_ty,
+			SecretBackend::Memory(m) => m.allow_any_ty,
+			SecretBackend::Env(e) => e.read.allow_any_ty,
+			SecretBackend::DryRun(d) => d.inner().allow_any_ty(),
+			SecretBackend::Retrying(r) => r.inner().allow_any_ty(),
+			SecretBackend::Unset => false,
+		}
+	}
+
+	/// Wrap backend, so that stores are only kept in memory, already stored secrets are still
+	/// readable. Unset backend is replaced with the empty memory one.
+	pub fn into_dry_run(self) -> Self {
+		let inner = match self {
+			SecretBackend::DryRun(_) => return self,
+			SecretBackend::Unset => SecretBackend::Memory(MemoryNodeKeys {
+				allow_any_ty: true,
+				..Default::default()
+			}),
+			other => other,
+		};
+		let allow_any_ty = inner.allow_any_ty();
+		SecretBackend::DryRun(Box::new(DryRunSecretStorage::new(inner, allow_any_ty)))
+	}
+
+	/// Wrapper backends log stores themselves (or delegate it to the wrapped backend)
+	fn is_wrapper(&self) -> bool {
+		matches!(self, SecretBackend::DryRun(_) | SecretBackend::Retrying(_))
+	}
+
+	/// Same as [`SecretStorage::with_keystore_dir`], but keeps the concrete backend type
+	pub(crate) fn with_keystore_override(&self, node: &str, dir: &Path) -> Result<SecretBackend> {
+		Ok(match self {
+			SecretBackend::File(f) => {
+				let mut f = f.clone();
+				f.keystore_overrides
+					.insert(node.to_owned(), dir.to_path_buf());
+				SecretBackend::File(f)
+			}
+			SecretBackend::Memory(m) => {
+				let mut m = m.clone();
+				m.keystore_overrides
+					.insert(node.to_owned(), dir.to_path_buf());
+				SecretBackend::Memory(m)
+			}
+			SecretBackend::Env(e) => {
+				let mut e = e.clone();
+				e.read
+					.keystore_overrides
+					.insert(node.to_owned(), dir.to_path_buf());
+				SecretBackend::Env(e)
+			}
+			SecretBackend::DryRun(d) => {
+				SecretBackend::DryRun(Box::new(d.with_keystore_override(node, dir)?))
+			}
+			SecretBackend::Retrying(r) => SecretBackend::Retrying(Box::new(
+				r.try_map(|inner| inner.with_keystore_override(node, dir))?,
+			)),
+			SecretBackend::Unset => {
+				return Err(Error::InvalidParameter("secret backend is not set"))
+			}
+		})
+	}
+}
 impl FromStr for SecretBackend {
-	type Err = &'static str;
+	type Err = String;
 
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		if let Some(file) = s.strip_prefix("file=") {
-			Ok(Self::File(FileNodeKeys {
+		let mut opts = Options::parse(s)?;
+		let backend = if let Some(file) = opts.value.strip_prefix("file=") {
+			Self::File(FileNodeKeys {
 				root: {
 					let mut cwd = env::current_dir().map_err(|_| "failed to get CWD")?;
 					cwd.push(file);
 					cwd
 				},
-			}))
+				allow_any_ty: opts.take_parsed("allow_any_ty")?.unwrap_or(false),
+				keystore_overrides: BTreeMap::new(),
+			})
+		} else if opts.value == "memory" {
+			Self::Memory(MemoryNodeKeys {
+				allow_any_ty: opts.take_parsed("allow_any_ty")?.unwrap_or(false),
+				..Default::default()
+			})
+		} else if opts.value == "env" {
+			Self::Env(EnvNodeKeys::new(
+				opts.take_parsed("allow_any_ty")?.unwrap_or(false),
+			))
 		} else {
-			Ok(SecretBackend::Unset)
-		}
+			return Err(format!(
+				"unknown secret backend: {:?}, available:\n  {}",
+				opts.value,
+				Self::FORMS.join("\n  ")
+			));
+		};
+		let backend = if opts.take_parsed("dry_run")?.unwrap_or(false) {
+			backend.into_dry_run()
+		} else {
+			backend
+		};
+		let retry_on = opts.take_parsed::<RetryOn>("retry_on")?;
+		let backend = match opts.take_parsed::<u32>("retries")? {
+			Some(retries) if retries > 0 => {
+				let retrying = RetryingSecretStorage::new(backend, retries);
+				Self::Retrying(Box::new(match retry_on {
+					Some(retry_on) => retrying.with_classifier(retry_on.classifier()),
+					None => retrying,
+				}))
+			}
+			_ if retry_on.is_some() => return Err("retry_on requires retries=<n>".to_owned()),
+			_ => backend,
+		};
+		opts.finish()?;
+		Ok(backend)
 	}
 }
 
 impl SecretStorage for SecretBackend {
 	fn store_node_key(&self, name: &str, keypair: ed25519::Keypair) -> Result<()> {
-		let base58 = PeerId::from_public_key(&keypair.public().into()).to_base58();
-		info!("🛂 new node identity {name} => {base58}");
+		// Wrapped backend logs on its own
+		if !self.is_wrapper() {
+			let base58 = PeerId::from_public_key(&keypair.public().into()).to_base58();
+			info!("🛂 new node identity {name} => {base58}");
+		}
 		match self {
 			SecretBackend::File(f) => f.store_node_key(name, keypair),
+			SecretBackend::Memory(m) => m.store_node_key(name, keypair),
+			SecretBackend::Env(e) => e.store_node_key(name, keypair),
+			SecretBackend::DryRun(d) => d.store_node_key(name, keypair),
+			SecretBackend::Retrying(r) => r.store_node_key(name, keypair),
 			SecretBackend::Unset => Err(Error::InvalidParameter("secret backend is not set")),
 		}
 	}
@@ -365,6 +1361,32 @@ impl SecretStorage for SecretBackend {
 	fn get_node_id(&self, name: &str) -> Result<Option<String>> {
 		match self {
 			SecretBackend::File(f) => f.get_node_id(name),
+			SecretBackend::Memory(m) => m.get_node_id(name),
+			SecretBackend::Env(e) => e.get_node_id(name),
+			SecretBackend::DryRun(d) => d.get_node_id(name),
+			SecretBackend::Retrying(r) => r.get_node_id(name),
+			SecretBackend::Unset => Err(Error::InvalidParameter("secret backend is not set")),
+		}
+	}
+
+	fn get_node_key(&self, name: &str) -> Result<Option<ed25519::Keypair>> {
+		match self {
+			SecretBackend::File(f) => f.get_node_key(name),
+			SecretBackend::Memory(m) => m.get_node_key(name),
+			SecretBackend::Env(e) => e.get_node_key(name),
+			SecretBackend::DryRun(d) => d.get_node_key(name),
+			SecretBackend::Retrying(r) => r.get_node_key(name),
+			SecretBackend::Unset => Err(Error::InvalidParameter("secret backend is not set")),
+		}
+	}
+
+	fn list_nodes(&self) -> Result<Vec<String>> {
+		match self {
+			SecretBackend::File(f) => f.list_nodes(),
+			SecretBackend::Memory(m) => m.list_nodes(),
+			SecretBackend::Env(e) => e.list_nodes(),
+			SecretBackend::DryRun(d) => d.list_nodes(),
+			SecretBackend::Retrying(r) => r.list_nodes(),
 			SecretBackend::Unset => Err(Error::InvalidParameter("secret backend is not set")),
 		}
 	}
@@ -377,11 +1399,17 @@ impl SecretStorage for SecretBackend {
 		suri: &str,
 		format: Ss58AddressFormat,
 	) -> Result<()> {
-		info!("🔑 new node key {node} ({ty}) => {}", {
-			address_seed(schema, suri, format)?
-		});
+		if !self.is_wrapper() {
+			info!("🔑 new node key {node} ({ty}) => {}", {
+				address_seed(schema, suri, format)?
+			});
+		}
 		match self {
 			SecretBackend::File(f) => f.store_typed_key(node, ty, schema, suri, format),
+			SecretBackend::Memory(m) => m.store_typed_key(node, ty, schema, suri, format),
+			SecretBackend::Env(e) => e.store_typed_key(node, ty, schema, suri, format),
+			SecretBackend::DryRun(d) => d.store_typed_key(node, ty, schema, suri, format),
+			SecretBackend::Retrying(r) => r.store_typed_key(node, ty, schema, suri, format),
 			SecretBackend::Unset => Err(Error::InvalidParameter("secret backend is not set")),
 		}
 	}
@@ -395,6 +1423,32 @@ impl SecretStorage for SecretBackend {
 	) -> Result<Option<String>> {
 		match self {
 			SecretBackend::File(f) => f.get_typed(node, ty, schema, format),
+			SecretBackend::Memory(m) => m.get_typed(node, ty, schema, format),
+			SecretBackend::Env(e) => e.get_typed(node, ty, schema, format),
+			SecretBackend::DryRun(d) => d.get_typed(node, ty, schema, format),
+			SecretBackend::Retrying(r) => r.get_typed(node, ty, schema, format),
+			SecretBackend::Unset => Err(Error::InvalidParameter("secret backend is not set")),
+		}
+	}
+
+	fn list_keys(&self, node: &str) -> Result<Vec<StoredKey>> {
+		match self {
+			SecretBackend::File(f) => f.list_keys(node),
+			SecretBackend::Memory(m) => m.list_keys(node),
+			SecretBackend::Env(e) => e.list_keys(node),
+			SecretBackend::DryRun(d) => d.list_keys(node),
+			SecretBackend::Retrying(r) => r.list_keys(node),
+			SecretBackend::Unset => Err(Error::InvalidParameter("secret backend is not set")),
+		}
+	}
+
+	fn key_age(&self, node: &str, ty: &str) -> Result<Option<u64>> {
+		match self {
+			SecretBackend::File(f) => f.key_age(node, ty),
+			SecretBackend::Memory(m) => m.key_age(node, ty),
+			SecretBackend::Env(e) => e.key_age(node, ty),
+			SecretBackend::DryRun(d) => d.key_age(node, ty),
+			SecretBackend::Retrying(r) => r.key_age(node, ty),
 			SecretBackend::Unset => Err(Error::InvalidParameter("secret backend is not set")),
 		}
 	}
@@ -408,11 +1462,17 @@ impl SecretStorage for SecretBackend {
 		format: Ss58AddressFormat,
 	) -> Result<()> {
 		// todo!()
-		info!(" new node wallet {name} ({ty}) => {}", {
-			address_seed(schema, suri, format)?
-		});
+		if !self.is_wrapper() {
+			info!(" new node wallet {name} ({ty}) => {}", {
+				address_seed(schema, suri, format)?
+			});
+		}
 		match self {
 			SecretBackend::File(f) => f.store_wallet(name, ty, schema, suri, format),
+			SecretBackend::Memory(m) => m.store_wallet(name, ty, schema, suri, format),
+			SecretBackend::Env(e) => e.store_wallet(name, ty, schema, suri, format),
+			SecretBackend::DryRun(d) => d.store_wallet(name, ty, schema, suri, format),
+			SecretBackend::Retrying(r) => r.store_wallet(name, ty, schema, suri, format),
 			SecretBackend::Unset => Err(Error::InvalidParameter("secret backend is not set")),
 		}
 	}
@@ -426,6 +1486,39 @@ impl SecretStorage for SecretBackend {
 	) -> Result<Option<String>> {
 		match self {
 			SecretBackend::File(f) => f.get_wallet(node, ty, schema, format),
+			SecretBackend::Memory(m) => m.get_wallet(node, ty, schema, format),
+			SecretBackend::Env(e) => e.get_wallet(node, ty, schema, format),
+			SecretBackend::DryRun(d) => d.get_wallet(node, ty, schema, format),
+			SecretBackend::Retrying(r) => r.get_wallet(node, ty, schema, format),
+			SecretBackend::Unset => Err(Error::InvalidParameter("secret backend is not set")),
+		}
+	}
+
+	fn list_wallets(&self) -> Result<Vec<StoredWallet>> {
+		match self {
+			SecretBackend::File(f) => f.list_wallets(),
+			SecretBackend::Memory(m) => m.list_wallets(),
+			SecretBackend::Env(e) => e.list_wallets(),
+			SecretBackend::DryRun(d) => d.list_wallets(),
+			SecretBackend::Retrying(r) => r.list_wallets(),
+			SecretBackend::Unset => Err(Error::InvalidParameter("secret backend is not set")),
+		}
+	}
+
+	fn export_wallet_json(
+		&self,
+		name: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		password: &str,
+		format: Ss58AddressFormat,
+	) -> Result<Option<String>> {
+		match self {
+			SecretBackend::File(f) => f.export_wallet_json(name, ty, schema, password, format),
+			SecretBackend::Memory(m) => m.export_wallet_json(name, ty, schema, password, format),
+			SecretBackend::Env(e) => e.export_wallet_json(name, ty, schema, password, format),
+			SecretBackend::DryRun(d) => d.export_wallet_json(name, ty, schema, password, format),
+			SecretBackend::Retrying(r) => r.export_wallet_json(name, ty, schema, password, format),
 			SecretBackend::Unset => Err(Error::InvalidParameter("secret backend is not set")),
 		}
 	}
@@ -433,6 +1526,10 @@ impl SecretStorage for SecretBackend {
 	fn local_keystore_dir(&self, node: &str) -> Result<Option<String>> {
 		match self {
 			SecretBackend::File(f) => f.local_keystore_dir(node),
+			SecretBackend::Memory(m) => m.local_keystore_dir(node),
+			SecretBackend::Env(e) => e.local_keystore_dir(node),
+			SecretBackend::DryRun(d) => d.local_keystore_dir(node),
+			SecretBackend::Retrying(r) => r.local_keystore_dir(node),
 			SecretBackend::Unset => Err(Error::InvalidParameter("secret backend is not set")),
 		}
 	}
@@ -440,7 +1537,227 @@ impl SecretStorage for SecretBackend {
 	fn local_node_file(&self, node: &str) -> Result<Option<String>> {
 		match self {
 			SecretBackend::File(f) => f.local_node_file(node),
+			SecretBackend::Memory(m) => m.local_node_file(node),
+			SecretBackend::Env(e) => e.local_node_file(node),
+			SecretBackend::DryRun(d) => d.local_node_file(node),
+			SecretBackend::Retrying(r) => r.local_node_file(node),
 			SecretBackend::Unset => Err(Error::InvalidParameter("secret backend is not set")),
 		}
 	}
+
+	fn with_keystore_dir(&self, node: &str, dir: &Path) -> Result<Box<dyn SecretStorage>> {
+		// Keep the wrapper, so logging and retries are preserved
+		Ok(Box::new(self.with_keystore_override(node, dir)?))
+	}
+
+	fn is_dry_run(&self) -> bool {
+		match self {
+			SecretBackend::DryRun(_) => true,
+			SecretBackend::Retrying(r) => r.is_dry_run(),
+			_ => false,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{collections::BTreeMap, fs, os::unix::fs::PermissionsExt, path::Path, thread};
+
+	use chainql_core::address::{address_seed, public_bytes_seed, SignatureSchema};
+	use sp_core::crypto::Ss58AddressFormat;
+	use tempfile::TempDir;
+
+	use super::{FileNodeKeys, MemoryNodeKeys, SecretBackend, SecretStorage};
+	use crate::options::sample_forms;
+
+	fn file_keys(dir: &TempDir) -> FileNodeKeys {
+		FileNodeKeys {
+			root: dir.path().to_owned(),
+			allow_any_ty: false,
+			keystore_overrides: BTreeMap::new(),
+		}
+	}
+
+	#[test]
+	fn read_interleaved_with_store() {
+		let dir = TempDir::new().unwrap();
+		let keys = file_keys(&dir);
+		let schema = SignatureSchema::Sr25519;
+		let format = Ss58AddressFormat::custom(42);
+		let suris = ["//Alice", "//Bob"];
+		let addresses = suris.map(|suri| address_seed(schema, suri, format).unwrap());
+		keys.store_typed_key("alice", "aura", schema, suris[0], format)
+			.unwrap();
+
+		thread::scope(|s| {
+			s.spawn(|| {
+				for i in 0..200 {
+					keys.store_typed_key("alice", "aura", schema, suris[i % 2], format)
+						.unwrap();
+				}
+			});
+			for _ in 0..200 {
+				let found = keys.get_typed("alice", "aura", schema, format).unwrap();
+				if let Some(found) = found {
+					assert!(addresses.contains(&found), "unexpected key {found}");
+				}
+			}
+		});
+
+		assert_eq!(
+			keys.get_typed("alice", "aura", schema, format).unwrap(),
+			Some(addresses[1].clone())
+		);
+		assert_eq!(keys.list_keys("alice").unwrap().len(), 1);
+	}
+
+	#[test]
+	fn wallet_regeneration() {
+		let dir = TempDir::new().unwrap();
+		let keys = file_keys(&dir);
+		let format = Ss58AddressFormat::custom(42);
+		keys.store_wallet(
+			"alice",
+			"stash",
+			SignatureSchema::Sr25519,
+			"//Alice",
+			format,
+		)
+		.unwrap();
+		keys.store_wallet("alice", "stash", SignatureSchema::Ed25519, "//Bob", format)
+			.unwrap();
+
+		assert_eq!(
+			keys.get_wallet("alice", "stash", SignatureSchema::Ed25519, format)
+				.unwrap(),
+			Some(address_seed(SignatureSchema::Ed25519, "//Bob", format).unwrap())
+		);
+		let wallets = keys.list_wallets().unwrap();
+		assert_eq!(wallets.len(), 1);
+		assert_eq!(wallets[0].name, "alice");
+		assert_eq!(wallets[0].ty, "stash");
+		assert_eq!(wallets[0].suri, "//Bob");
+	}
+
+	#[test]
+	fn wallet_file_permissions() {
+		let dir = TempDir::new().unwrap();
+		let keys = file_keys(&dir);
+		let format = Ss58AddressFormat::custom(42);
+		keys.store_wallet(
+			"alice",
+			"stash",
+			SignatureSchema::Sr25519,
+			"//Alice",
+			format,
+		)
+		.unwrap();
+
+		let metadata = fs::metadata(dir.path().join("wallet/alice-stash")).unwrap();
+		assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+	}
+
+	#[test]
+	fn listed_forms_parse() {
+		let (wrappers, forms) = SecretBackend::FORMS.split_last().unwrap();
+		let wrappers = wrappers
+			.strip_prefix("any of the above, followed by ")
+			.unwrap();
+		for form in forms {
+			for base in sample_forms(form) {
+				base.parse::<SecretBackend>()
+					.unwrap_or_else(|e| panic!("{base}: {e}"));
+				for wrapper in sample_forms(wrappers) {
+					let full = format!("{base}{wrapper}");
+					full.parse::<SecretBackend>()
+						.unwrap_or_else(|e| panic!("{full}: {e}"));
+				}
+			}
+		}
+	}
+
+	const SCHEMAS: [SignatureSchema; 3] = [
+		SignatureSchema::Sr25519,
+		SignatureSchema::Ed25519,
+		SignatureSchema::Ecdsa,
+	];
+
+	#[test]
+	fn typed_key_with_password() {
+		let format = Ss58AddressFormat::custom(42);
+		for schema in SCHEMAS {
+			let dir = TempDir::new().unwrap();
+			let keys = file_keys(&dir);
+			let suri = "//Alice///secret";
+			keys.store_typed_key("alice", "aura", schema, suri, format)
+				.unwrap();
+
+			let expected = address_seed(schema, suri, format).unwrap();
+			assert_ne!(
+				expected,
+				address_seed(schema, "//Alice", format).unwrap(),
+				"password should affect the derived key"
+			);
+			assert_eq!(
+				keys.get_typed("alice", "aura", schema, format).unwrap(),
+				Some(expected)
+			);
+		}
+	}
+
+	#[test]
+	fn wallet_with_password() {
+		let format = Ss58AddressFormat::custom(42);
+		for schema in SCHEMAS {
+			let dir = TempDir::new().unwrap();
+			let keys = file_keys(&dir);
+			let suri = "//Alice//stash///secret";
+			keys.store_wallet("alice", "stash", schema, suri, format)
+				.unwrap();
+
+			assert_eq!(
+				keys.get_wallet("alice", "stash", schema, format).unwrap(),
+				Some(address_seed(schema, suri, format).unwrap())
+			);
+			let wallet = fs::read_to_string(dir.path().join("wallet/alice-stash")).unwrap();
+			assert!(!wallet.contains("secret"), "password is stored apart");
+			let wallets = keys.list_wallets().unwrap();
+			assert_eq!(wallets.len(), 1);
+			assert_eq!(wallets[0].suri, suri);
+
+			// Regenerated without password, stale password should not be applied
+			keys.store_wallet("alice", "stash", schema, "//Alice//stash", format)
+				.unwrap();
+			assert_eq!(
+				keys.get_wallet("alice", "stash", schema, format).unwrap(),
+				Some(address_seed(schema, "//Alice//stash", format).unwrap())
+			);
+		}
+	}
+
+	#[test]
+	fn memory_materializes_requested_node() {
+		let keys = MemoryNodeKeys::default();
+		let schema = SignatureSchema::Sr25519;
+		let format = Ss58AddressFormat::custom(42);
+		keys.store_typed_key("alice", "aura", schema, "//Alice", format)
+			.unwrap();
+		keys.store_typed_key("bob", "aura", schema, "//Bob", format)
+			.unwrap();
+
+		let alice = keys.local_keystore_dir("alice").unwrap().unwrap();
+		let alice = Path::new(&alice);
+		assert!(!alice.with_file_name("bob").exists());
+
+		keys.store_typed_key("alice", "aura", schema, "//Charlie", format)
+			.unwrap();
+		keys.local_keystore_dir("alice").unwrap();
+		let files = fs::read_dir(alice)
+			.unwrap()
+			.map(|entry| entry.unwrap().file_name().into_string().unwrap())
+			.collect::<Vec<_>>();
+		let public = hex::encode(public_bytes_seed(schema, "//Charlie").unwrap());
+		assert_eq!(files.len(), 1);
+		assert!(files[0].ends_with(&public), "stale key {}", files[0]);
+	}
 }
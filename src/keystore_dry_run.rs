@@ -0,0 +1,170 @@
+use std::path::Path;
+
+use chainql_core::address::SignatureSchema;
+use libp2p::identity::ed25519;
+use sp_core::crypto::Ss58AddressFormat;
+use tracing::info;
+
+use crate::keystore::{
+	MemoryNodeKeys, Result, SecretBackend, SecretStorage, StoredKey, StoredWallet,
+};
+
+/// Storage, which never writes to the wrapped backend.
+///
+/// Secrets, which would be stored, are kept in memory instead, so that the rest of the run sees
+/// them, but they are lost on exit, and every value derived from them is a placeholder.
+#[derive(Clone)]
+pub struct DryRunSecretStorage {
+	inner: SecretBackend,
+	/// Secrets which would have been stored, never persisted or materialized on disk
+	planned: MemoryNodeKeys,
+}
+impl DryRunSecretStorage {
+	pub fn new(inner: SecretBackend, allow_any_ty: bool) -> Self {
+		let mut planned = MemoryNodeKeys::default();
+		planned.allow_any_ty = allow_any_ty;
+		Self { inner, planned }
+	}
+	pub fn inner(&self) -> &SecretBackend {
+		&self.inner
+	}
+	/// Redirect keystore of the wrapped backend, planned secrets are still kept in memory
+	pub fn with_keystore_override(&self, node: &str, dir: &Path) -> Result<Self> {
+		Ok(Self {
+			inner: self.inner.with_keystore_override(node, dir)?,
+			planned: self.planned.clone(),
+		})
+	}
+}
+
+impl SecretStorage for DryRunSecretStorage {
+	fn store_node_key(&self, name: &str, keypair: ed25519::Keypair) -> Result<()> {
+		info!("🧪 dry run: would store node identity {name}");
+		self.planned.store_node_key(name, keypair)
+	}
+	fn get_node_id(&self, name: &str) -> Result<Option<String>> {
+		match self.planned.get_node_id(name)? {
+			Some(id) => Ok(Some(id)),
+			None => self.inner.get_node_id(name),
+		}
+	}
+	fn get_node_key(&self, name: &str) -> Result<Option<ed25519::Keypair>> {
+		match self.planned.get_node_key(name)? {
+			Some(key) => Ok(Some(key)),
+			None => self.inner.get_node_key(name),
+		}
+	}
+	fn list_nodes(&self) -> Result<Vec<String>> {
+		let mut out = self.inner.list_nodes()?;
+		out.extend(self.planned.list_nodes()?);
+		out.sort();
+		out.dedup();
+		Ok(out)
+	}
+
+	fn store_typed_key(
+		&self,
+		node: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		suri: &str,
+		format: Ss58AddressFormat,
+	) -> Result<()> {
+		info!("🧪 dry run: would store node key {node} ({ty})");
+		self.planned.store_typed_key(node, ty, schema, suri, format)
+	}
+	fn get_typed(
+		&self,
+		node: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		format: Ss58AddressFormat,
+	) -> Result<Option<String>> {
+		match self.planned.get_typed(node, ty, schema, format)? {
+			Some(address) => Ok(Some(address)),
+			None => self.inner.get_typed(node, ty, schema, format),
+		}
+	}
+	fn list_keys(&self, node: &str) -> Result<Vec<StoredKey>> {
+		let planned = self.planned.list_keys(node)?;
+		let mut out = self.inner.list_keys(node)?;
+		out.retain(|k| !planned.iter().any(|p| p.ty == k.ty));
+		out.extend(planned);
+		Ok(out)
+	}
+	fn key_age(&self, node: &str, ty: &str) -> Result<Option<u64>> {
+		match self.planned.key_age(node, ty)? {
+			Some(age) => Ok(Some(age)),
+			None => self.inner.key_age(node, ty),
+		}
+	}
+
+	fn store_wallet(
+		&self,
+		name: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		suri: &str,
+		format: Ss58AddressFormat,
+	) -> Result<()> {
+		info!("🧪 dry run: would store wallet {name} ({ty})");
+		self.planned.store_wallet(name, ty, schema, suri, format)
+	}
+	fn get_wallet(
+		&self,
+		node: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		format: Ss58AddressFormat,
+	) -> Result<Option<String>> {
+		match self.planned.get_wallet(node, ty, schema, format)? {
+			Some(address) => Ok(Some(address)),
+			None => self.inner.get_wallet(node, ty, schema, format),
+		}
+	}
+	fn list_wallets(&self) -> Result<Vec<StoredWallet>> {
+		let planned = self.planned.list_wallets()?;
+		let mut out = self.inner.list_wallets()?;
+		out.retain(|w| !planned.iter().any(|p| p.name == w.name && p.ty == w.ty));
+		out.extend(planned);
+		Ok(out)
+	}
+
+	fn export_wallet_json(
+		&self,
+		name: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		password: &str,
+		format: Ss58AddressFormat,
+	) -> Result<Option<String>> {
+		match self
+			.planned
+			.export_wallet_json(name, ty, schema, password, format)?
+		{
+			Some(json) => Ok(Some(json)),
+			None => self
+				.inner
+				.export_wallet_json(name, ty, schema, password, format),
+		}
+	}
+
+	/// Paths of the wrapped backend, planned secrets are never written there
+	fn local_keystore_dir(&self, node: &str) -> Result<Option<String>> {
+		self.inner.local_keystore_dir(node)
+	}
+	fn local_node_file(&self, node: &str) -> Result<Option<String>> {
+		match self.inner.local_node_file(node)? {
+			Some(file) => Ok(Some(file)),
+			None => Ok(Some("/var/empty".to_owned())),
+		}
+	}
+
+	fn with_keystore_dir(&self, node: &str, dir: &Path) -> Result<Box<dyn SecretStorage>> {
+		Ok(Box::new(self.with_keystore_override(node, dir)?))
+	}
+
+	fn is_dry_run(&self) -> bool {
+		true
+	}
+}
@@ -0,0 +1,225 @@
+use std::{io::ErrorKind, path::Path, str::FromStr, thread, time::Duration};
+
+use chainql_core::address::SignatureSchema;
+use libp2p::identity::ed25519;
+use sp_core::crypto::Ss58AddressFormat;
+use tracing::warn;
+
+use crate::keystore::{Error, Result, SecretStorage, StoredKey, StoredWallet};
+
+/// Delay before the first retry, doubled after every failed attempt
+const INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+
+/// Retries storage operations failed with transient errors
+///
+/// Local backends rarely need it, it is intended for backends talking to the remote API.
+#[derive(Clone)]
+pub struct RetryingSecretStorage<S> {
+	inner: S,
+	retries: u32,
+	backoff: Duration,
+	is_transient: fn(&Error) -> bool,
+}
+
+impl<S> RetryingSecretStorage<S> {
+	pub fn new(inner: S, retries: u32) -> Self {
+		Self {
+			inner,
+			retries,
+			backoff: INITIAL_BACKOFF,
+			is_transient: is_transient_default,
+		}
+	}
+	/// Override which errors are considered worth retrying
+	pub fn with_classifier(mut self, is_transient: fn(&Error) -> bool) -> Self {
+		self.is_transient = is_transient;
+		self
+	}
+	pub fn inner(&self) -> &S {
+		&self.inner
+	}
+	/// Replace wrapped storage, preserving retry configuration
+	pub fn try_map<T>(&self, f: impl FnOnce(&S) -> Result<T>) -> Result<RetryingSecretStorage<T>> {
+		Ok(RetryingSecretStorage {
+			inner: f(&self.inner)?,
+			retries: self.retries,
+			backoff: self.backoff,
+			is_transient: self.is_transient,
+		})
+	}
+
+	fn retry<T>(&self, op: &'static str, mut f: impl FnMut(&S) -> Result<T>) -> Result<T> {
+		let mut delay = self.backoff;
+		let mut attempt = 1;
+		loop {
+			match f(&self.inner) {
+				Ok(v) => return Ok(v),
+				Err(e) if !(self.is_transient)(&e) => return Err(e),
+				Err(e) if attempt > self.retries => {
+					return Err(Error::RetriesExhausted(op, attempt, Box::new(e)))
+				}
+				Err(e) => {
+					warn!(
+						"keystore {op} failed (attempt {attempt}/{}), retrying in {delay:?}: {e}",
+						self.retries + 1
+					);
+					thread::sleep(delay);
+					delay *= 2;
+					attempt += 1;
+				}
+			}
+		}
+	}
+}
+
+fn is_transient_io(kind: ErrorKind) -> bool {
+	matches!(
+		kind,
+		ErrorKind::Interrupted
+			| ErrorKind::TimedOut
+			| ErrorKind::WouldBlock
+			| ErrorKind::ConnectionReset
+			| ErrorKind::ConnectionAborted
+			| ErrorKind::ConnectionRefused
+			| ErrorKind::BrokenPipe
+			| ErrorKind::UnexpectedEof
+	)
+}
+
+/// Which errors are retried, set by the `retry_on` option
+#[derive(Clone, Copy)]
+pub enum RetryOn {
+	/// Transient io errors, see [`is_transient_default`]
+	Io,
+	/// Every error, for backends which report transient failures in other ways
+	All,
+}
+impl RetryOn {
+	pub fn classifier(self) -> fn(&Error) -> bool {
+		match self {
+			RetryOn::Io => is_transient_default,
+			RetryOn::All => |_| true,
+		}
+	}
+}
+impl FromStr for RetryOn {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"io" => Ok(Self::Io),
+			"all" => Ok(Self::All),
+			_ => Err(format!("unknown retry_on {s:?}, expected io or all")),
+		}
+	}
+}
+
+/// Only io errors which may disappear on their own are retried, everything else
+/// (malformed keys, conflicts, invalid parameters) would fail the same way again
+pub fn is_transient_default(e: &Error) -> bool {
+	match e {
+		Error::Io(e) => is_transient_io(e.kind()),
+		Error::Persist(e) => is_transient_io(e.error.kind()),
+		_ => false,
+	}
+}
+
+impl<S: SecretStorage> SecretStorage for RetryingSecretStorage<S> {
+	fn store_node_key(&self, name: &str, keypair: ed25519::Keypair) -> Result<()> {
+		self.retry("store_node_key", |s| {
+			s.store_node_key(name, keypair.clone())
+		})
+	}
+	fn get_node_id(&self, name: &str) -> Result<Option<String>> {
+		self.retry("get_node_id", |s| s.get_node_id(name))
+	}
+	fn get_node_key(&self, name: &str) -> Result<Option<ed25519::Keypair>> {
+		self.retry("get_node_key", |s| s.get_node_key(name))
+	}
+	fn list_nodes(&self) -> Result<Vec<String>> {
+		self.retry("list_nodes", |s| s.list_nodes())
+	}
+
+	fn store_typed_key(
+		&self,
+		node: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		suri: &str,
+		format: Ss58AddressFormat,
+	) -> Result<()> {
+		self.retry("store_typed_key", |s| {
+			s.store_typed_key(node, ty, schema, suri, format)
+		})
+	}
+	fn get_typed(
+		&self,
+		node: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		format: Ss58AddressFormat,
+	) -> Result<Option<String>> {
+		self.retry("get_typed", |s| s.get_typed(node, ty, schema, format))
+	}
+	fn list_keys(&self, node: &str) -> Result<Vec<StoredKey>> {
+		self.retry("list_keys", |s| s.list_keys(node))
+	}
+	fn key_age(&self, node: &str, ty: &str) -> Result<Option<u64>> {
+		self.retry("key_age", |s| s.key_age(node, ty))
+	}
+
+	fn store_wallet(
+		&self,
+		name: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		suri: &str,
+		format: Ss58AddressFormat,
+	) -> Result<()> {
+		self.retry("store_wallet", |s| {
+			s.store_wallet(name, ty, schema, suri, format)
+		})
+	}
+	fn get_wallet(
+		&self,
+		node: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		format: Ss58AddressFormat,
+	) -> Result<Option<String>> {
+		self.retry("get_wallet", |s| s.get_wallet(node, ty, schema, format))
+	}
+	fn list_wallets(&self) -> Result<Vec<StoredWallet>> {
+		self.retry("list_wallets", |s| s.list_wallets())
+	}
+
+	fn export_wallet_json(
+		&self,
+		name: &str,
+		ty: &str,
+		schema: SignatureSchema,
+		password: &str,
+		format: Ss58AddressFormat,
+	) -> Result<Option<String>> {
+		self.retry("export_wallet_json", |s| {
+			s.export_wallet_json(name, ty, schema, password, format)
+		})
+	}
+
+	fn local_keystore_dir(&self, node: &str) -> Result<Option<String>> {
+		self.retry("local_keystore_dir", |s| s.local_keystore_dir(node))
+	}
+	fn local_node_file(&self, node: &str) -> Result<Option<String>> {
+		self.retry("local_node_file", |s| s.local_node_file(node))
+	}
+
+	/// Not retried, and the returned storage is not wrapped, as it can't be named generically.
+	/// [`crate::keystore::SecretBackend`] rewraps it itself.
+	fn with_keystore_dir(&self, node: &str, dir: &Path) -> Result<Box<dyn SecretStorage>> {
+		self.inner.with_keystore_dir(node, dir)
+	}
+
+	fn is_dry_run(&self) -> bool {
+		self.inner.is_dry_run()
+	}
+}
@@ -1,11 +1,16 @@
 use std::any::Any;
-use std::collections::BTreeMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use bip39::{Language, Mnemonic};
-use chainql_core::address::{SignatureSchema, Ss58Format};
+use chainql_core::address::{address_seed, SignatureSchema, Ss58Format};
 use jrsonnet_evaluator::manifest::JsonFormat;
-use jrsonnet_evaluator::typed::{Either3, Typed};
+use jrsonnet_evaluator::typed::{Either2, Either3, Typed};
 use jrsonnet_evaluator::{bail, runtime_error, Either, ObjValue};
 use jrsonnet_evaluator::{
 	error::Result,
@@ -16,12 +21,26 @@ use jrsonnet_evaluator::{
 	Thunk, Val,
 };
 use jrsonnet_gcmodule::Trace;
-use libp2p::identity::ed25519;
-use tracing::{debug, warn};
+use parity_scale_codec::{Compact, Encode};
+use sc_executor::{RuntimeVersionOf, WasmExecutor};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sp_core::crypto::{AccountId32, Ss58AddressFormat, Ss58Codec};
+use sp_core::storage::{well_known_keys, ChildInfo, Storage, StorageChild};
+use sp_core::traits::{RuntimeCode, WrappedRuntimeCode};
+use sp_state_machine::BasicExternalities;
+use tracing::{debug, info, warn};
 
+use crate::fs_utils::{create_dir_mode, write_mode};
 use crate::keystore::SecretStorage;
-use crate::spec_builder::{docker_mounts, FileLocation, SpecBuilder, SpecSource};
-use crate::{apply_tla_opt, spec_builder};
+use crate::metrics::Metrics;
+use crate::rng::KeyRng;
+use crate::rpc::{self, RpcClient};
+use crate::spec_builder::{
+	docker_mounts, FileLocation, NodeOutput, SpecBuilders, SpecCache, SpecSource,
+};
+use crate::val_utils::{get_path, glob_match, glob_path};
+use crate::{apply_tla_opt, output_path};
 
 fn mix_inner(
 	state: &State,
@@ -92,6 +111,498 @@ pub fn builtin_mixer(ctx: Context, mixin: Val) -> Result<FuncVal> {
 	}))
 }
 
+thread_local! {
+	/// Files, which are being included right now, used for cycle detection
+	static INCLUDE_STACK: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+}
+struct IncludeGuard;
+impl IncludeGuard {
+	fn enter(path: PathBuf) -> Result<Self> {
+		INCLUDE_STACK.with_borrow_mut(|stack| -> Result<()> {
+			if let Some(pos) = stack.iter().position(|p| p == &path) {
+				let cycle = stack[pos..]
+					.iter()
+					.chain(std::iter::once(&path))
+					.map(|p| p.display().to_string())
+					.collect::<Vec<_>>()
+					.join(" -> ");
+				bail!("include cycle detected: {cycle}");
+			}
+			stack.push(path);
+			Ok(())
+		})?;
+		Ok(Self)
+	}
+}
+impl Drop for IncludeGuard {
+	fn drop(&mut self) {
+		INCLUDE_STACK.with_borrow_mut(|stack| stack.pop());
+	}
+}
+
+/// Import config file, and apply it as a mixin, same way as `bdk.mixer` does
+#[builtin]
+pub fn builtin_include(ctx: Context, path: String) -> Result<FuncVal> {
+	#[builtin(fields(
+		path: String,
+		state: State,
+	))]
+	pub fn builtin_include_mix(this: &builtin_include_mix, prev: Val) -> Result<Val> {
+		let canonical = std::fs::canonicalize(&this.path)
+			.map_err(|e| runtime_error!("failed to resolve include {:?}: {e}", this.path))?;
+		let _guard = IncludeGuard::enter(canonical)?;
+		let mixin = this.state.import(&this.path)?;
+
+		let final_val = Pending::new();
+		let result = mix_inner(
+			&this.state,
+			prev,
+			mixin,
+			&GcHashMap::new(),
+			final_val.clone(),
+		)
+		.with_description(|| format!("<include {}>", this.path))?;
+		final_val.fill(result.clone());
+		Ok(result)
+	}
+	Ok(FuncVal::builtin(builtin_include_mix {
+		path,
+		state: ctx.state().clone(),
+	}))
+}
+
+/// RFC 7386 JSON Merge Patch
+fn merge_patch(target: Val, patch: Val) -> Result<Val> {
+	let Val::Obj(patch) = patch else {
+		return Ok(patch);
+	};
+	let mut out = ObjValueBuilder::new();
+	let target = match target {
+		Val::Obj(target) => Some(target),
+		_ => None,
+	};
+	if let Some(target) = &target {
+		for (name, value) in target.iter(false) {
+			let value = value?;
+			match patch.get(name.clone())? {
+				None => out.field(name).value(value),
+				Some(Val::Null) => continue,
+				Some(patch_value) => out.field(name).value(merge_patch(value, patch_value)?),
+			};
+		}
+	}
+	for (name, patch_value) in patch.iter(false) {
+		if target
+			.as_ref()
+			.is_some_and(|t| t.has_field_ex(name.clone(), false))
+		{
+			continue;
+		}
+		let patch_value = patch_value?;
+		if matches!(patch_value, Val::Null) {
+			continue;
+		}
+		out.field(name).value(merge_patch(Val::Null, patch_value)?);
+	}
+	Ok(Val::Obj(out.build()))
+}
+
+#[builtin]
+pub fn builtin_merge_patch(target: Val, patch: Val) -> Result<Val> {
+	merge_patch(target, patch)
+}
+
+/// Parse RFC 6901 JSON Pointer into unescaped reference tokens
+fn parse_pointer(pointer: &str) -> Result<Vec<String>> {
+	if pointer.is_empty() {
+		return Ok(vec![]);
+	}
+	let Some(rest) = pointer.strip_prefix('/') else {
+		bail!("json pointer should start with `/`: {pointer:?}");
+	};
+	Ok(rest
+		.split('/')
+		.map(|t| t.replace("~1", "/").replace("~0", "~"))
+		.collect())
+}
+
+fn parse_array_index(pointer: &str, token: &str) -> Result<usize> {
+	if token.is_empty()
+		|| !token.bytes().all(|b| b.is_ascii_digit())
+		|| token.len() > 1 && token.starts_with('0')
+	{
+		bail!("{pointer}: invalid array index {token:?}");
+	}
+	token
+		.parse()
+		.map_err(|_| runtime_error!("{pointer}: invalid array index {token:?}"))
+}
+
+enum PatchOp {
+	Add(Val),
+	Replace(Val),
+	Remove,
+}
+
+fn apply_patch(target: Val, tokens: &[String], op: &PatchOp, pointer: &str) -> Result<Val> {
+	let Some((token, rest)) = tokens.split_first() else {
+		return match op {
+			PatchOp::Add(v) | PatchOp::Replace(v) => Ok(v.clone()),
+			PatchOp::Remove => bail!("{pointer}: can't remove the whole document"),
+		};
+	};
+	match target {
+		Val::Obj(obj) => {
+			let new = match (obj.get(token.as_str().into())?, op) {
+				(None, PatchOp::Add(v)) if rest.is_empty() => Some(v.clone()),
+				(None, _) => bail!("{pointer}: field {token:?} doesn't exist"),
+				(Some(existing), _) if !rest.is_empty() => {
+					Some(apply_patch(existing, rest, op, pointer)?)
+				}
+				(Some(_), PatchOp::Add(v) | PatchOp::Replace(v)) => Some(v.clone()),
+				(Some(_), PatchOp::Remove) => None,
+			};
+			let mut out = ObjValueBuilder::new();
+			let mut replaced = false;
+			for (name, value) in obj.iter(false) {
+				if name.as_str() == token {
+					replaced = true;
+					if let Some(new) = &new {
+						out.field(name).value(new.clone());
+					}
+				} else {
+					out.field(name).value(value?);
+				}
+			}
+			if let (false, Some(new)) = (replaced, new) {
+				out.field(token.as_str()).value(new);
+			}
+			Ok(Val::Obj(out.build()))
+		}
+		Val::Arr(arr) => {
+			let mut items = arr.iter().collect::<Result<Vec<_>>>()?;
+			if rest.is_empty() {
+				match op {
+					PatchOp::Add(v) if token == "-" => items.push(v.clone()),
+					PatchOp::Add(v) => {
+						let index = parse_array_index(pointer, token)?;
+						if index > items.len() {
+							bail!("{pointer}: index {index} is out of bounds for insertion");
+						}
+						items.insert(index, v.clone());
+					}
+					PatchOp::Replace(v) => {
+						let index = parse_array_index(pointer, token)?;
+						let Some(item) = items.get_mut(index) else {
+							bail!("{pointer}: index {index} doesn't exist");
+						};
+						*item = v.clone();
+					}
+					PatchOp::Remove => {
+						let index = parse_array_index(pointer, token)?;
+						if index >= items.len() {
+							bail!("{pointer}: index {index} doesn't exist");
+						}
+						items.remove(index);
+					}
+				}
+			} else {
+				let index = parse_array_index(pointer, token)?;
+				let Some(item) = items.get_mut(index) else {
+					bail!("{pointer}: index {index} doesn't exist");
+				};
+				*item = apply_patch(item.clone(), rest, op, pointer)?;
+			}
+			Vec::<Val>::into_untyped(items)
+		}
+		other => bail!(
+			"{pointer}: can't reference {token:?} in {}",
+			other.value_type()
+		),
+	}
+}
+
+#[derive(Typed)]
+pub struct GenesisPatch {
+	/// `add`, `replace` (default) or `remove`
+	op: Option<String>,
+	/// RFC 6901 JSON Pointer, i.e `/genesis/runtimeGenesis/patch/sudo/key`
+	path: String,
+	value: Option<Val>,
+}
+
+/// Apply JSON Pointer edits to the spec, in order
+#[builtin]
+pub fn builtin_patch_genesis(spec: Val, patches: Vec<GenesisPatch>) -> Result<Val> {
+	let mut spec = spec;
+	for patch in patches {
+		let op = match (patch.op.as_deref().unwrap_or("replace"), patch.value) {
+			("add", Some(v)) => PatchOp::Add(v),
+			("replace", Some(v)) => PatchOp::Replace(v),
+			("remove", None) => PatchOp::Remove,
+			("add" | "replace", None) => bail!("{}: value is required", patch.path),
+			("remove", Some(_)) => bail!("{}: remove doesn't accept value", patch.path),
+			(other, _) => bail!(
+				"{}: unknown op {other:?}, expected add, replace or remove",
+				patch.path
+			),
+		};
+		let tokens = parse_pointer(&patch.path)?;
+		spec = apply_patch(spec, &tokens, &op, &patch.path)?;
+	}
+	Ok(spec)
+}
+
+/// Escape reference token for use in RFC 6901 JSON Pointer
+fn escape_pointer_token(token: &str) -> String {
+	token.replace('~', "~0").replace('/', "~1")
+}
+
+#[derive(Default)]
+struct SpecDiff {
+	added: serde_json::Map<String, serde_json::Value>,
+	removed: serde_json::Map<String, serde_json::Value>,
+	changed: serde_json::Map<String, serde_json::Value>,
+}
+
+fn diff_json(
+	pointer: &str,
+	a: &serde_json::Value,
+	b: &serde_json::Value,
+	ignore: &BTreeSet<String>,
+	out: &mut SpecDiff,
+) {
+	use serde_json::Value;
+	if ignore.contains(pointer) {
+		return;
+	}
+	match (a, b) {
+		// Key order is irrelevant, i.e raw storage may be emitted in any order
+		(Value::Object(a), Value::Object(b)) => {
+			for (key, a_value) in a {
+				let pointer = format!("{pointer}/{}", escape_pointer_token(key));
+				match b.get(key) {
+					Some(b_value) => diff_json(&pointer, a_value, b_value, ignore, out),
+					None if !ignore.contains(&pointer) => {
+						out.removed.insert(pointer, a_value.clone());
+					}
+					None => {}
+				}
+			}
+			for (key, b_value) in b {
+				if a.contains_key(key) {
+					continue;
+				}
+				let pointer = format!("{pointer}/{}", escape_pointer_token(key));
+				if !ignore.contains(&pointer) {
+					out.added.insert(pointer, b_value.clone());
+				}
+			}
+		}
+		(Value::Array(a), Value::Array(b)) => {
+			for i in 0..a.len().max(b.len()) {
+				let pointer = format!("{pointer}/{i}");
+				match (a.get(i), b.get(i)) {
+					(Some(a), Some(b)) => diff_json(&pointer, a, b, ignore, out),
+					(Some(a), None) if !ignore.contains(&pointer) => {
+						out.removed.insert(pointer, a.clone());
+					}
+					(None, Some(b)) if !ignore.contains(&pointer) => {
+						out.added.insert(pointer, b.clone());
+					}
+					_ => {}
+				}
+			}
+		}
+		(a, b) if a == b => {}
+		(a, b) => {
+			out.changed
+				.insert(pointer.to_owned(), json!({ "from": a, "to": b }));
+		}
+	}
+}
+
+/// Structural diff of two (usually raw) chain specs, as `{ added, removed, changed }` objects,
+/// keyed by JSON Pointer of the difference.
+///
+/// Values are `{ from, to }` for changed paths, object field order is not considered a change.
+/// Paths (and everything under them) listed in `ignore` are skipped.
+#[builtin]
+pub fn builtin_chain_spec_diff(a: Val, b: Val, ignore: Option<Vec<String>>) -> Result<Val> {
+	let to_json = |v: Val, name: &str| -> Result<serde_json::Value> {
+		let json = v.manifest(JsonFormat::cli(0, true)).description(name)?;
+		serde_json::from_str(&json).map_err(|e| runtime_error!("{name}: json: {e}"))
+	};
+	let a = to_json(a, "a")?;
+	let b = to_json(b, "b")?;
+	let ignore = ignore.into_iter().flatten().collect::<BTreeSet<_>>();
+	for pointer in &ignore {
+		parse_pointer(pointer)?;
+	}
+
+	let mut diff = SpecDiff::default();
+	diff_json("", &a, &b, &ignore, &mut diff);
+	let diff = json!({
+		"added": diff.added,
+		"removed": diff.removed,
+		"changed": diff.changed,
+	});
+	serde_json::from_value(diff).map_err(|e| runtime_error!("json: {e}"))
+}
+
+/// JSON with sorted keys and without whitespace
+fn write_canonical_json(value: &serde_json::Value, out: &mut String) {
+	match value {
+		serde_json::Value::Array(arr) => {
+			out.push('[');
+			for (i, v) in arr.iter().enumerate() {
+				if i != 0 {
+					out.push(',');
+				}
+				write_canonical_json(v, out);
+			}
+			out.push(']');
+		}
+		serde_json::Value::Object(obj) => {
+			let mut keys = obj.keys().collect::<Vec<_>>();
+			keys.sort();
+			out.push('{');
+			for (i, k) in keys.into_iter().enumerate() {
+				if i != 0 {
+					out.push(',');
+				}
+				out.push_str(&serde_json::Value::String(k.clone()).to_string());
+				out.push(':');
+				write_canonical_json(&obj[k], out);
+			}
+			out.push('}');
+		}
+		v => out.push_str(&v.to_string()),
+	}
+}
+
+fn remove_path(value: &mut serde_json::Value, path: &str) {
+	let (parent, last) = match path.rsplit_once('.') {
+		Some((parent, last)) => (Some(parent), last),
+		None => (None, path),
+	};
+	let mut current = value;
+	for field in parent.into_iter().flat_map(|p| p.split('.')) {
+		match current.get_mut(field) {
+			Some(v) => current = v,
+			None => return,
+		}
+	}
+	if let Some(obj) = current.as_object_mut() {
+		obj.remove(last);
+	}
+}
+
+/// Hex-encode string (as utf-8) or byte array, `0x`-prefixed unless `prefix` is false
+#[builtin]
+pub fn builtin_hex_encode(data: Either![String, Vec<u8>], prefix: Option<bool>) -> Result<String> {
+	let encoded = match data {
+		Either2::A(s) => hex::encode(s),
+		Either2::B(bytes) => hex::encode(bytes),
+	};
+	Ok(if prefix.unwrap_or(true) {
+		format!("0x{encoded}")
+	} else {
+		encoded
+	})
+}
+
+/// Decode hex string (with optional `0x` prefix) into the byte array
+#[builtin]
+pub fn builtin_hex_decode(hex: String) -> Result<Vec<u8>> {
+	let data = hex.strip_prefix("0x").unwrap_or(&hex);
+	hex::decode(data).map_err(|e| runtime_error!("invalid hex {hex:?}: {e}"))
+}
+
+/// Base58-encode (bitcoin alphabet, as used by peer ids) the byte array
+#[builtin]
+pub fn builtin_base58_encode(data: Vec<u8>) -> Result<String> {
+	Ok(bs58::encode(data).into_string())
+}
+
+/// Decode base58 (bitcoin alphabet) string into the byte array
+#[builtin]
+pub fn builtin_base58_decode(data: String) -> Result<Vec<u8>> {
+	bs58::decode(&data)
+		.into_vec()
+		.map_err(|e| runtime_error!("invalid base58 {data:?}: {e}"))
+}
+
+/// Substitute `{{ name }}` placeholders with values from `vars`, unknown placeholders are errors.
+///
+/// There is no control flow, whitespace around the name is ignored, `\{{` is emitted as literal `{{`
+/// (written as `"\\{{"` in jsonnet string literals).
+#[builtin]
+pub fn builtin_template(
+	template: String,
+	vars: BTreeMap<String, Either![String, f64, bool]>,
+) -> Result<String> {
+	let mut out = String::with_capacity(template.len());
+	let mut rest = template.as_str();
+	while let Some(start) = rest.find("{{") {
+		if rest[..start].ends_with('\\') {
+			out.push_str(&rest[..start - 1]);
+			out.push_str("{{");
+			rest = &rest[start + 2..];
+			continue;
+		}
+		out.push_str(&rest[..start]);
+		let Some(end) = rest[start..].find("}}") else {
+			bail!("unterminated placeholder at: {:?}", &rest[start..]);
+		};
+		let name = rest[start + 2..start + end].trim();
+		match vars.get(name) {
+			Some(Either3::A(s)) => out.push_str(s),
+			Some(Either3::B(n)) => out.push_str(&n.to_string()),
+			Some(Either3::C(b)) => out.push_str(&b.to_string()),
+			None => bail!("unknown template placeholder: {name:?}"),
+		}
+		rest = &rest[start + end + 2..];
+	}
+	out.push_str(rest);
+	Ok(out)
+}
+
+/// Upper bound of `bdk.randomHex` size, bigger values are most likely a mistake
+const MAX_RANDOM_BYTES: usize = 64;
+
+/// `n` random bytes as unprefixed hex, i.e for unique volume/project names.
+///
+/// Uses the same RNG as key generation, thus reproducible with `--insecure-deterministic-rng`.
+#[builtin(fields(
+	#[trace(skip)]
+	rng: KeyRng,
+))]
+pub fn builtin_random_hex(this: &builtin_random_hex, n: usize) -> Result<String> {
+	if n == 0 || n > MAX_RANDOM_BYTES {
+		bail!("random byte count should be in 1..={MAX_RANDOM_BYTES} range, got {n}");
+	}
+	let mut bytes = vec![0; n];
+	this.rng.fill(&mut bytes);
+	Ok(hex::encode(bytes))
+}
+
+/// Stable sha256 of the value, usually called with `final` config.
+///
+/// Generated keys are part of the config, so key generation changes the fingerprint.
+#[builtin]
+pub fn builtin_config_fingerprint(value: Val, ignore_paths: Option<Vec<String>>) -> Result<String> {
+	let json = value.manifest(JsonFormat::cli(0, true))?;
+	let mut json: serde_json::Value =
+		serde_json::from_str(&json).map_err(|e| runtime_error!("json: {e}"))?;
+	for path in ignore_paths.iter().flatten() {
+		remove_path(&mut json, path);
+	}
+	let mut canonical = String::new();
+	write_canonical_json(&json, &mut canonical);
+	Ok(hex::encode(Sha256::digest(canonical.as_bytes())))
+}
+
 #[builtin]
 pub fn builtin_to_relative(from: String, to: String) -> Result<String> {
 	let diff = pathdiff::diff_paths(to, from)
@@ -108,21 +619,82 @@ pub fn builtin_docker_mounts() -> Result<Vec<String>> {
 	Ok(docker_mounts()?)
 }
 
+/// Pin docker image of the location to its digest, so every spec built from it uses the same image
+#[builtin]
+pub fn builtin_pin_image(location: FileLocation) -> Result<FileLocation> {
+	Ok(location.pin_image()?)
+}
+
 #[builtin(fields(
 	#[trace(skip)]
-	builder: Rc<dyn SpecBuilder>,
+	builders: Rc<SpecBuilders>,
+	#[trace(skip)]
+	metrics: Rc<Metrics>,
+	#[trace(skip)]
+	cache: Rc<SpecCache>,
 ))]
 pub fn builtin_process_spec(
 	this: &builtin_process_spec,
 	bin: FileLocation,
 	spec: SpecSource,
+	backend: Option<String>,
 ) -> Result<Val> {
-	let builder = &*this.builder;
+	let builder = &*this.builders.get(backend.as_deref())?;
+	let metrics = &this.metrics;
+	let cache = &this.cache;
 	Ok(match spec {
+		SpecSource::Genesis(g) if g.modify.is_none() && g.single_container.unwrap_or(false) => {
+			let timeout = g.timeout()?;
+			debug!("building genesis and raw");
+			let v = metrics.time("genesis+raw build", || {
+				// Keystore contents are not a part of the fingerprint
+				let key = match g.keystore_mount {
+					Some(_) => None,
+					None => cache.key(
+						"genesis+raw",
+						builder,
+						&bin,
+						&[g.chain.as_deref().unwrap_or_default().as_bytes()],
+					)?,
+				};
+				cache.get_or_build(key, || {
+					builder.build_genesis_raw(
+						&bin,
+						g.chain.clone(),
+						g.keystore_mount.as_ref(),
+						timeout,
+					)
+				})
+			})?;
+			let mut v = v.parse()?;
+			if let Some(modify) = &g.modify_raw {
+				v = modify
+					.evaluate_simple(&(v,), true)
+					.description("modify_raw callback")?;
+			}
+			v
+		}
 		SpecSource::Genesis(g) => {
+			if g.single_container.unwrap_or(false) {
+				warn!("singleContainer is ignored, as modify callback is set");
+			}
+			let timeout = g.timeout()?;
 			debug!("building genesis");
-			let v = builder.build_genesis(&bin, g.chain.clone())?;
-			let mut v: Val = serde_json::from_slice(&v).map_err(spec_builder::Error::from)?;
+			let v = metrics.time("genesis build", || {
+				let key = match g.keystore_mount {
+					Some(_) => None,
+					None => cache.key(
+						"genesis",
+						builder,
+						&bin,
+						&[g.chain.as_deref().unwrap_or_default().as_bytes()],
+					)?,
+				};
+				cache.get_or_build(key, || {
+					builder.build_genesis(&bin, g.chain.clone(), g.keystore_mount.as_ref(), timeout)
+				})
+			})?;
+			let mut v = v.parse()?;
 			if let Some(modify) = &g.modify {
 				v = modify
 					.evaluate_simple(&(v,), true)
@@ -130,8 +702,13 @@ pub fn builtin_process_spec(
 			}
 			let spec = v.manifest(JsonFormat::cli(4, true))?;
 			debug!("building raw");
-			let v = builder.build_raw(&bin, g.spec_file_prefix, spec)?;
-			let mut v: Val = serde_json::from_slice(&v).map_err(spec_builder::Error::from)?;
+			let v = metrics.time("raw build", || {
+				let key = cache.key("raw", builder, &bin, &[spec.as_bytes()])?;
+				cache.get_or_build(key, || {
+					builder.build_raw(&bin, g.spec_file_prefix, spec, timeout)
+				})
+			})?;
+			let mut v = v.parse()?;
 			if let Some(modify) = &g.modify_raw {
 				v = modify
 					.evaluate_simple(&(v,), true)
@@ -140,11 +717,30 @@ pub fn builtin_process_spec(
 			v
 		}
 		SpecSource::Raw(raw) => raw.raw_spec.clone(),
+		SpecSource::ExistingRawFile(f) => {
+			debug!("reading existing raw spec");
+			let data = std::fs::read_to_string(&f.path)
+				.map_err(|e| runtime_error!("failed to read raw spec {:?}: {e}", f.path))?;
+			let mut v: Val = serde_json::from_str(&data)
+				.map_err(|e| runtime_error!("raw spec {:?} is not valid json: {e}", f.path))?;
+			if let Some(modify) = &f.modify_raw {
+				v = modify
+					.evaluate_simple(&(v,), true)
+					.description("modify_raw callback")?;
+			}
+			v
+		}
 		SpecSource::FromScratchGenesis(f) => {
+			let timeout = f.timeout()?;
 			let spec = f.spec.manifest(JsonFormat::cli(4, true))?;
 			debug!("building raw");
-			let v = builder.build_raw(&bin, f.spec_file_prefix, spec)?;
-			let mut v: Val = serde_json::from_slice(&v).map_err(spec_builder::Error::from)?;
+			let v = metrics.time("raw build", || {
+				let key = cache.key("raw", builder, &bin, &[spec.as_bytes()])?;
+				cache.get_or_build(key, || {
+					builder.build_raw(&bin, f.spec_file_prefix, spec, timeout)
+				})
+			})?;
+			let mut v = v.parse()?;
 			if let Some(modify) = &f.modify_raw {
 				v = modify
 					.evaluate_simple(&(v,), true)
@@ -155,110 +751,1576 @@ pub fn builtin_process_spec(
 	})
 }
 
-#[derive(Typed)]
-pub struct AliasName {
-	alias: String,
+fn genesis_export_output(v: NodeOutput) -> Result<String> {
+	let out = String::from_utf8(v.into_bytes()?)
+		.map_err(|_| runtime_error!("node output is not utf-8"))?;
+	let out = out.trim();
+	if !out.starts_with("0x") {
+		bail!("unexpected node output, expected hex blob: {out:?}");
+	}
+	Ok(out.to_owned())
 }
 
 #[builtin(fields(
 	#[trace(skip)]
-	secrets: Rc<dyn SecretStorage>,
+	builders: Rc<SpecBuilders>,
 ))]
-pub fn builtin_ensure_keys(
-	this: &builtin_ensure_keys,
-	path: String,
-	wanted_keys: BTreeMap<String, Either![SignatureSchema, AliasName, ObjValue]>,
-	format: Option<Ss58Format>,
-) -> Result<Val> {
-	#[derive(Default, Typed)]
-	struct Keys {
-		#[typed(rename = "nodeIdentity")]
-		node_identity: String,
-		#[typed(add)]
-		keys: BTreeMap<String, String>,
-		#[typed(add)]
-		wallets: BTreeMap<String, String>,
-		#[typed(rename = "localKeystoreDir")]
-		local_keystore_dir: String,
-		#[typed(rename = "localNodeFile")]
-		local_node_file: String,
+pub fn builtin_export_genesis_state(
+	this: &builtin_export_genesis_state,
+	bin: FileLocation,
+	raw_spec: Val,
+	spec_file_prefix: Option<String>,
+	backend: Option<String>,
+) -> Result<String> {
+	let builder = this.builders.get(backend.as_deref())?;
+	let spec = raw_spec.manifest(JsonFormat::cli(4, true))?;
+	debug!("exporting genesis state");
+	let v = builder.build_genesis_state(&bin, spec_file_prefix, spec)?;
+	genesis_export_output(v)
+}
+
+#[builtin(fields(
+	#[trace(skip)]
+	builders: Rc<SpecBuilders>,
+))]
+pub fn builtin_export_genesis_wasm(
+	this: &builtin_export_genesis_wasm,
+	bin: FileLocation,
+	raw_spec: Val,
+	spec_file_prefix: Option<String>,
+	backend: Option<String>,
+) -> Result<String> {
+	let builder = this.builders.get(backend.as_deref())?;
+	let spec = raw_spec.manifest(JsonFormat::cli(4, true))?;
+	debug!("exporting genesis wasm");
+	let v = builder.build_genesis_wasm(&bin, spec_file_prefix, spec)?;
+	genesis_export_output(v)
+}
+
+/// Genesis hashes computed during this run, keyed by sha256 of the raw spec
+static GENESIS_HASHES: Mutex<BTreeMap<[u8; 32], String>> = Mutex::new(BTreeMap::new());
+
+/// Decode `0x`-prefixed hex key or value of the raw spec storage
+fn raw_storage_hex(s: &str) -> Result<Vec<u8>> {
+	let data = s
+		.strip_prefix("0x")
+		.ok_or_else(|| runtime_error!("raw storage entry should be 0x-prefixed: {s:?}"))?;
+	hex::decode(data).map_err(|e| runtime_error!("invalid hex {s:?}: {e}"))
+}
+
+fn raw_storage_map(v: &serde_json::Value) -> Result<BTreeMap<Vec<u8>, Vec<u8>>> {
+	let map = v
+		.as_object()
+		.ok_or_else(|| runtime_error!("raw storage should be object"))?;
+	map.iter()
+		.map(|(k, v)| {
+			let v = v
+				.as_str()
+				.ok_or_else(|| runtime_error!("raw storage value should be string: {k}"))?;
+			Ok((raw_storage_hex(k)?, raw_storage_hex(v)?))
+		})
+		.collect()
+}
+
+/// Genesis block hash, computed from the raw genesis storage the same way the node does it
+fn local_genesis_hash(spec: &str) -> Result<[u8; 32]> {
+	let spec: serde_json::Value =
+		serde_json::from_str(spec).map_err(|e| runtime_error!("invalid spec json: {e}"))?;
+	let raw = spec
+		.pointer("/genesis/raw")
+		.ok_or_else(|| runtime_error!("spec has no raw genesis"))?;
+	let top = raw_storage_map(
+		raw.get("top")
+			.ok_or_else(|| runtime_error!("raw genesis has no top storage"))?,
+	)?;
+	let mut children_default = HashMap::new();
+	if let Some(children) = raw.get("childrenDefault") {
+		let children = children
+			.as_object()
+			.ok_or_else(|| runtime_error!("childrenDefault should be object"))?;
+		for (key, data) in children {
+			let key = raw_storage_hex(key)?;
+			let child_info = ChildInfo::new_default(&key);
+			let data = raw_storage_map(data)?;
+			children_default.insert(key, StorageChild { data, child_info });
+		}
 	}
 
-	let format = format.unwrap_or_default().0;
-	let secrets = &this.secrets;
+	// Trie layout depends on the state version of the genesis runtime
+	let code = top
+		.get(well_known_keys::CODE)
+		.ok_or_else(|| runtime_error!("raw genesis has no runtime code"))?;
+	let executor = WasmExecutor::<sp_io::SubstrateHostFunctions>::builder()
+		.with_allow_missing_host_functions(true)
+		.build();
+	let runtime_code = RuntimeCode {
+		code_fetcher: &WrappedRuntimeCode(code.as_slice().into()),
+		heap_pages: None,
+		hash: sp_core::blake2_256(code).to_vec(),
+	};
+	let state_version = executor
+		.runtime_version(&mut BasicExternalities::new_empty(), &runtime_code)
+		.map_err(|e| runtime_error!("failed to read genesis runtime version: {e}"))?
+		.state_version();
 
-	let mut out = Keys::default();
+	let mut ext = BasicExternalities::new(Storage {
+		top,
+		children_default,
+	});
+	let (state_root, extrinsics_root) = ext.execute_with(|| {
+		(
+			sp_io::storage::root(state_version),
+			sp_io::trie::blake2_256_ordered_root(vec![], state_version),
+		)
+	});
+
+	// Header encoding, with zero parent hash, zero block number and empty digest.
+	// Compact encoding of zero is the same for any block number type.
+	let mut header = vec![0; 32];
+	Compact(0u32).encode_to(&mut header);
+	header.extend_from_slice(&state_root);
+	header.extend_from_slice(extrinsics_root.as_bytes());
+	Compact(0u32).encode_to(&mut header);
+	Ok(sp_core::blake2_256(&header))
+}
 
-	if secrets.get_node_id(&path)?.is_none() {
-		let pair = ed25519::Keypair::generate();
-		secrets.store_node_key(&path, pair)?;
+/// Genesis block hash, computed from the raw genesis storage.
+///
+/// If it can't be computed locally, it is computed as the hash of the genesis head exported by the node,
+/// which only works for nodes supporting `export-genesis-state`, i.e cumulus-based ones.
+#[builtin(fields(
+	#[trace(skip)]
+	builders: Rc<SpecBuilders>,
+))]
+pub fn builtin_genesis_hash(
+	this: &builtin_genesis_hash,
+	bin: FileLocation,
+	raw_spec: Val,
+	backend: Option<String>,
+) -> Result<String> {
+	let spec = raw_spec.manifest(JsonFormat::cli(4, true))?;
+	let key: [u8; 32] = Sha256::digest(spec.as_bytes()).into();
+	if let Some(hash) = GENESIS_HASHES.lock().expect("not poisoned").get(&key) {
+		return Ok(hash.clone());
 	}
-	out.node_identity = secrets.get_node_id(&path)?.expect("just inserted");
+	let hash = match local_genesis_hash(&spec) {
+		Ok(hash) => hash,
+		Err(e) => {
+			debug!("failed to compute genesis hash locally, exporting genesis head: {e}");
+			let builder = this.builders.get(backend.as_deref())?;
+			let head = genesis_export_output(builder.build_genesis_state(&bin, None, spec)?)?;
+			let head = hex::decode(&head[2..])
+				.map_err(|e| runtime_error!("node returned invalid genesis head: {e}"))?;
+			sp_core::blake2_256(&head)
+		}
+	};
+	let hash = format!("0x{}", hex::encode(hash));
+	GENESIS_HASHES
+		.lock()
+		.expect("not poisoned")
+		.insert(key, hash.clone());
+	Ok(hash)
+}
 
-	for (name, scheme) in &wanted_keys {
-		if let Some(ty) = name.strip_prefix('_') {
-			let Either3::A(scheme) = scheme else {
-				bail!("wallet scheme should be string-based: {name}");
-			};
-			if secrets.get_wallet(&path, ty, *scheme, format)?.is_none() {
-				let suri = Mnemonic::generate_in(Language::English, 24)
-					.unwrap()
-					.to_string();
-				secrets.store_wallet(&path, ty, *scheme, &suri, format)?;
-			}
-			out.wallets.insert(
-				name[1..].to_string(),
-				secrets
-					.get_wallet(&path, ty, *scheme, format)?
-					.expect("just inserted"),
-			);
-		} else if name.ends_with("Keys") && name.len() > 4
-			|| name.ends_with("Key") && name.len() > 3
-		{
-			// Key set, i.e `sessionKeys`, pass.
+/// Keys requested per `state_getKeysPaged` call
+const FORK_PAGE_SIZE: usize = 1000;
+/// `state_queryStorageAt` response item: block hash, and changed keys with their values
+type StorageChangeSet = (String, Vec<(String, Option<String>)>);
+
+/// Storage of the listed pallets (by name, or by `0x`-prefixed raw key prefix), read from
+/// the live node, in the format of `genesis.raw.top`.
+///
+/// Every key is read, which is heavy for both baedeker and the node, so the list of pallets should
+/// be kept short. State is read at the given block hash, or at the finalized head, and nodes
+/// prune the old state by default, so the archive node is required for the older blocks.
+#[builtin]
+pub fn builtin_fork_state(
+	rpc_url: String,
+	pallets: Vec<String>,
+	at: Option<String>,
+	timeout_secs: Option<u32>,
+) -> Result<ObjValue> {
+	let timeout = Duration::from_secs(timeout_secs.unwrap_or(60).into());
+	let client = RpcClient::new(&rpc_url, timeout)?;
+	let at = match at {
+		Some(at) => at,
+		None => {
+			let head = client.call("chain_getFinalizedHead", json!([]))?;
+			head.as_str()
+				.ok_or_else(|| {
+					rpc::Error::UnexpectedResponse("chain_getFinalizedHead".into(), head.clone())
+				})?
+				.to_owned()
+		}
+	};
+	let mut out = ObjValueBuilder::new();
+	let mut total = 0;
+	for pallet in &pallets {
+		let prefix = if pallet.starts_with("0x") {
+			pallet.clone()
 		} else {
-			if matches!(scheme, Either3::B(_)) {
-				continue;
-			};
-			let Either3::A(scheme) = scheme else {
-				bail!("secret scheme should be string-based: {name}");
-			};
-			if secrets.get_typed(&path, name, *scheme, format)?.is_none() {
-				let suri = Mnemonic::generate_in(Language::English, 12)
-					.unwrap()
-					.to_string();
-				secrets.store_typed_key(&path, name, *scheme, &suri, format)?;
-				for (alias_name, alias) in &wanted_keys {
-					let Either3::B(alias) = alias else {
-						continue;
-					};
-					if &alias.alias != name {
+			format!("0x{}", hex::encode(sp_core::twox_128(pallet.as_bytes())))
+		};
+		let mut start: Option<String> = None;
+		loop {
+			let keys = client.call(
+				"state_getKeysPaged",
+				json!([prefix, FORK_PAGE_SIZE, start, at]),
+			)?;
+			let keys: Vec<String> = serde_json::from_value(keys.clone())
+				.map_err(|_| rpc::Error::UnexpectedResponse("state_getKeysPaged".into(), keys))?;
+			if keys.is_empty() {
+				break;
+			}
+			let changes = client.call("state_queryStorageAt", json!([keys, at]))?;
+			let sets: Vec<StorageChangeSet> = changes
+				.as_array()
+				.into_iter()
+				.flatten()
+				.map(|set| {
+					serde_json::from_value(json!([set["block"], set["changes"]])).map_err(|_| {
+						rpc::Error::UnexpectedResponse("state_queryStorageAt".into(), set.clone())
+					})
+				})
+				.collect::<std::result::Result<_, _>>()?;
+			for (_, changes) in sets {
+				for (key, value) in changes {
+					let Some(value) = value else {
 						continue;
 					};
-					secrets.store_typed_key(&path, alias_name, *scheme, &suri, format)?;
+					out.field(key).value(String::into_untyped(value)?);
+					total += 1;
 				}
 			}
-			let stored = secrets
-				.get_typed(&path, name, *scheme, format)?
-				.expect("just inserted");
-			out.keys.insert(name.clone(), stored.clone());
-			for (alias_name, alias) in &wanted_keys {
-				let Either3::B(alias) = alias else {
-					continue;
-				};
-				if &alias.alias != name {
-					continue;
-				};
-				out.keys.insert(alias_name.clone(), stored.clone());
+			let done = keys.len() < FORK_PAGE_SIZE;
+			start = keys.into_iter().last();
+			if done {
+				break;
 			}
 		}
 	}
-	// TODO: Remove the requirement
-	out.local_keystore_dir = secrets
-		.local_keystore_dir(&path)?
+	info!(
+		"forked {total} storage entries of {} pallet(s) at {at}",
+		pallets.len()
+	);
+	Ok(out.build())
+}
+
+#[builtin]
+pub fn builtin_discover_peers(rpc_url: String, timeout_secs: Option<u32>) -> Result<Val> {
+	#[derive(Typed)]
+	struct Peer {
+		#[typed(rename = "peerId")]
+		peer_id: String,
+		#[typed(rename = "listenAddresses")]
+		listen_addresses: Vec<String>,
+	}
+
+	let timeout = Duration::from_secs(timeout_secs.unwrap_or(10).into());
+	let client = RpcClient::new(&rpc_url, timeout)?;
+
+	let peer_id = client.call("system_localPeerId", json!([]))?;
+	let peer_id = peer_id
+		.as_str()
+		.ok_or_else(|| {
+			rpc::Error::UnexpectedResponse("system_localPeerId".into(), peer_id.clone())
+		})?
+		.to_owned();
+
+	let addresses = client.call("system_localListenAddresses", json!([]))?;
+	let listen_addresses = serde_json::from_value(addresses.clone()).map_err(|_| {
+		rpc::Error::UnexpectedResponse("system_localListenAddresses".into(), addresses)
+	})?;
+
+	Peer::into_untyped(Peer {
+		peer_id,
+		listen_addresses,
+	})
+}
+
+/// Poll `system_health` until node is healthy (not syncing, and has peers if it should),
+/// returns the health object
+#[builtin]
+pub fn builtin_wait_for_rpc(
+	rpc_url: String,
+	timeout_secs: u32,
+	interval_secs: Option<f64>,
+) -> Result<Val> {
+	let interval = Duration::from_secs_f64(interval_secs.unwrap_or(1.0).max(0.1));
+	let timeout = Duration::from_secs(timeout_secs.into());
+	let client = RpcClient::new(&rpc_url, interval.max(Duration::from_secs(1)))?;
+	let deadline = Instant::now() + timeout;
+
+	let mut attempt = 0;
+	loop {
+		attempt += 1;
+		let last = match client.call("system_health", json!([])) {
+			Ok(health) => {
+				let syncing = health["isSyncing"].as_bool().unwrap_or(true);
+				let should_have_peers = health["shouldHavePeers"].as_bool().unwrap_or(false);
+				let peers = health["peers"].as_u64().unwrap_or(0);
+				if !syncing && (!should_have_peers || peers > 0) {
+					return serde_json::from_value(health)
+						.map_err(|e| runtime_error!("system_health: {e}"));
+				}
+				format!("not healthy yet: {health}")
+			}
+			Err(e) => e.to_string(),
+		};
+		debug!("waiting for {rpc_url}, attempt {attempt}: {last}");
+		if Instant::now() >= deadline {
+			bail!("node at {rpc_url} is not healthy after {timeout_secs}s: {last}");
+		}
+		std::thread::sleep(interval);
+	}
+}
+
+#[derive(Typed)]
+pub struct PrometheusNode {
+	/// Defaults to the node name
+	hostname: Option<String>,
+	#[typed(rename = "prometheusPort")]
+	prometheus_port: Option<u16>,
+}
+
+/// `prometheus.yml` with scrape config for every node exposing `prometheusPort`,
+/// should be emitted as the docker_compose output entry
+#[builtin]
+pub fn builtin_prometheus_targets(
+	nodes: BTreeMap<String, PrometheusNode>,
+	job: Option<String>,
+) -> Result<String> {
+	let static_configs = nodes
+		.into_iter()
+		.filter_map(|(name, node)| {
+			let port = node.prometheus_port?;
+			let host = node.hostname.unwrap_or_else(|| name.clone());
+			Some(json!({
+				"targets": [format!("{host}:{port}")],
+				"labels": { "node": name },
+			}))
+		})
+		.collect::<Vec<_>>();
+	let config = json!({
+		"scrape_configs": [{
+			"job_name": job.unwrap_or_else(|| "substrate".to_owned()),
+			"static_configs": static_configs,
+		}],
+	});
+	serde_yaml::to_string(&config).map_err(|e| runtime_error!("yaml: {e}"))
+}
+
+#[derive(Typed)]
+pub struct AuthorityNode {
+	/// Role => address, as returned by `ensureKeys`
+	keys: Option<ObjValue>,
+}
+
+/// Authority list as `[{ node, <role>: address }]`, ordered by node name.
+///
+/// `nodes` is a map of node name to the `ensureKeys` output (or any object with `keys`), every
+/// node should have all the roles. Nodes with identical keys are only listed once, but the same
+/// key can't be shared between nodes with different key sets.
+#[builtin]
+pub fn builtin_authority_set(
+	nodes: BTreeMap<String, AuthorityNode>,
+	roles: Vec<String>,
+) -> Result<Val> {
+	if roles.is_empty() {
+		bail!("at least one authority role should be specified");
+	}
+	let mut missing = Vec::new();
+	let mut authorities = Vec::<(String, Vec<String>)>::new();
+	for (name, node) in &nodes {
+		let mut keys = Vec::with_capacity(roles.len());
+		let mut node_missing = Vec::new();
+		for role in &roles {
+			let key = match &node.keys {
+				Some(keys) => keys.get(role.as_str().into())?,
+				None => None,
+			};
+			match key {
+				Some(key) => keys.push(
+					String::from_untyped(key).with_description(|| format!("{name}.keys.{role}"))?,
+				),
+				None => node_missing.push(role.as_str()),
+			}
+		}
+		if !node_missing.is_empty() {
+			missing.push(format!("{name} (missing {})", node_missing.join(", ")));
+			continue;
+		}
+		if let Some((other, _)) = authorities.iter().find(|(_, other)| *other == keys) {
+			warn!("authority {name} has the same keys as {other}, only listing it once");
+			continue;
+		}
+		authorities.push((name.clone(), keys));
+	}
+	if !missing.is_empty() {
+		bail!("nodes are missing authority roles: {}", missing.join("; "));
+	}
+
+	let mut owners = BTreeMap::<(&str, &str), &str>::new();
+	for (name, keys) in &authorities {
+		for (role, key) in roles.iter().zip(keys) {
+			if let Some(other) = owners.insert((role.as_str(), key.as_str()), name.as_str()) {
+				bail!("{role} key {key} is shared by {other} and {name}, which have different key sets");
+			}
+		}
+	}
+
+	let mut out = Vec::with_capacity(authorities.len());
+	for (name, keys) in authorities {
+		let mut entry = ObjValueBuilder::new();
+		entry.field("node").value(String::into_untyped(name)?);
+		for (role, key) in roles.iter().zip(keys) {
+			entry.field(role.as_str()).value(String::into_untyped(key)?);
+		}
+		out.push(Val::Obj(entry.build()));
+	}
+	<Vec<Val>>::into_untyped(out)
+}
+
+#[derive(Typed)]
+pub struct ProxyNode {
+	/// Defaults to the node name
+	hostname: Option<String>,
+	#[typed(rename = "rpcPort")]
+	rpc_port: Option<u16>,
+}
+
+/// Node names become url paths and hostnames are embedded into the config as is
+fn is_proxy_token(s: &str) -> bool {
+	!s.is_empty()
+		&& s.chars()
+			.all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Reverse proxy config, routing `/<node>` to the RPC port of every node exposing `rpcPort`,
+/// `kind` is either `caddy` (Caddyfile) or `nginx` (server block for `conf.d`).
+/// Should be emitted as the docker_compose output entry
+#[builtin]
+pub fn builtin_reverse_proxy_config(
+	nodes: BTreeMap<String, ProxyNode>,
+	kind: String,
+	listen_port: Option<u16>,
+) -> Result<String> {
+	let listen = listen_port.unwrap_or(8080);
+	let mut routes = vec![];
+	for (name, node) in nodes {
+		let Some(port) = node.rpc_port else {
+			continue;
+		};
+		let host = node.hostname.unwrap_or_else(|| name.clone());
+		if !is_proxy_token(&name) || !is_proxy_token(&host) {
+			bail!("node {name:?} (hostname {host:?}) can't be used in the proxy config, only alphanumerics, `-`, `_` and `.` are allowed");
+		}
+		routes.push((name, host, port));
+	}
+	let mut out = String::new();
+	match kind.as_str() {
+		"caddy" => {
+			out.push_str(&format!(":{listen} {{\n"));
+			for (name, host, port) in routes {
+				// Websocket upgrades are handled by reverse_proxy itself
+				out.push_str(&format!(
+					"\thandle_path /{name}* {{\n\t\treverse_proxy {host}:{port}\n\t}}\n"
+				));
+			}
+			out.push_str("}\n");
+		}
+		"nginx" => {
+			out.push_str(
+				"map $http_upgrade $connection_upgrade {\n\tdefault upgrade;\n\t'' close;\n}\n\n",
+			);
+			out.push_str(&format!("server {{\n\tlisten {listen};\n"));
+			for (name, host, port) in routes {
+				out.push_str(&format!(
+					"\tlocation /{name}/ {{\n\
+					 \t\tproxy_pass http://{host}:{port}/;\n\
+					 \t\tproxy_http_version 1.1;\n\
+					 \t\tproxy_set_header Upgrade $http_upgrade;\n\
+					 \t\tproxy_set_header Connection $connection_upgrade;\n\
+					 \t}}\n"
+				));
+			}
+			out.push_str("}\n");
+		}
+		other => bail!("unknown reverse proxy kind {other:?}, expected caddy or nginx"),
+	}
+	Ok(out)
+}
+
+#[derive(Typed, Default)]
+pub struct NodeRules {
+	/// Paths of fields, which should be present in node
+	required: Option<Vec<String>>,
+	/// Paths of fields, which should contain valid port numbers, in addition to top-level `*Port` fields
+	ports: Option<Vec<String>>,
+	/// Paths of fields, which should contain either scheme or map of schemes, in addition to `wantedKeys`
+	schemes: Option<Vec<String>>,
+}
+
+fn assert_port(path: &str, val: Val) -> Result<()> {
+	let port = u16::from_untyped(val).with_description(|| format!("node.{path}"))?;
+	if port == 0 {
+		bail!("node.{path}: port should be in range 1..=65535");
+	}
+	Ok(())
+}
+
+fn assert_scheme(path: &str, val: Val) -> Result<()> {
+	SignatureSchema::from_untyped(val).with_description(|| format!("node.{path}"))?;
+	Ok(())
+}
+
+#[builtin]
+pub fn builtin_assert_node(node: ObjValue, rules: Option<NodeRules>) -> Result<ObjValue> {
+	let rules = rules.unwrap_or_default();
+	let node_val = Val::Obj(node.clone());
+
+	for path in rules.required.iter().flatten() {
+		if get_path(&node_val, path)?.is_none() {
+			bail!("node.{path}: required field is missing");
+		}
+	}
+
+	for field in node.fields(false) {
+		if field.ends_with("Port") {
+			let value = node.get(field.clone())?.expect("field exists");
+			assert_port(&field, value)?;
+		}
+	}
+	for path in rules.ports.iter().flatten() {
+		let Some(value) = get_path(&node_val, path)? else {
+			continue;
+		};
+		assert_port(path, value)?;
+	}
+
+	let scheme_paths =
+		std::iter::once("wantedKeys").chain(rules.schemes.iter().flatten().map(String::as_str));
+	for path in scheme_paths {
+		match get_path(&node_val, path)? {
+			None => {}
+			Some(Val::Obj(schemes)) => {
+				for (name, value) in schemes.iter(false) {
+					let value = value?;
+					// Aliases and key sets are validated by ensureKeys itself
+					if matches!(value, Val::Str(_)) {
+						assert_scheme(&format!("{path}.{name}"), value)?;
+					}
+				}
+			}
+			Some(value) => assert_scheme(path, value)?,
+		}
+	}
+
+	Ok(node)
+}
+
+#[derive(Typed, Default)]
+pub struct NetworkSelectors {
+	/// Paths to node maps, `*` matches any field
+	nodes: Option<Vec<String>>,
+	/// Field of node, containing its hostname, node name is used if missing
+	hostname: Option<String>,
+	/// Fields of node, containing ports, top-level `*Port` fields are used if not set
+	ports: Option<Vec<String>>,
+}
+
+#[builtin]
+pub fn builtin_validate_network(config: Val, selectors: Option<NetworkSelectors>) -> Result<Val> {
+	let selectors = selectors.unwrap_or_default();
+	let node_paths = selectors
+		.nodes
+		.unwrap_or_else(|| vec!["relay.nodes".to_owned(), "parachains.*.nodes".to_owned()]);
+	let hostname_field = selectors.hostname.unwrap_or_else(|| "hostname".to_owned());
+
+	let mut hostnames = BTreeMap::<String, String>::new();
+	let mut ports = BTreeMap::<u16, String>::new();
+	for nodes_path in &node_paths {
+		for (nodes_path, nodes) in glob_path(&config, nodes_path)? {
+			let nodes = ObjValue::from_untyped(nodes).with_description(|| nodes_path.clone())?;
+			for (name, node) in nodes.iter(false) {
+				let node_path = format!("{nodes_path}.{name}");
+				let node = node?;
+
+				let hostname = match get_path(&node, &hostname_field)? {
+					Some(hostname) => {
+						String::from_untyped(hostname).with_description(|| node_path.clone())?
+					}
+					None => name.to_string(),
+				};
+				if let Some(other) = hostnames.insert(hostname.clone(), node_path.clone()) {
+					bail!("duplicate hostname {hostname:?}: used by both {other} and {node_path}");
+				}
+
+				let node_ports = match &selectors.ports {
+					Some(ports) => ports.clone(),
+					None => ObjValue::from_untyped(node.clone())?
+						.fields(false)
+						.into_iter()
+						.filter(|f| f.ends_with("Port"))
+						.map(|f| f.to_string())
+						.collect(),
+				};
+				for port_field in node_ports {
+					let Some(port) = get_path(&node, &port_field)? else {
+						continue;
+					};
+					let port = u16::from_untyped(port)
+						.with_description(|| format!("{node_path}.{port_field}"))?;
+					let used_by = format!("{node_path}.{port_field}");
+					if let Some(other) = ports.insert(port, used_by.clone()) {
+						bail!("duplicate port {port}: used by both {other} and {used_by}");
+					}
+				}
+			}
+		}
+	}
+
+	Ok(config)
+}
+
+pub struct PortAllocator {
+	cursor: u32,
+	reserved: BTreeSet<u16>,
+	allocated: BTreeMap<String, u16>,
+}
+impl PortAllocator {
+	fn is_free(&self, port: u16) -> bool {
+		!self.reserved.contains(&port) && !self.allocated.values().any(|p| *p == port)
+	}
+	fn next(&mut self, name: Option<String>) -> Result<u16> {
+		// Jsonnet may evaluate the same expression multiple times, named allocations are stable
+		if let Some(port) = name.as_ref().and_then(|n| self.allocated.get(n)) {
+			return Ok(*port);
+		}
+		loop {
+			let Ok(port) = u16::try_from(self.cursor) else {
+				bail!("port allocator exhausted");
+			};
+			self.cursor += 1;
+			if !self.is_free(port) {
+				continue;
+			}
+			let name = name.unwrap_or_else(|| format!("#{}", self.allocated.len()));
+			self.allocated.insert(name, port);
+			return Ok(port);
+		}
+	}
+}
+
+#[builtin(fields(
+	#[trace(skip)]
+	allocator: Rc<RefCell<PortAllocator>>,
+))]
+pub fn builtin_port_allocator_next(
+	this: &builtin_port_allocator_next,
+	name: Option<String>,
+) -> Result<u16> {
+	this.allocator.borrow_mut().next(name)
+}
+
+#[builtin(fields(
+	#[trace(skip)]
+	allocator: Rc<RefCell<PortAllocator>>,
+))]
+pub fn builtin_port_allocator_allocations(
+	this: &builtin_port_allocator_allocations,
+) -> Result<BTreeMap<String, u16>> {
+	Ok(this.allocator.borrow().allocated.clone())
+}
+
+#[builtin]
+pub fn builtin_port_allocator(base: u16, reserved: Option<Vec<u16>>) -> Result<ObjValue> {
+	if base == 0 {
+		bail!("base port should be in range 1..=65535");
+	}
+	let allocator = Rc::new(RefCell::new(PortAllocator {
+		cursor: base.into(),
+		reserved: reserved.into_iter().flatten().collect(),
+		allocated: BTreeMap::new(),
+	}));
+	let mut out = ObjValueBuilder::new();
+	out.method(
+		"next",
+		builtin_port_allocator_next {
+			allocator: allocator.clone(),
+		},
+	);
+	out.method(
+		"allocations",
+		builtin_port_allocator_allocations { allocator },
+	);
+	Ok(out.build())
+}
+
+#[derive(Typed)]
+pub struct NodeResources {
+	/// Fractional number of cpus, either number or string
+	cpus: Option<Either![f64, String]>,
+	/// Memory limit in docker notation (`512m`, `2g`)
+	memory: Option<String>,
+}
+
+fn validate_memory(memory: &str) -> bool {
+	let digits = memory.trim_end_matches(['b', 'k', 'm', 'g', 'B', 'K', 'M', 'G']);
+	let suffix = &memory[digits.len()..];
+	!digits.is_empty()
+		&& digits.bytes().all(|b| b.is_ascii_digit())
+		&& matches!(
+			suffix.to_ascii_lowercase().as_str(),
+			"" | "b" | "k" | "m" | "g" | "kb" | "mb" | "gb"
+		)
+}
+
+/// Nodes as `[{ name, node }]` array, ordered by `key(name, node)` (string or number), or by name.
+///
+/// Object fields are already iterated in sorted order by jsonnet, unless order preservation is
+/// requested, but arrays built from them (and node lists passed around as arrays) are not.
+#[builtin]
+pub fn builtin_sort_nodes(nodes: ObjValue, key: Option<FuncVal>) -> Result<Val> {
+	let mut entries = Vec::new();
+	for name in nodes.fields(false) {
+		let node = nodes.get(name.clone())?.expect("field exists");
+		let sort_key = match &key {
+			Some(key) => <Either![String, f64]>::from_untyped(
+				key.evaluate_simple(&(name.to_string(), node.clone()), false)
+					.with_description(|| format!("sort key of {name}"))?,
+			)
+			.with_description(|| format!("sort key of {name}"))?,
+			None => Either2::A(name.to_string()),
+		};
+		entries.push((sort_key, name, node));
+	}
+
+	let mut failed = false;
+	entries.sort_by(|(a, ..), (b, ..)| match (a, b) {
+		(Either2::A(a), Either2::A(b)) => a.cmp(b),
+		(Either2::B(a), Either2::B(b)) => a.total_cmp(b),
+		_ => {
+			failed = true;
+			std::cmp::Ordering::Equal
+		}
+	});
+	if failed {
+		bail!("sort keys should be either all strings or all numbers");
+	}
+
+	let mut out = Vec::with_capacity(entries.len());
+	for (_, name, node) in entries {
+		let mut entry = ObjValueBuilder::new();
+		entry.field("name").value(IStr::into_untyped(name)?);
+		entry.field("node").value(node);
+		out.push(Val::Obj(entry.build()));
+	}
+	<Vec<Val>>::into_untyped(out)
+}
+
+/// Canonical JSON of the value, equal for the structurally equal values
+fn canonical_key(value: &Val) -> Result<String> {
+	let json = value.manifest(JsonFormat::cli(0, true))?;
+	let json: serde_json::Value =
+		serde_json::from_str(&json).map_err(|e| runtime_error!("json: {e}"))?;
+	let mut canonical = String::new();
+	write_canonical_json(&json, &mut canonical);
+	Ok(canonical)
+}
+
+/// Returns the list unchanged, or fails listing all keys (`key(item)`, or the item itself)
+/// present more than once, with their positions.
+#[builtin]
+pub fn builtin_assert_unique(list: Vec<Val>, key: Option<FuncVal>) -> Result<Vec<Val>> {
+	let mut seen = BTreeMap::<String, Vec<usize>>::new();
+	for (i, item) in list.iter().enumerate() {
+		let item_key = match &key {
+			Some(key) => key
+				.evaluate_simple(&(item.clone(),), false)
+				.with_description(|| format!("key of list[{i}]"))?,
+			None => item.clone(),
+		};
+		let item_key = canonical_key(&item_key).with_description(|| format!("key of list[{i}]"))?;
+		seen.entry(item_key).or_default().push(i);
+	}
+	let mut duplicates = Vec::new();
+	for (item_key, positions) in &seen {
+		if positions.len() < 2 {
+			continue;
+		}
+		let values = positions
+			.iter()
+			.map(|&i| canonical_key(&list[i]))
+			.collect::<Result<Vec<_>>>()?;
+		duplicates.push(format!(
+			"key {item_key} at positions {positions:?}, values: {}",
+			values.join(", ")
+		));
+	}
+	if !duplicates.is_empty() {
+		bail!("list has duplicate keys:\n{}", duplicates.join("\n"));
+	}
+	Ok(list)
+}
+
+/// Validate `node.resources`, and convert them to the compose service mixin
+#[builtin]
+pub fn builtin_node_resources(node: ObjValue) -> Result<Val> {
+	let Some(resources) = node.get("resources".into())? else {
+		return Ok(Val::Obj(ObjValueBuilder::new().build()));
+	};
+	let resources = NodeResources::from_untyped(resources).description("node.resources")?;
+
+	let mut limits = ObjValueBuilder::new();
+	if let Some(cpus) = resources.cpus {
+		let cpus = match cpus {
+			Either2::A(n) => n,
+			Either2::B(s) => s
+				.parse()
+				.map_err(|_| runtime_error!("node.resources.cpus: invalid number: {s:?}"))?,
+		};
+		if !cpus.is_finite() || cpus <= 0.0 {
+			bail!("node.resources.cpus: should be positive, got {cpus}");
+		}
+		limits
+			.field("cpus")
+			.value(String::into_untyped(cpus.to_string())?);
+	}
+	if let Some(memory) = resources.memory {
+		if !validate_memory(&memory) {
+			bail!("node.resources.memory: expected <number>[b|k|m|g], got {memory:?}");
+		}
+		limits.field("memory").value(String::into_untyped(memory)?);
+	}
+
+	let mut deploy = ObjValueBuilder::new();
+	deploy.field("resources").value({
+		let mut resources = ObjValueBuilder::new();
+		resources.field("limits").value(Val::Obj(limits.build()));
+		Val::Obj(resources.build())
+	});
+	let mut out = ObjValueBuilder::new();
+	out.field("deploy").value(Val::Obj(deploy.build()));
+	Ok(Val::Obj(out.build()))
+}
+
+fn is_env_name(name: &str) -> bool {
+	let mut chars = name.chars();
+	chars
+		.next()
+		.is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+		&& chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Merge `node.env` with `extra` (which takes precedence), result is used as the compose service `environment`
+#[builtin]
+pub fn builtin_node_env(
+	node: ObjValue,
+	extra: Option<BTreeMap<String, String>>,
+) -> Result<ObjValue> {
+	let mut env = match node.get("env".into())? {
+		Some(env) => BTreeMap::<String, String>::from_untyped(env).description("node.env")?,
+		None => BTreeMap::new(),
+	};
+	env.extend(extra.unwrap_or_default());
+
+	let mut out = ObjValueBuilder::new();
+	for (name, value) in env {
+		if !is_env_name(&name) {
+			bail!("invalid environment variable name: {name:?}");
+		}
+		out.field(name).value(String::into_untyped(value)?);
+	}
+	Ok(out.build())
+}
+
+fn quote_env_value(value: &str) -> String {
+	if value
+		.chars()
+		.all(|c| c.is_ascii_alphanumeric() || "_./:@,+-".contains(c))
+	{
+		value.to_owned()
+	} else if !value.contains('\'') {
+		// Single-quoted values are taken literally by compose
+		format!("'{value}'")
+	} else {
+		let mut out = String::from("\"");
+		for c in value.chars() {
+			if matches!(c, '\\' | '"' | '$') {
+				out.push('\\');
+			}
+			out.push(c);
+		}
+		out.push('"');
+		out
+	}
+}
+
+const LOG_LEVELS: &[&str] = &["trace", "debug", "info", "warn", "error", "off"];
+
+fn validate_log_directive(directive: &str) -> bool {
+	let (target, level) = match directive.split_once('=') {
+		Some((target, level)) => (Some(target), level),
+		None if LOG_LEVELS.contains(&directive.to_ascii_lowercase().as_str()) => {
+			return true;
+		}
+		// Bare target enables all levels for it
+		None => (Some(directive), "trace"),
+	};
+	target.is_some_and(|t| {
+		!t.is_empty()
+			&& t.chars()
+				.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | ':' | '-'))
+	}) && LOG_LEVELS.contains(&level.to_ascii_lowercase().as_str())
+}
+
+/// Validate node `RUST_LOG` directives, result is an environment mixin to be merged with `bdk.nodeEnv`
+#[builtin]
+pub fn builtin_node_log_config(node: String, directives: String) -> Result<ObjValue> {
+	if let Some(invalid) = directives
+		.split(',')
+		.find(|d| !validate_log_directive(d.trim()))
+	{
+		bail!("node {node}: invalid log directive {invalid:?}, expected [target=]level");
+	}
+	let mut out = ObjValueBuilder::new();
+	out.field("RUST_LOG")
+		.value(String::into_untyped(directives)?);
+	Ok(out.build())
+}
+
+/// Format `.env` file for compose, should be emitted as `.env` entry of the docker_compose output
+#[builtin]
+pub fn builtin_env_file(vars: BTreeMap<String, Either![String, f64, bool]>) -> Result<String> {
+	let mut out = String::new();
+	for (name, value) in vars {
+		if !is_env_name(&name) {
+			bail!("invalid environment variable name: {name:?}");
+		}
+		let value = match value {
+			Either3::A(s) => s,
+			Either3::B(n) => n.to_string(),
+			Either3::C(b) => b.to_string(),
+		};
+		if value.contains(['\n', '\r']) {
+			bail!("environment variable {name} value should not contain newlines");
+		}
+		out.push_str(&name);
+		out.push('=');
+		out.push_str(&quote_env_value(&value));
+		out.push('\n');
+	}
+	Ok(out)
+}
+
+fn on_path(binary: &str) -> bool {
+	let Some(path) = std::env::var_os("PATH") else {
+		return false;
+	};
+	std::env::split_paths(&path).any(|dir| {
+		dir.join(binary)
+			.metadata()
+			.is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+	})
+}
+
+/// Architecture name, as used in docker platforms
+fn docker_arch(arch: &str) -> &str {
+	match arch {
+		"x86_64" => "amd64",
+		"aarch64" => "arm64",
+		"arm" => "arm",
+		"riscv64" => "riscv64",
+		"powerpc64" => "ppc64le",
+		"s390x" => "s390x",
+		other => other,
+	}
+}
+
+/// Whether the node passes `--node-filter`, every node does if the filter is not set.
+///
+/// Library should only emit and build specs for the selected nodes, but still reference the
+/// full node set in the address book and discovery.
+#[builtin(fields(
+	#[trace(skip)]
+	node_filter: Option<String>,
+))]
+pub fn builtin_node_selected(this: &builtin_node_selected, name: String) -> bool {
+	match this.node_filter.as_deref() {
+		Some(filter) => glob_match(filter, &name),
+		None => true,
+	}
+}
+
+#[derive(Typed)]
+pub struct HostInfo {
+	/// Architecture in docker notation (`amd64`, `arm64`)
+	arch: String,
+	os: String,
+	#[typed(rename = "dockerAvailable")]
+	docker_available: bool,
+	#[typed(rename = "podmanAvailable")]
+	podman_available: bool,
+}
+
+/// Container runtimes availability (docker, podman), probed once per run
+static RUNTIMES: OnceLock<(bool, bool)> = OnceLock::new();
+
+#[builtin(fields(
+	#[trace(skip)]
+	platform: Option<String>,
+))]
+pub fn builtin_host_info(this: &builtin_host_info) -> Result<HostInfo> {
+	let (docker_available, podman_available) =
+		*RUNTIMES.get_or_init(|| (on_path("docker"), on_path("podman")));
+	let (os, arch) = match &this.platform {
+		Some(platform) => {
+			let (os, arch) = platform
+				.split_once('/')
+				.ok_or_else(|| runtime_error!("platform should be in form of os/arch"))?;
+			(os.to_owned(), arch.to_owned())
+		}
+		None => (
+			std::env::consts::OS.to_owned(),
+			docker_arch(std::env::consts::ARCH).to_owned(),
+		),
+	};
+	Ok(HostInfo {
+		arch,
+		os,
+		docker_available,
+		podman_available,
+	})
+}
+
+/// Overrides the current time for `bdk.now`, as in https://reproducible-builds.org/specs/source-date-epoch/
+const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
+/// Format unix timestamp as RFC3339 UTC time
+fn rfc3339(secs: u64) -> String {
+	let (days, rem) = (secs / 86400, secs % 86400);
+	// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+	let z = days as i64 + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z.rem_euclid(146097);
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + i64::from(month <= 2);
+	format!(
+		"{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+		rem / 3600,
+		rem % 3600 / 60,
+		rem % 60
+	)
+}
+
+/// Current time as RFC3339 string, outputs using it are not reproducible unless
+/// `SOURCE_DATE_EPOCH` is set
+fn now() -> Result<String> {
+	static WARNED: AtomicBool = AtomicBool::new(false);
+	let secs = match std::env::var(SOURCE_DATE_EPOCH) {
+		Ok(epoch) => epoch
+			.parse::<u64>()
+			.map_err(|e| runtime_error!("invalid {SOURCE_DATE_EPOCH}: {e}"))?,
+		Err(_) => {
+			if !WARNED.swap(true, Ordering::Relaxed) {
+				warn!("bdk.now() makes outputs non-reproducible, set {SOURCE_DATE_EPOCH} to fix the time");
+			}
+			SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.map_err(|_| runtime_error!("system time is before unix epoch"))?
+				.as_secs()
+		}
+	};
+	Ok(rfc3339(secs))
+}
+
+#[builtin]
+pub fn builtin_now() -> Result<String> {
+	now()
+}
+
+#[builtin]
+pub fn builtin_version() -> String {
+	env!("CARGO_PKG_VERSION").to_owned()
+}
+
+#[derive(Typed)]
+pub struct BuildMeta {
+	version: String,
+	/// Set at build time using `BDK_GIT_SHA` env variable
+	#[typed(rename = "gitSha")]
+	git_sha: Option<String>,
+	now: String,
+}
+
+#[builtin]
+pub fn builtin_build_meta() -> Result<BuildMeta> {
+	Ok(BuildMeta {
+		version: env!("CARGO_PKG_VERSION").to_owned(),
+		git_sha: option_env!("BDK_GIT_SHA").map(str::to_owned),
+		now: now()?,
+	})
+}
+
+fn decode_ss58(address: &str) -> Result<(AccountId32, Ss58AddressFormat)> {
+	AccountId32::from_ss58check_with_version(address)
+		.map_err(|e| runtime_error!("invalid ss58 address {address:?}: {e}"))
+}
+
+/// Network prefix, with which the address is encoded
+#[builtin]
+pub fn builtin_ss58_prefix(address: String) -> Result<u16> {
+	Ok(decode_ss58(&address)?.1.prefix())
+}
+
+/// Encode the same account for a different network
+#[builtin]
+pub fn builtin_reencode_ss58(address: String, new_prefix: u16) -> Result<String> {
+	let (account, _) = decode_ss58(&address)?;
+	Ok(account.to_ss58check_with_version(Ss58AddressFormat::custom(new_prefix)))
+}
+
+/// Account address, either SS58 or ethereum-style hex
+fn is_address(s: &str) -> bool {
+	if let Some(hex) = s.strip_prefix("0x") {
+		return hex.len() == 40 && hex.bytes().all(|b| b.is_ascii_hexdigit());
+	}
+	AccountId32::from_ss58check(s).is_ok()
+}
+
+fn validate_amount(amount: &Val) -> Result<()> {
+	let valid = match amount {
+		Val::Num(n) => n.fract() == 0.0 && *n > 0.0 && *n <= (1u64 << 53) as f64,
+		Val::Str(s) => s.to_string().parse::<u128>().is_ok_and(|v| v > 0),
+		// Bigint
+		other => other
+			.manifest(JsonFormat::cli(0, true))?
+			.parse::<u128>()
+			.is_ok_and(|v| v > 0),
+	};
+	if !valid {
+		bail!("amount should be a positive integer fitting into u128, numbers above 2^53 should be passed as strings");
+	}
+	Ok(())
+}
+
+/// Build `balances.balances` genesis array, accounts are either addresses or SURIs
+#[builtin]
+pub fn builtin_genesis_balances(
+	accounts: Vec<String>,
+	amount: Val,
+	scheme: Option<SignatureSchema>,
+	format: Option<Ss58Format>,
+) -> Result<Vec<Vec<Val>>> {
+	validate_amount(&amount)?;
+	let scheme = scheme.unwrap_or(SignatureSchema::Sr25519);
+	let format = format.unwrap_or_default().0;
+
+	let mut seen = BTreeSet::new();
+	let mut out = Vec::new();
+	for account in accounts {
+		let address = if is_address(&account) {
+			account
+		} else {
+			address_seed(scheme, &account, format)
+				.map_err(|e| runtime_error!("account is neither address nor valid SURI: {e}"))?
+		};
+		if !seen.insert(address.clone()) {
+			debug!("duplicate genesis balance account: {address}");
+			continue;
+		}
+		out.push(vec![String::into_untyped(address)?, amount.clone()]);
+	}
+	Ok(out)
+}
+
+/// Directory inside of the container, under which staged certificates are mounted
+const CONTAINER_CERT_DIR: &str = "/etc/bdk/certs";
+
+fn validate_pem(name: &str, data: &str, key: bool) -> Result<()> {
+	let pem = pem::parse(data).map_err(|e| runtime_error!("{name}: invalid PEM: {e}"))?;
+	let tag = pem.tag();
+	let valid = if key {
+		tag.ends_with("PRIVATE KEY")
+	} else {
+		tag == "CERTIFICATE"
+	};
+	if !valid {
+		bail!("{name}: unexpected PEM block type: {tag}");
+	}
+	Ok(())
+}
+
+/// Read file relative to the output directory, i.e artifact generated by the previous run
+#[builtin(fields(
+	#[trace(skip)]
+	output_root: Option<PathBuf>,
+))]
+pub fn builtin_import_str_from_output(
+	this: &builtin_import_str_from_output,
+	path: String,
+) -> Result<String> {
+	let Some(output_root) = &this.output_root else {
+		bail!("output files can only be read with directory-backed generator, i.e docker_compose");
+	};
+	let file = output_path(output_root, &path)?;
+	std::fs::read_to_string(&file).map_err(|e| runtime_error!("read {file:?}: {e}"))
+}
+
+#[builtin(fields(
+	#[trace(skip)]
+	output_root: Option<PathBuf>,
+))]
+pub fn builtin_stage_cert(
+	this: &builtin_stage_cert,
+	name: String,
+	cert_pem: String,
+	key_pem: String,
+) -> Result<Val> {
+	#[derive(Typed)]
+	struct StagedCert {
+		cert: String,
+		key: String,
+		#[typed(rename = "hostCert")]
+		host_cert: String,
+		#[typed(rename = "hostKey")]
+		host_key: String,
+	}
+
+	let Some(output_root) = &this.output_root else {
+		bail!(
+			"certificates can only be staged with directory-backed generator, i.e docker_compose"
+		);
+	};
+	if name.is_empty()
+		|| !name
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+		|| name.starts_with('.')
+	{
+		bail!("invalid certificate name: {name:?}");
+	}
+	validate_pem("cert", &cert_pem, false)?;
+	validate_pem("key", &key_pem, true)?;
+
+	let dir = output_root.join("certs").join(&name);
+	create_dir_mode(&dir, 0o755).map_err(|e| runtime_error!("mkdir {dir:?}: {e}"))?;
+	let host_cert = dir.join("cert.pem");
+	write_mode(&host_cert, cert_pem.as_bytes(), 0o644)
+		.map_err(|e| runtime_error!("write {host_cert:?}: {e}"))?;
+	let host_key = dir.join("key.pem");
+	write_mode(&host_key, key_pem.as_bytes(), 0o600)
+		.map_err(|e| runtime_error!("write {host_key:?}: {e}"))?;
+
+	let utf8 = |p: PathBuf| {
+		p.into_os_string()
+			.into_string()
+			.map_err(|_| runtime_error!("output root is not utf-8"))
+	};
+	StagedCert::into_untyped(StagedCert {
+		cert: format!("{CONTAINER_CERT_DIR}/{name}/cert.pem"),
+		key: format!("{CONTAINER_CERT_DIR}/{name}/key.pem"),
+		host_cert: utf8(host_cert)?,
+		host_key: utf8(host_key)?,
+	})
+}
+
+#[builtin(fields(
+	#[trace(skip)]
+	secrets: Rc<dyn SecretStorage>,
+))]
+pub fn builtin_export_wallet(
+	this: &builtin_export_wallet,
+	name: String,
+	ty: String,
+	password: String,
+	scheme: Option<SignatureSchema>,
+	format: Option<Ss58Format>,
+) -> Result<String> {
+	let format = format.unwrap_or_default().0;
+	let scheme = scheme.unwrap_or(SignatureSchema::Sr25519);
+	this.secrets
+		.export_wallet_json(&name, &ty, scheme, &password, format)?
+		.ok_or_else(|| runtime_error!("wallet not found: {name} ({ty})"))
+}
+
+/// Last modification time of the node key in seconds since the unix epoch, `null` if missing
+#[builtin(fields(
+	#[trace(skip)]
+	secrets: Rc<dyn SecretStorage>,
+))]
+pub fn builtin_key_age(this: &builtin_key_age, node: String, ty: String) -> Result<Val> {
+	Ok(match this.secrets.key_age(&node, &ty)? {
+		Some(m) => Val::Num(m as f64),
+		None => Val::Null,
+	})
+}
+
+#[derive(Typed)]
+pub struct AliasName {
+	alias: String,
+}
+
+type WantedKeys = BTreeMap<String, Either![SignatureSchema, AliasName, ObjValue]>;
+
+#[builtin(fields(
+	#[trace(skip)]
+	secrets: Rc<dyn SecretStorage>,
+	#[trace(skip)]
+	metrics: Rc<Metrics>,
+	#[trace(skip)]
+	rng: KeyRng,
+	#[trace(skip)]
+	allow_secret_export: bool,
+))]
+pub fn builtin_ensure_keys(
+	this: &builtin_ensure_keys,
+	path: String,
+	wanted_keys: WantedKeys,
+	format: Option<Ss58Format>,
+	keystore_dir: Option<String>,
+	expose_secrets: Option<bool>,
+) -> Result<Val> {
+	let format = format.unwrap_or_default().0;
+	let expose_secrets = expose_secrets.unwrap_or(false);
+	if expose_secrets {
+		if !this.allow_secret_export {
+			bail!("exposeSecrets requires --allow-secret-export to be passed");
+		}
+		warn!("⚠️ secrets of {path} are exposed to the config, they may end up in the generated files");
+	}
+	ensure_node_keys(
+		&*this.secrets,
+		&this.metrics,
+		&this.rng,
+		&path,
+		&wanted_keys,
+		format,
+		keystore_dir.as_deref(),
+		expose_secrets,
+	)
+}
+
+/// Same as `ensureKeys`, but for many nodes at once, result is keyed by node path.
+///
+/// `keystore_dirs` is keyed by node path, nodes missing from it use the default keystore dir.
+#[builtin(fields(
+	#[trace(skip)]
+	secrets: Rc<dyn SecretStorage>,
+	#[trace(skip)]
+	metrics: Rc<Metrics>,
+	#[trace(skip)]
+	rng: KeyRng,
+	#[trace(skip)]
+	allow_secret_export: bool,
+))]
+pub fn builtin_ensure_keys_batch(
+	this: &builtin_ensure_keys_batch,
+	nodes: BTreeMap<String, WantedKeys>,
+	format: Option<Ss58Format>,
+	keystore_dirs: Option<BTreeMap<String, String>>,
+	expose_secrets: Option<bool>,
+) -> Result<ObjValue> {
+	let format = format.unwrap_or_default().0;
+	let keystore_dirs = keystore_dirs.unwrap_or_default();
+	if let Some(path) = keystore_dirs.keys().find(|path| !nodes.contains_key(*path)) {
+		bail!("keystore dir is set for unknown node: {path}");
+	}
+	let expose_secrets = expose_secrets.unwrap_or(false);
+	if expose_secrets {
+		if !this.allow_secret_export {
+			bail!("exposeSecrets requires --allow-secret-export to be passed");
+		}
+		warn!(
+			"⚠️ secrets of {} are exposed to the config, they may end up in the generated files",
+			nodes.keys().cloned().collect::<Vec<_>>().join(", ")
+		);
+	}
+	let mut out = ObjValueBuilder::new();
+	for (path, wanted_keys) in &nodes {
+		let keys = ensure_node_keys(
+			&*this.secrets,
+			&this.metrics,
+			&this.rng,
+			path,
+			wanted_keys,
+			format,
+			keystore_dirs.get(path).map(String::as_str),
+			expose_secrets,
+		)
+		.with_description(|| format!("node {path}"))?;
+		out.field(path.as_str()).value(keys);
+	}
+	Ok(out.build())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn ensure_node_keys(
+	secrets: &dyn SecretStorage,
+	metrics: &Metrics,
+	rng: &KeyRng,
+	path: &str,
+	wanted_keys: &WantedKeys,
+	format: Ss58AddressFormat,
+	keystore_dir: Option<&str>,
+	expose_secrets: bool,
+) -> Result<Val> {
+	#[derive(Default, Typed)]
+	struct ExposedSecrets {
+		keys: BTreeMap<String, String>,
+		wallets: BTreeMap<String, String>,
+	}
+	/// Only with dry-run secret backend
+	#[derive(Default, Typed)]
+	struct DryRun {
+		/// Keys which would be generated, their addresses are placeholders
+		placeholders: Vec<String>,
+		/// Keys which are already stored
+		present: Vec<String>,
+	}
+	#[derive(Default, Typed)]
+	struct Keys {
+		#[typed(rename = "nodeIdentity")]
+		node_identity: String,
+		#[typed(add)]
+		keys: BTreeMap<String, String>,
+		#[typed(add)]
+		wallets: BTreeMap<String, String>,
+		#[typed(rename = "localKeystoreDir")]
+		local_keystore_dir: String,
+		#[typed(rename = "localNodeFile")]
+		local_node_file: String,
+		/// SURIs, only with `exposeSecrets`
+		#[typed(rename = "INSECURE_exposedSecrets")]
+		exposed_secrets: Option<ExposedSecrets>,
+		#[typed(rename = "DRY_RUN")]
+		dry_run: Option<DryRun>,
+	}
+
+	let overridden;
+	let secrets: &dyn SecretStorage = if let Some(keystore_dir) = keystore_dir {
+		let keystore_dir = Path::new(keystore_dir);
+		if !keystore_dir.is_absolute() {
+			bail!("keystore dir override should be absolute: {keystore_dir:?}");
+		}
+		overridden = secrets.with_keystore_dir(path, keystore_dir)?;
+		&*overridden
+	} else {
+		secrets
+	};
+
+	let mut out = Keys::default();
+	let mut dry_run = DryRun::default();
+
+	if secrets.get_node_id(path)?.is_none() {
+		metrics.increment("node identities generated");
+		let pair = rng.node_keypair();
+		secrets.store_node_key(path, pair)?;
+		dry_run.placeholders.push("nodeIdentity".to_owned());
+	} else {
+		metrics.increment("node identities reused");
+		dry_run.present.push("nodeIdentity".to_owned());
+	}
+	out.node_identity = secrets.get_node_id(path)?.expect("just inserted");
+
+	for (name, scheme) in wanted_keys {
+		if let Some(ty) = name.strip_prefix('_') {
+			let Either3::A(scheme) = scheme else {
+				bail!("wallet scheme should be string-based: {name}");
+			};
+			if secrets.get_wallet(path, ty, *scheme, format)?.is_none() {
+				metrics.increment("wallets generated");
+				let suri = rng.mnemonic(24);
+				secrets.store_wallet(path, ty, *scheme, &suri, format)?;
+				dry_run.placeholders.push(name.clone());
+			} else {
+				metrics.increment("wallets reused");
+				dry_run.present.push(name.clone());
+			}
+			out.wallets.insert(
+				name[1..].to_string(),
+				secrets
+					.get_wallet(path, ty, *scheme, format)?
+					.expect("just inserted"),
+			);
+		} else if name.ends_with("Keys") && name.len() > 4
+			|| name.ends_with("Key") && name.len() > 3
+		{
+			// Key set, i.e `sessionKeys`, pass.
+		} else {
+			if matches!(scheme, Either3::B(_)) {
+				continue;
+			};
+			let Either3::A(scheme) = scheme else {
+				bail!("secret scheme should be string-based: {name}");
+			};
+			if secrets.get_typed(path, name, *scheme, format)?.is_none() {
+				metrics.increment("keys generated");
+				let suri = rng.mnemonic(12);
+				secrets.store_typed_key(path, name, *scheme, &suri, format)?;
+				for (alias_name, alias) in wanted_keys {
+					let Either3::B(alias) = alias else {
+						continue;
+					};
+					if &alias.alias != name {
+						continue;
+					};
+					secrets.store_typed_key(path, alias_name, *scheme, &suri, format)?;
+				}
+				dry_run.placeholders.push(name.clone());
+			} else {
+				metrics.increment("keys reused");
+				dry_run.present.push(name.clone());
+			}
+			let stored = secrets
+				.get_typed(path, name, *scheme, format)?
+				.expect("just inserted");
+			out.keys.insert(name.clone(), stored.clone());
+			for (alias_name, alias) in wanted_keys {
+				let Either3::B(alias) = alias else {
+					continue;
+				};
+				if &alias.alias != name {
+					continue;
+				};
+				out.keys.insert(alias_name.clone(), stored.clone());
+			}
+		}
+	}
+	if expose_secrets {
+		let mut exposed = ExposedSecrets::default();
+		for key in secrets.list_keys(path)? {
+			if out.keys.contains_key(&key.ty) {
+				exposed.keys.insert(key.ty, key.suri);
+			}
+		}
+		for wallet in secrets.list_wallets()? {
+			if wallet.name == path && out.wallets.contains_key(&wallet.ty) {
+				exposed.wallets.insert(wallet.ty, wallet.suri);
+			}
+		}
+		out.exposed_secrets = Some(exposed);
+	}
+	if secrets.is_dry_run() {
+		if !dry_run.placeholders.is_empty() {
+			warn!(
+				"🧪 dry run: {path} keys {} would be generated, returned addresses are PLACEHOLDERS",
+				dry_run.placeholders.join(", ")
+			);
+		}
+		out.dry_run = Some(dry_run);
+	}
+	// TODO: Remove the requirement
+	out.local_keystore_dir = secrets
+		.local_keystore_dir(path)?
 		.ok_or_else(|| runtime_error!("local keystore dir required"))?;
 	out.local_node_file = secrets
-		.local_node_file(&path)?
+		.local_node_file(path)?
 		.ok_or_else(|| runtime_error!("local node file required"))?;
 	Keys::into_untyped(out)
 }
@@ -266,27 +2328,153 @@ pub fn builtin_ensure_keys(
 #[derive(Trace)]
 pub struct BdkContextInitializer {
 	#[trace(skip)]
-	pub spec_builder: Rc<dyn SpecBuilder>,
+	pub spec_builders: Rc<SpecBuilders>,
+	#[trace(skip)]
+	pub spec_cache: Rc<SpecCache>,
 	#[trace(skip)]
 	pub secrets: Rc<dyn SecretStorage>,
+	#[trace(skip)]
+	pub metrics: Rc<Metrics>,
+	/// Output directory of the directory-backed generator, if any is in use
+	#[trace(skip)]
+	pub output_root: Option<PathBuf>,
+	/// Platform override for `bdk.hostInfo`, in form of `os/arch`
+	#[trace(skip)]
+	pub platform: Option<String>,
+	/// Random source for generated secrets
+	#[trace(skip)]
+	pub rng: KeyRng,
+	/// Set by `--allow-secret-export`, permits `exposeSecrets` of `bdk.ensureKeys`
+	#[trace(skip)]
+	pub allow_secret_export: bool,
+	/// Set by `--node-filter`, glob of node names to generate
+	#[trace(skip)]
+	pub node_filter: Option<String>,
 }
 
 impl ContextInitializer for BdkContextInitializer {
 	fn populate(&self, _for_file: Source, builder: &mut ContextBuilder) {
 		let mut bdk = ObjValueBuilder::new();
 		bdk.method("mixer", builtin_mixer::INST);
+		bdk.method("include", builtin_include::INST);
+		bdk.method("mergePatch", builtin_merge_patch::INST);
+		bdk.method("patchGenesis", builtin_patch_genesis::INST);
+		bdk.method("chainSpecDiff", builtin_chain_spec_diff::INST);
 		bdk.method("toRelative", builtin_to_relative::INST);
+		bdk.method("hexEncode", builtin_hex_encode::INST);
+		bdk.method("hexDecode", builtin_hex_decode::INST);
+		bdk.method("base58Encode", builtin_base58_encode::INST);
+		bdk.method("base58Decode", builtin_base58_decode::INST);
+		bdk.method("template", builtin_template::INST);
+		bdk.method("configFingerprint", builtin_config_fingerprint::INST);
 		bdk.method("dockerMounts", builtin_docker_mounts::INST);
+		bdk.method("pinImage", builtin_pin_image::INST);
+		bdk.method("discoverPeers", builtin_discover_peers::INST);
+		bdk.method("waitForRpc", builtin_wait_for_rpc::INST);
+		bdk.method("prometheusTargets", builtin_prometheus_targets::INST);
+		bdk.method("assertNode", builtin_assert_node::INST);
+		bdk.method("nodeResources", builtin_node_resources::INST);
+		bdk.method("nodeEnv", builtin_node_env::INST);
+		bdk.method("sortNodes", builtin_sort_nodes::INST);
+		bdk.method("assertUnique", builtin_assert_unique::INST);
+		bdk.method("authoritySet", builtin_authority_set::INST);
+		bdk.method("envFile", builtin_env_file::INST);
+		bdk.method("nodeLogConfig", builtin_node_log_config::INST);
+		bdk.method("ss58Prefix", builtin_ss58_prefix::INST);
+		bdk.method("forkState", builtin_fork_state::INST);
+		bdk.method("reencodeSs58", builtin_reencode_ss58::INST);
+		bdk.method("reverseProxyConfig", builtin_reverse_proxy_config::INST);
+		bdk.method("now", builtin_now::INST);
+		bdk.method("version", builtin_version::INST);
+		bdk.method("buildMeta", builtin_build_meta::INST);
+		bdk.method("validateNetwork", builtin_validate_network::INST);
+		bdk.method("portAllocator", builtin_port_allocator::INST);
+		bdk.method("genesisBalances", builtin_genesis_balances::INST);
 		bdk.method(
 			"processSpec",
 			builtin_process_spec {
-				builder: self.spec_builder.clone(),
+				builders: self.spec_builders.clone(),
+				metrics: self.metrics.clone(),
+				cache: self.spec_cache.clone(),
+			},
+		);
+		bdk.method(
+			"exportGenesisState",
+			builtin_export_genesis_state {
+				builders: self.spec_builders.clone(),
+			},
+		);
+		bdk.method(
+			"genesisHash",
+			builtin_genesis_hash {
+				builders: self.spec_builders.clone(),
+			},
+		);
+		bdk.method(
+			"exportGenesisWasm",
+			builtin_export_genesis_wasm {
+				builders: self.spec_builders.clone(),
 			},
 		);
 		bdk.method(
 			"ensureKeys",
 			builtin_ensure_keys {
 				secrets: self.secrets.clone(),
+				metrics: self.metrics.clone(),
+				rng: self.rng.clone(),
+				allow_secret_export: self.allow_secret_export,
+			},
+		);
+		bdk.method(
+			"ensureKeysBatch",
+			builtin_ensure_keys_batch {
+				secrets: self.secrets.clone(),
+				metrics: self.metrics.clone(),
+				rng: self.rng.clone(),
+				allow_secret_export: self.allow_secret_export,
+			},
+		);
+
+		bdk.method(
+			"randomHex",
+			builtin_random_hex {
+				rng: self.rng.clone(),
+			},
+		);
+		bdk.method(
+			"keyAge",
+			builtin_key_age {
+				secrets: self.secrets.clone(),
+			},
+		);
+		bdk.method(
+			"hostInfo",
+			builtin_host_info {
+				platform: self.platform.clone(),
+			},
+		);
+		bdk.method(
+			"nodeSelected",
+			builtin_node_selected {
+				node_filter: self.node_filter.clone(),
+			},
+		);
+		bdk.method(
+			"exportWallet",
+			builtin_export_wallet {
+				secrets: self.secrets.clone(),
+			},
+		);
+		bdk.method(
+			"importStrFromOutput",
+			builtin_import_str_from_output {
+				output_root: self.output_root.clone(),
+			},
+		);
+		bdk.method(
+			"stageCert",
+			builtin_stage_cert {
+				output_root: self.output_root.clone(),
 			},
 		);
 
@@ -297,3 +2485,113 @@ impl ContextInitializer for BdkContextInitializer {
 		self
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use chainql_core::address::SignatureSchema;
+	use jrsonnet_evaluator::{manifest::JsonFormat, typed::Either3, Val};
+	use serde_json::{json, Value};
+	use sp_core::crypto::Ss58AddressFormat;
+
+	use super::{ensure_node_keys, merge_patch, WantedKeys};
+	use crate::keystore::MemoryNodeKeys;
+	use crate::metrics::Metrics;
+	use crate::rng::KeyRng;
+
+	fn patched(target: Value, patch: Value) -> Value {
+		let target: Val = serde_json::from_value(target).expect("target");
+		let patch: Val = serde_json::from_value(patch).expect("patch");
+		let out = merge_patch(target, patch).expect("merge patch");
+		let out = out.manifest(JsonFormat::cli(0, true)).expect("manifest");
+		serde_json::from_str(&out).expect("json")
+	}
+
+	#[test]
+	fn nested_delete() {
+		assert_eq!(
+			patched(
+				json!({"a": {"b": {"c": 1, "d": 2}, "e": 3}, "f": 4}),
+				json!({"a": {"b": {"c": null}}, "f": null}),
+			),
+			json!({"a": {"b": {"d": 2}, "e": 3}}),
+		);
+	}
+
+	#[test]
+	fn delete_missing_is_noop() {
+		assert_eq!(
+			patched(json!({"a": 1}), json!({"b": null, "c": {"d": null}})),
+			json!({"a": 1, "c": {}}),
+		);
+	}
+
+	#[test]
+	fn object_replaced_with_scalar() {
+		assert_eq!(
+			patched(json!({"a": {"b": 1}}), json!({"a": "x"})),
+			json!({"a": "x"}),
+		);
+	}
+
+	#[test]
+	fn scalar_replaced_with_object() {
+		assert_eq!(
+			patched(json!({"a": 1}), json!({"a": {"b": 2, "c": null}})),
+			json!({"a": {"b": 2}}),
+		);
+	}
+
+	#[test]
+	fn arrays_are_replaced() {
+		assert_eq!(
+			patched(json!({"a": [1, 2]}), json!({"a": [{"b": null}]})),
+			json!({"a": [{"b": null}]}),
+		);
+	}
+
+	#[test]
+	fn non_object_patch_replaces_target() {
+		assert_eq!(patched(json!({"a": 1}), json!([1])), json!([1]));
+	}
+
+	#[test]
+	fn deterministic_keys() {
+		let wanted: WantedKeys = [
+			("_stash", SignatureSchema::Sr25519),
+			("aura", SignatureSchema::Sr25519),
+			("gran", SignatureSchema::Ed25519),
+		]
+		.into_iter()
+		.map(|(name, schema)| (name.to_owned(), Either3::A(schema)))
+		.collect();
+		let keys = ensure_node_keys(
+			&MemoryNodeKeys::default(),
+			&Metrics::default(),
+			&KeyRng::insecure_deterministic(0xbdc),
+			"alice",
+			&wanted,
+			Ss58AddressFormat::custom(42),
+			None,
+			false,
+		)
+		.expect("ensure keys");
+		let keys: Value =
+			serde_json::from_str(&keys.manifest(JsonFormat::cli(0, true)).expect("manifest"))
+				.expect("json");
+		assert_eq!(
+			keys["nodeIdentity"],
+			"12D3KooWEG8FkK89TLkzzdKTDT9XBrp56gVcYHc9eGxsc7XxGct9"
+		);
+		assert_eq!(
+			keys["wallets"],
+			json!({"stash": "5CD2CJic9vHdBe4BPe5ntFBzbBJ8722sffg7smS1TwAEwR6f"}),
+		);
+		assert_eq!(
+			keys["keys"],
+			json!({
+				"aura": "5EKstonbQqjW4NgsDZh3dSH3EgLFV5C1gfppLiSArfjjdmEB",
+				"gran": "5DaTZ2wPQhiHbt8nAFitqRt95YcYPx3b46QiSaTthe7aH5di",
+			}),
+		);
+	}
+}
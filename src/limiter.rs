@@ -0,0 +1,33 @@
+use std::sync::{Condvar, Mutex};
+
+/// Counting semaphore, limiting the number of simultaneously running containers
+pub struct ContainerLimiter {
+	available: Mutex<usize>,
+	released: Condvar,
+}
+impl ContainerLimiter {
+	pub fn new(permits: usize) -> Self {
+		Self {
+			available: Mutex::new(permits.max(1)),
+			released: Condvar::new(),
+		}
+	}
+
+	/// Block until a container slot is available
+	pub fn acquire(&self) -> Permit<'_> {
+		let mut available = self.available.lock().expect("not poisoned");
+		while *available == 0 {
+			available = self.released.wait(available).expect("not poisoned");
+		}
+		*available -= 1;
+		Permit(self)
+	}
+}
+
+pub struct Permit<'l>(&'l ContainerLimiter);
+impl Drop for Permit<'_> {
+	fn drop(&mut self) {
+		*self.0.available.lock().expect("not poisoned") += 1;
+		self.0.released.notify_one();
+	}
+}
@@ -1,55 +1,139 @@
 use std::{
+	collections::{BTreeMap, BTreeSet},
 	env,
-	fs::{create_dir_all, read_to_string, write},
-	path::{Component, PathBuf},
+	fs::{create_dir_all, read_to_string, set_permissions, write, File, Permissions},
+	io::{ErrorKind, Write},
+	num::NonZeroUsize,
+	os::unix::fs::PermissionsExt,
+	path::{Component, Path, PathBuf},
 	str::FromStr,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+	thread,
 };
 
 use clap::{Parser, Subcommand};
+use failure::{FailureClass, EXIT_CODES_HELP};
+use flate2::{write::GzEncoder, Compression};
+use fs_utils::write_mode;
+use git_check::GitCheck;
 use jrsonnet_cli::{MiscOpts, TlaOpts, TraceOpts};
 use jrsonnet_evaluator::{
 	bail,
-	function::{CallLocation, TlaArg},
+	function::{CallLocation, FuncVal, TlaArg},
 	gc::GcHashMap,
 	manifest::JsonFormat,
-	parser::{Source, SourcePath, SourceVirtual},
+	parser::{ExprLocation, Source, SourcePath, SourceVirtual},
 	runtime_error,
 	trace::PathResolver,
 	typed::{NativeFn, Typed},
 	IStr, ObjValue, ObjValueBuilder, Pending, Result, ResultExt, State, Val,
 };
+use keys::KeysCommand;
 use keystore::SecretBackend;
-use spec_builder::SpecBackend;
+use limiter::ContainerLimiter;
+use metrics::Metrics;
+use options::Options;
+use otel::Otel;
+use output_manifest::OutputManifest;
+use rng::KeyRng;
+use spec_builder::{
+	LimitedSpecBuilder, NamedSpecBackend, SpecBackend, SpecBuilder, SpecBuilders, SpecCache,
+};
 use std::rc::Rc;
-use tokio::runtime::Handle;
-use tracing::{debug, error, info};
-use tracing_subscriber::EnvFilter;
+use tokio::{
+	runtime::Handle,
+	signal::unix::{signal, SignalKind},
+};
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+use warnings::WarnCollector;
 
 use crate::docker::EMPTY_IMAGE;
 
 // mod asset;
 mod docker;
+mod doctor;
+mod failure;
 mod fs_utils;
+mod git_check;
+mod keys;
 mod keystore;
+mod keystore_dry_run;
+mod keystore_retry;
 mod library;
+mod limiter;
+mod metrics;
+mod offline;
+mod options;
+mod otel;
+mod output_manifest;
+mod rng;
+mod rpc;
 mod spec_builder;
+mod val_utils;
+mod wallet_json;
+mod warnings;
+
+const BASE_MODULE: &str = "lib:baedeker-library/inputs/base.libsonnet";
+/// Versions of baedeker-library, which this binary is able to work with
+const SUPPORTED_LIBRARY_VERSION: &str = ">=0.1.0, <0.2.0";
+
+/// Check version handshake (`__bdkLibraryVersion` field), provided by base.libsonnet
+fn check_library_version(config: &Val) -> Result<()> {
+	let Some(config) = config.as_obj() else {
+		bail!("config should evaluate to object");
+	};
+	let Some(version) = config.get("__bdkLibraryVersion".into())? else {
+		// Not a warning until baedeker-library releases with the version field, otherwise every
+		// --fail-on-warn run would fail
+		debug!("baedeker-library doesn't declare its version, supported versions: {SUPPORTED_LIBRARY_VERSION}");
+		return Ok(());
+	};
+	let version = String::from_untyped(version).description("__bdkLibraryVersion")?;
+	let parsed = semver::Version::parse(&version)
+		.map_err(|e| runtime_error!("invalid baedeker-library version {version:?}: {e}"))?;
+	let req = semver::VersionReq::parse(SUPPORTED_LIBRARY_VERSION).expect("valid requirement");
+	if !req.matches(&parsed) {
+		bail!("baedeker-library {SUPPORTED_LIBRARY_VERSION} required, found {version}, update baedeker-library (or baedeker itself)");
+	}
+	Ok(())
+}
 
 #[derive(Clone)]
 enum Generator {
-	DockerCompose(PathBuf),
-	DockerComposeDiscover(PathBuf),
-	Debug,
-	AddressBook,
+	DockerCompose(DockerCompose),
+	DockerComposeDiscover(PathBuf, Vec<String>),
+	Debug(Option<String>),
+	AddressBook(AddressBook),
+	Stdout(String),
 }
 impl Generator {
+	/// Forms accepted by [`FromStr`], printed by `--list-generators`
+	const FORMS: &'static [&'static str] = &[
+		"docker_compose=<dir>[,project=<name>][,compress_specs=gzip][,validate_refs=false]",
+		"docker_compose_discover=<file>[,format=compose+env+json]",
+		"addressbook",
+		"addressbook=<file>[,merge=true]",
+		"debug",
+		"debug=<dotted.path>",
+		"stdout=<attribute>",
+	];
+
 	fn value(self) -> Box<dyn GeneratorT> {
 		match self {
-			Generator::DockerCompose(output_dir) => Box::new(DockerCompose { output_dir }),
-			Generator::DockerComposeDiscover(output_file) => {
-				Box::new(DockerComposeDiscover { output_file })
+			Generator::DockerCompose(compose) => Box::new(compose),
+			Generator::DockerComposeDiscover(output_file, formats) => {
+				Box::new(DockerComposeDiscover {
+					output_file,
+					formats,
+				})
 			}
-			Generator::Debug => Box::new(DebugGen),
-			Generator::AddressBook => Box::new(AddressBook),
+			Generator::Debug(path) => Box::new(DebugGen { path }),
+			Generator::AddressBook(address_book) => Box::new(address_book),
+			Generator::Stdout(attribute) => Box::new(StdoutGen { attribute }),
 		}
 	}
 }
@@ -62,15 +146,206 @@ trait GeneratorT {
 	fn output_attribute(&self) -> String;
 	/// Supply config data to jsonnet
 	fn config(&self) -> Result<Option<Val>>;
-	/// Process output attribute data
-	fn process(&self, data: Val) -> Result<()>;
+	/// Dotted paths, which should be present in the output attribute data before it is processed
+	fn required_config(&self) -> Vec<String> {
+		vec![]
+	}
+	/// Process output attribute data, every written file should be recorded to `written`
+	fn process(&self, data: Val, written: &OutputManifest) -> Result<()>;
+	/// Directory, in which this generator writes its files, if any
+	fn output_root(&self) -> Option<PathBuf> {
+		None
+	}
+	/// Whether this generator emits per-node outputs, which should be limited by `--node-filter`.
+	/// Generators, which describe the whole network (address book, discovery) should see all nodes.
+	fn node_filtered(&self) -> bool {
+		false
+	}
 
 	// /// Should not be used, standard library should be same regardless of which generators are in use.
 	// fn extend_stdlib(&self, std: &mut ObjValueBuilder) -> Result<()>;
 }
 
+/// Set by `--strict-output-escape`, output paths are additionally checked with symlinks resolved
+static STRICT_OUTPUT_ESCAPE: AtomicBool = AtomicBool::new(false);
+
+/// Resolve path of the generator output entry, rejecting entries escaping the output directory
+pub fn output_path(dir: &Path, name: &str) -> Result<PathBuf> {
+	let path = dir.join(name);
+	if path.components().any(|c| c == Component::ParentDir) {
+		bail!("generator output should not use parent dir");
+	}
+	if !path.starts_with(dir) {
+		bail!("generator output should not escape the output directory: tried to write to {path:?}, which is outside of {dir:?}");
+	}
+	if STRICT_OUTPUT_ESCAPE.load(Ordering::Relaxed) {
+		check_canonical_escape(dir, &path)?;
+	}
+	Ok(path)
+}
+
+/// Reject paths, which resolve outside of the output directory through symlinks.
+/// Components which don't exist yet can't be symlinks, so only the deepest existing ancestor
+/// is resolved.
+fn check_canonical_escape(dir: &Path, path: &Path) -> Result<()> {
+	let root = dir
+		.canonicalize()
+		.map_err(|e| failure::io_error(format!("canonicalize output directory {dir:?}: {e}")))?;
+	let existing = path
+		.ancestors()
+		.find(|p| p.symlink_metadata().is_ok())
+		.expect("output directory exists");
+	let resolved = existing
+		.canonicalize()
+		.map_err(|e| failure::io_error(format!("canonicalize {existing:?}: {e}")))?;
+	if !resolved.starts_with(&root) {
+		bail!("generator output should not escape the output directory: {path:?} resolves to {resolved:?} through symlinks, which is outside of {root:?}");
+	}
+	Ok(())
+}
+
+fn record_written(written: &OutputManifest, path: &Path, previous: Option<&[u8]>) -> Result<()> {
+	written
+		.record(path, previous)
+		.map_err(|e| failure::io_error(format!("failed to hash written file {path:?}: {e}")))
+}
+
+/// Check that the generator output directory can be written to, before spending time on the evaluation
+fn probe_output_dir(dir: &Path) -> Result<()> {
+	create_dir_all(dir).map_err(|e| {
+		failure::io_error(format!("failed to create output directory {dir:?}: {e}"))
+	})?;
+	tempfile::Builder::new()
+		.prefix(".bdk-probe")
+		.tempfile_in(dir)
+		.map_err(|e| failure::io_error(format!("output directory {dir:?} is not writable: {e}")))?;
+	Ok(())
+}
+
+/// Parse file contents for structured reconciliation, JSON files are parsed as JSON, everything else as YAML
+fn parse_structured(data: &str, is_json: bool) -> Result<Val> {
+	if is_json {
+		serde_json::from_str(data).map_err(|e| runtime_error!("json: {e}"))
+	} else {
+		serde_yaml::from_str(data).map_err(|e| runtime_error!("yaml: {e}"))
+	}
+}
+
+fn manifest_structured(data: Val, is_json: bool) -> Result<String> {
+	let json = data.manifest(JsonFormat::cli(2, true))?;
+	if is_json {
+		return Ok(json);
+	}
+	let value: serde_yaml::Value =
+		serde_json::from_str(&json).map_err(|e| runtime_error!("json: {e}"))?;
+	serde_yaml::to_string(&value).map_err(|e| runtime_error!("yaml: {e}"))
+}
+
+/// Suffix of the raw chain spec files, which are compressed with `compress_specs=gzip`
+const RAW_SPEC_SUFFIX: &str = ".raw.json";
+
+#[derive(Clone)]
 struct DockerCompose {
 	output_dir: PathBuf,
+	/// Compose project name, defaults to the output directory name in compose itself
+	project: Option<String>,
+	/// Write raw specs gzip-compressed, as `<name>.raw.json.gz`
+	compress_specs: bool,
+	/// Check that bind mounts of the emitted compose files only reference emitted files
+	validate_refs: bool,
+}
+impl DockerCompose {
+	/// Bind mount sources of the compose file services, resolved relative to the compose file
+	fn bind_sources(compose: &Path, data: &str) -> Result<Vec<PathBuf>> {
+		let value: serde_yaml::Value = serde_yaml::from_str(data)
+			.map_err(|e| runtime_error!("parsing emitted {compose:?}: {e}"))?;
+		let base = compose.parent().expect("file is inside of the output dir");
+		let mut out = vec![];
+		let Some(services) = value.get("services").and_then(|s| s.as_mapping()) else {
+			return Ok(out);
+		};
+		for service in services.values() {
+			let Some(volumes) = service.get("volumes").and_then(|v| v.as_sequence()) else {
+				continue;
+			};
+			for volume in volumes {
+				let source = match volume {
+					// Named volumes have no path-like source
+					serde_yaml::Value::String(short) => short
+						.split(':')
+						.next()
+						.filter(|s| s.starts_with(['.', '/'])),
+					long if long.get("type").and_then(|t| t.as_str()) == Some("bind") => {
+						long.get("source").and_then(|s| s.as_str())
+					}
+					_ => None,
+				};
+				if let Some(source) = source {
+					out.push(normalize_path(&base.join(source)));
+				}
+			}
+		}
+		Ok(out)
+	}
+
+	fn validate_refs(&self, emitted: &BTreeSet<PathBuf>) -> Result<()> {
+		for compose in emitted
+			.iter()
+			.filter(|p| p.extension().is_some_and(|e| e == "yml" || e == "yaml"))
+		{
+			let data = read_to_string(compose)
+				.map_err(|e| failure::io_error(format!("read {compose:?}: {e}")))?;
+			for source in Self::bind_sources(compose, &data)? {
+				if !source.starts_with(&self.output_dir) {
+					continue;
+				}
+				// Directory mounts are fine, as long as something was emitted into them
+				if emitted.iter().any(|p| p.starts_with(&source)) {
+					continue;
+				}
+				if !source.exists() {
+					bail!("{compose:?} mounts {source:?}, which was not emitted, use validate_refs=false if it is provided externally");
+				}
+				warn!("{compose:?} mounts {source:?}, which was not emitted during this run, it may be stale");
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Octal file mode from the `mode_<name>` sibling attribute of the output entry, if set
+fn output_file_mode(output: &ObjValue, name: &IStr) -> Result<Option<u32>> {
+	let Some(mode) = output.get(format!("mode_{name}").into())? else {
+		return Ok(None);
+	};
+	let mode = String::from_untyped(mode).with_description(|| format!("mode_{name}"))?;
+	match u32::from_str_radix(&mode, 8) {
+		Ok(mode) if mode <= 0o7777 => Ok(Some(mode)),
+		_ => bail!("mode_{name}: expected octal file mode (i.e \"0755\"), got {mode:?}"),
+	}
+}
+
+fn apply_mode(path: &Path, mode: Option<u32>) -> Result<()> {
+	let Some(mode) = mode else {
+		return Ok(());
+	};
+	set_permissions(path, Permissions::from_mode(mode))
+		.map_err(|e| failure::io_error(format!("chmod {path:?}: {e}")))
+}
+
+/// Lexically resolve `.` and `..` components
+fn normalize_path(path: &Path) -> PathBuf {
+	let mut out = PathBuf::new();
+	for component in path.components() {
+		match component {
+			Component::CurDir => {}
+			Component::ParentDir => {
+				out.pop();
+			}
+			other => out.push(other),
+		}
+	}
+	out
 }
 impl GeneratorT for DockerCompose {
 	fn library_modules(&self) -> Vec<String> {
@@ -88,9 +363,14 @@ impl GeneratorT for DockerCompose {
 			empty_image: String,
 			#[typed(rename = "outputRoot")]
 			output_root: String,
+			project: Option<String>,
+			#[typed(rename = "compressSpecs")]
+			compress_specs: bool,
 		}
 		Config::into_untyped(Config {
 			empty_image: EMPTY_IMAGE.to_string(),
+			project: self.project.clone(),
+			compress_specs: self.compress_specs,
 			output_root: self
 				.output_dir
 				.to_str()
@@ -100,40 +380,110 @@ impl GeneratorT for DockerCompose {
 		.map(Some)
 	}
 
-	fn process(&self, data: Val) -> Result<()> {
+	fn required_config(&self) -> Vec<String> {
+		vec![
+			"_config.emptyImage".to_owned(),
+			"_config.outputRoot".to_owned(),
+		]
+	}
+
+	fn node_filtered(&self) -> bool {
+		true
+	}
+
+	fn process(&self, data: Val, written: &OutputManifest) -> Result<()> {
 		let output = ObjValue::from_untyped(data)?;
 		let dir = &self.output_dir;
+		let mut emitted = BTreeSet::new();
 
 		for (name, value) in output.iter(false) {
-			let mut path = dir.clone();
-			path.push(name.as_str());
-			if path.components().any(|c| c == Component::ParentDir) {
-				bail!("generator output should not use parent dir");
-			}
-			if !path.starts_with(dir) {
-				bail!("generator output should not escape the output directory: tried to write to {path:?}, which is outside of {dir:?}");
-			}
+			let path = output_path(dir, name.as_str())?;
+			emitted.insert(normalize_path(&path));
 			let value = IStr::from_untyped(value?)?;
-			create_dir_all(path.parent().expect("not root")).expect("mkdirp");
-			if path.exists() && output.has_field_ex(format!("reconcile_{name}").into(), true) {
-				let data = read_to_string(&path).expect("read");
+			let mode = output_file_mode(&output, &name)?;
+			let parent = path
+				.parent()
+				.expect("joined to the output dir, can't be root");
+			create_dir_all(parent)
+				.map_err(|e| failure::io_error(format!("mkdir {parent:?}: {e}")))?;
+			let structured_reconciler: IStr = format!("reconcile_structured_{name}").into();
+			if path.exists() && output.has_field_ex(structured_reconciler.clone(), true) {
+				let data = read_to_string(&path)
+					.map_err(|e| failure::io_error(format!("read {path:?}: {e}")))?;
+				let reconciler = output
+					.get(structured_reconciler)?
+					.expect("reconciler exists");
+				let reconciler = <NativeFn<((Val, Val), Val)>>::from_untyped(reconciler)
+					.description("structured reconciler type")?;
+				let is_json = path.extension().is_some_and(|e| e == "json");
+				let old = parse_structured(&data, is_json)
+					.with_description(|| format!("parsing existing {path:?}"))?;
+				let new = parse_structured(&value, is_json)
+					.with_description(|| format!("parsing generated {name}"))?;
+				let reconciled = reconciler(old, new).description("structured reconciler call")?;
+				let reconciled = manifest_structured(reconciled, is_json)?;
+				write(&path, reconciled.as_bytes())
+					.map_err(|e| failure::io_error(format!("write {path:?}: {e}")))?;
+				apply_mode(&path, mode)?;
+				record_written(written, &path, Some(data.as_bytes()))?;
+			} else if path.exists() && output.has_field_ex(format!("reconcile_{name}").into(), true)
+			{
+				let data = read_to_string(&path)
+					.map_err(|e| failure::io_error(format!("read {path:?}: {e}")))?;
 				let reconciler = output
 					.get(format!("reconcile_{name}").into())?
 					.expect("reconciler exists");
 				let reconciler = <NativeFn<((String, IStr), IStr)>>::from_untyped(reconciler)
 					.description("reconciler type")?;
+				let previous = data.clone();
 				let reconciled = reconciler(data, value).description("reconciler call")?;
-				write(&path, reconciled.as_bytes()).expect("write");
+				write(&path, reconciled.as_bytes())
+					.map_err(|e| failure::io_error(format!("write {path:?}: {e}")))?;
+				apply_mode(&path, mode)?;
+				record_written(written, &path, Some(previous.as_bytes()))?;
+			} else if self.compress_specs && name.as_str().ends_with(RAW_SPEC_SUFFIX) {
+				let mut compressed = path.into_os_string();
+				compressed.push(".gz");
+				let compressed = PathBuf::from(compressed);
+				let file = File::create(&compressed)
+					.map_err(|e| failure::io_error(format!("create {compressed:?}: {e}")))?;
+				let mut encoder = GzEncoder::new(file, Compression::default());
+				encoder
+					.write_all(value.as_bytes())
+					.map_err(|e| failure::io_error(format!("compress {name}: {e}")))?;
+				encoder
+					.finish()
+					.map_err(|e| failure::io_error(format!("compress {name}: {e}")))?;
+				apply_mode(&compressed, mode)?;
+				record_written(written, &compressed, None)?;
+				emitted.insert(normalize_path(&compressed));
 			} else {
-				write(&path, value.as_bytes()).expect("write");
+				match mode {
+					Some(mode) => write_mode(&path, value.as_bytes(), mode),
+					None => write(&path, value.as_bytes()),
+				}
+				.map_err(|e| failure::io_error(format!("write {path:?}: {e}")))?;
+				record_written(written, &path, None)?;
 			}
 		}
+		if self.validate_refs {
+			self.validate_refs(&emitted)?;
+		}
 		Ok(())
 	}
+
+	fn output_root(&self) -> Option<PathBuf> {
+		Some(self.output_dir.clone())
+	}
 }
 
+/// Representations of discovery data, which may be requested from the library
+const DISCOVER_FORMATS: &[&str] = &["compose", "env", "json"];
+
 struct DockerComposeDiscover {
 	output_file: PathBuf,
+	/// If set, library emits an object with requested formats, each written to the file with format suffix
+	formats: Vec<String>,
 }
 impl GeneratorT for DockerComposeDiscover {
 	fn library_modules(&self) -> Vec<String> {
@@ -145,23 +495,78 @@ impl GeneratorT for DockerComposeDiscover {
 	}
 
 	fn config(&self) -> Result<Option<Val>> {
-		Ok(None)
+		if self.formats.is_empty() {
+			return Ok(None);
+		}
+		#[derive(Typed)]
+		struct Config {
+			formats: Vec<String>,
+		}
+		Config::into_untyped(Config {
+			formats: self.formats.clone(),
+		})
+		.map(Some)
 	}
 
-	fn process(&self, data: Val) -> Result<()> {
-		let output = String::from_untyped(data)?;
+	fn process(&self, data: Val, written: &OutputManifest) -> Result<()> {
 		let parent = self
 			.output_file
 			.parent()
 			.ok_or_else(|| runtime_error!("no parent"))?;
-		create_dir_all(parent).map_err(|e| runtime_error!("mkdir failed: {e}"))?;
-		write(&self.output_file, output.as_bytes())
-			.map_err(|e| runtime_error!("write failed: {e}"))?;
+		create_dir_all(parent).map_err(|e| failure::io_error(format!("mkdir failed: {e}")))?;
+
+		if self.formats.is_empty() {
+			let output = String::from_untyped(data)?;
+			write(&self.output_file, output.as_bytes())
+				.map_err(|e| failure::io_error(format!("write failed: {e}")))?;
+			record_written(written, &self.output_file, None)?;
+			return Ok(());
+		}
+
+		let output = <BTreeMap<String, String>>::from_untyped(data)?;
+		for format in &self.formats {
+			let data = output.get(format).ok_or_else(|| {
+				runtime_error!("library has not emitted discovery in {format} format, make sure your library is updated.")
+			})?;
+			let mut file = self.output_file.clone().into_os_string();
+			file.push(".");
+			file.push(format);
+			write(&file, data.as_bytes())
+				.map_err(|e| failure::io_error(format!("write failed: {e}")))?;
+			record_written(written, Path::new(&file), None)?;
+		}
 		Ok(())
 	}
 }
 
-struct AddressBook;
+#[derive(Clone)]
+struct AddressBook {
+	/// Where to write the address book, printed to stderr if not set
+	output_file: Option<PathBuf>,
+	/// Pass previously written address book to the library, so it can keep assignments stable
+	merge: bool,
+}
+impl AddressBook {
+	fn prior(&self) -> Result<Val> {
+		let Some(output_file) = &self.output_file else {
+			return Ok(Val::Null);
+		};
+		let data = match read_to_string(output_file) {
+			Ok(data) => data,
+			Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Val::Null),
+			Err(e) => {
+				return Err(failure::io_error(format!(
+					"failed to read prior address book {output_file:?}: {e}"
+				)))
+			}
+		};
+		if data.trim().is_empty() {
+			return Ok(Val::Null);
+		}
+		serde_json::from_str(&data)
+			.map_err(|e| runtime_error!("failed to parse prior address book {output_file:?}: {e}"))
+	}
+}
 impl GeneratorT for AddressBook {
 	fn library_modules(&self) -> Vec<String> {
 		vec!["lib:baedeker-library/outputs/addressbook.libsonnet".to_string()]
@@ -172,17 +577,39 @@ impl GeneratorT for AddressBook {
 	}
 
 	fn config(&self) -> Result<Option<Val>> {
-		Ok(None)
+		if !self.merge {
+			return Ok(None);
+		}
+		#[derive(Typed)]
+		struct Config {
+			prior: Val,
+		}
+		Config::into_untyped(Config {
+			prior: self.prior()?,
+		})
+		.map(Some)
 	}
 
-	fn process(&self, data: Val) -> Result<()> {
+	fn process(&self, data: Val, written: &OutputManifest) -> Result<()> {
 		let data = data.to_string()?;
-		eprintln!("{data}");
+		let Some(output_file) = &self.output_file else {
+			eprintln!("{data}");
+			return Ok(());
+		};
+		if let Some(parent) = output_file.parent() {
+			create_dir_all(parent).map_err(|e| failure::io_error(format!("mkdir failed: {e}")))?;
+		}
+		write(output_file, data.as_bytes())
+			.map_err(|e| failure::io_error(format!("write failed: {e}")))?;
+		record_written(written, output_file, None)?;
 		Ok(())
 	}
 }
 
-struct DebugGen;
+struct DebugGen {
+	/// Dotted path of the subtree to print, whole output is printed if unset
+	path: Option<String>,
+}
 impl GeneratorT for DebugGen {
 	fn library_modules(&self) -> Vec<String> {
 		vec!["lib:baedeker-library/outputs/debug.libsonnet".to_string()]
@@ -196,73 +623,321 @@ impl GeneratorT for DebugGen {
 		Ok(None)
 	}
 
-	fn process(&self, data: Val) -> Result<()> {
+	fn process(&self, data: Val, _written: &OutputManifest) -> Result<()> {
+		let data = if let Some(path) = &self.path {
+			val_utils::get_path(&data, path)?
+				.ok_or_else(|| runtime_error!("debug path {path:?} doesn't exist in the output"))?
+		} else {
+			data
+		};
 		let debug = data.manifest(JsonFormat::cli(2, true))?;
 		eprintln!("{debug}");
 		Ok(())
 	}
 }
 
+/// Prints output of other generator to stdout
+struct StdoutGen {
+	attribute: String,
+}
+impl GeneratorT for StdoutGen {
+	fn library_modules(&self) -> Vec<String> {
+		vec![]
+	}
+
+	fn output_attribute(&self) -> String {
+		self.attribute.clone()
+	}
+
+	fn config(&self) -> Result<Option<Val>> {
+		Ok(None)
+	}
+
+	fn process(&self, data: Val, _written: &OutputManifest) -> Result<()> {
+		let out = data.manifest(JsonFormat::cli(2, true))?;
+		println!("{out}");
+		Ok(())
+	}
+}
+
+/// Compose project names may only contain lowercase letters, digits, dashes and underscores,
+/// and should start with a letter or digit
+fn validate_compose_project(name: &str) -> std::result::Result<(), String> {
+	let valid = name
+		.chars()
+		.next()
+		.is_some_and(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+		&& name
+			.chars()
+			.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+	if !valid {
+		return Err(format!("invalid compose project name {name:?}, only lowercase letters, digits, dashes and underscores are allowed, and it should start with a letter or digit"));
+	}
+	Ok(())
+}
+
 impl FromStr for Generator {
-	type Err = &'static str;
+	type Err = String;
 
 	fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-		if let Some(file) = s.strip_prefix("docker_compose=") {
-			return Ok(Self::DockerCompose({
+		if let Some(opts) = s.strip_prefix("docker_compose=") {
+			let mut opts = Options::parse(opts)?;
+			let project = opts.take("project").map(str::to_owned);
+			let validate_refs = opts.take_parsed("validate_refs")?.unwrap_or(true);
+			let compress_specs = match opts.take("compress_specs") {
+				None => false,
+				Some("gzip") => true,
+				Some(other) => {
+					return Err(format!(
+						"unsupported spec compression {other:?}, only gzip is available"
+					))
+				}
+			};
+			if let Some(project) = &project {
+				validate_compose_project(project)?;
+			}
+			let root = {
 				let mut root = env::current_dir().map_err(|_| "bad cwd")?;
-				root.push(file);
+				root.push(opts.value);
 				root
+			};
+			opts.finish()?;
+			return Ok(Self::DockerCompose(DockerCompose {
+				output_dir: root,
+				project,
+				compress_specs,
+				validate_refs,
 			}));
-		} else if let Some(file) = s.strip_prefix("docker_compose_discover=") {
-			return Ok(Self::DockerComposeDiscover({
+		} else if let Some(opts) = s.strip_prefix("docker_compose_discover=") {
+			let mut opts = Options::parse(opts)?;
+			let formats = opts
+				.take("format")
+				.map(|f| f.split('+').map(str::to_owned).collect::<Vec<_>>())
+				.unwrap_or_default();
+			if let Some(format) = formats
+				.iter()
+				.find(|f| !DISCOVER_FORMATS.contains(&f.as_str()))
+			{
+				return Err(format!(
+					"unknown discover format {format:?}, available: {}",
+					DISCOVER_FORMATS.join(", ")
+				));
+			}
+			let file = {
 				let mut root = env::current_dir().map_err(|_| "bad cwd")?;
-				root.push(file);
+				root.push(opts.value);
 				root
-			}));
+			};
+			opts.finish()?;
+			return Ok(Self::DockerComposeDiscover(file, formats));
 		} else if s == "addressbook" {
-			return Ok(Self::AddressBook);
+			return Ok(Self::AddressBook(AddressBook {
+				output_file: None,
+				merge: false,
+			}));
+		} else if let Some(opts) = s.strip_prefix("addressbook=") {
+			let mut opts = Options::parse(opts)?;
+			let merge = opts.take_parsed("merge")?.unwrap_or(false);
+			let output_file = {
+				let mut root = env::current_dir().map_err(|_| "bad cwd")?;
+				root.push(opts.value);
+				root
+			};
+			opts.finish()?;
+			return Ok(Self::AddressBook(AddressBook {
+				output_file: Some(output_file),
+				merge,
+			}));
 		} else if s == "debug" {
-			return Ok(Self::Debug);
+			return Ok(Self::Debug(None));
+		} else if let Some(path) = s.strip_prefix("debug=") {
+			return Ok(Self::Debug(Some(path.to_owned())));
+		} else if let Some(attribute) = s.strip_prefix("stdout=") {
+			if attribute.is_empty() || attribute.contains(['.', '/']) {
+				return Err(format!(
+					"stdout generator expects a single output attribute name, got {attribute:?}"
+				));
+			}
+			return Ok(Self::Stdout(attribute.to_owned()));
 		}
-		Err("unknown generator")
+		Err(format!(
+			"unknown generator, available:\n  {}",
+			Self::FORMS.join("\n  ")
+		))
 	}
 }
 
 #[derive(Parser)]
-#[command(about)]
+#[command(about, after_help = EXIT_CODES_HELP)]
 struct Opts {
 	#[command(subcommand)]
 	command: Option<Commands>,
 	/// Where and how to store secrets.
 	///
-	/// Available values: file.
-	#[arg(long, default_value = "SecretBackend::Unset")]
-	secret: SecretBackend,
-	/// How to build specs.
+	/// Available values: file=<dir>[,allow_any_ty=true], memory[,allow_any_ty=true],
+	/// env[,allow_any_ty=true].
+	/// memory keeps secrets in the process memory only, for tests; paths to the local keystore
+	/// are only valid while baedeker is running.
+	/// env is read-only, and takes secrets from `BDK_NODE_KEY_<node>` (hex-encoded ed25519 secret),
+	/// `BDK_KEY_<node>_<ty>` and `BDK_WALLET_<name>_<ty>` (SURIs) variables, characters other than
+	/// ascii letters, digits and `_` are replaced with `_` in variable names; missing secrets
+	/// fail the run instead of being generated.
+	/// allow_any_ty permits key types which are not four characters long, keystores with such keys
+	/// are not compatible with stock substrate.
+	/// Any backend also accepts retries=<n>, retrying operations failed with transient (io)
+	/// errors up to n times with exponential backoff; retry_on=all retries every error instead.
+	/// With dry_run=true, nothing is written to the backend: keys which would be generated are only
+	/// kept in memory, and `bdk.ensureKeys` reports them as PLACEHOLDERS, together with keys
+	/// which are already present.
+	#[arg(long, global = true)]
+	secret: Option<SecretBackend>,
+	/// How to build specs, may be repeated with backends named as `<name>=<backend>`, which are
+	/// then selected by the `backend` argument of `bdk.processSpec` and other spec builtins.
 	///
-	/// Available values: docker.
-	#[arg(long, default_value = "SpecBackend::Unset")]
-	spec: SpecBackend,
+	/// Available values:
+	/// docker[,remote=true][,tmpdir=<path>][,spec_output=<container path>][,timeout=<secs>]
+	/// [,pull=always|missing|never][,env=merge|replace][,env_<NAME>=<value>].
+	/// Use remote=true when `$DOCKER_HOST` points to the non-local daemon, spec files are then
+	/// passed using volumes instead of bind mounts.
+	/// Use tmpdir to place temporary spec files outside of the system temp directory.
+	/// Use spec_output for nodes writing the built spec to the fixed file instead of stdout, spec is
+	/// read from this path when node prints nothing.
+	/// Use timeout to change the default 25 seconds limit of build containers, it may also be set
+	/// per spec source with `timeoutSecs`.
+	/// Use pull to set the pull policy of docker image binaries, it may also be set per binary with
+	/// `pull`; by default digest-pinned images are pulled when missing, and tagged images are
+	/// never pulled, so a locally present (possibly stale) image is used.
+	/// Use env_<NAME> to set environment variable of build containers (empty value removes it),
+	/// on top of the defaults (RUST_LOG=debug,wasmtime_cranelift=info, RUST_BACKTRACE=full,
+	/// COLORBT_SHOW_HIDDEN=1), or of the empty environment with env=replace. Since options are
+	/// comma separated, `;` in values is replaced with `,`: env_RUST_LOG=info;runtime=trace.
+	#[arg(long, global = true)]
+	spec: Vec<NamedSpecBackend>,
 	/// Which type of output this generator should produce.
 	///
-	/// Available values: docker_compose, addressbook, debug.
+	/// Available values:
+	/// docker_compose=<dir>[,project=<name>][,compress_specs=gzip][,validate_refs=false],
+	/// docker_compose_discover=<file>[,format=compose+env+json],
+	/// addressbook, addressbook=<file>[,merge=true], debug, debug=<dotted.path>, stdout=<attribute>.
+	/// stdout prints output of another generator (i.e `stdout=addressbook`) as JSON to stdout.
+	/// With compress_specs=gzip, `*.raw.json` outputs are written as `*.raw.json.gz`, nodes don't
+	/// read compressed specs, so the library decompresses them in the container entrypoint.
+	/// Compose bind mounts under the output directory should reference emitted files, unless
+	/// validate_refs=false is set.
 	#[arg(long)]
 	generator: Vec<Generator>,
 	#[command(flatten)]
 	import: MiscOpts,
 	#[command(flatten)]
 	trace: TraceOpts,
+	// Top-level arguments, passed to every config function.
+	// Big jsonnet-valued arguments may be read from files with `--tla-code-file name=path`,
+	// `prev` and `final` names are reserved.
 	#[command(flatten)]
 	tla: TlaOpts,
 	modules: Vec<String>,
 	#[arg(long)]
 	input_modules: Vec<String>,
+	/// Do not append the default `baedeker-library/inputs/base.libsonnet` module.
+	///
+	/// Base module establishes conventions (node keys, bootnodes, spec building) which the
+	/// generators rely on, when disabled, config is responsible for providing them itself.
+	#[arg(long)]
+	no_base: bool,
+	/// Rebuild all specs, even if their inputs are unchanged since the previous run.
+	///
+	/// Otherwise, outputs of the spec builder are cached in the output directory.
+	#[arg(long)]
+	force: bool,
+	/// Also reject generator outputs, which escape the output directory through symlinks.
+	///
+	/// By default, output paths are only checked lexically.
+	#[arg(long)]
+	strict_output_escape: bool,
+	/// Keep intermediate files of spec builds (genesis, raw spec, mounted spec files) in
+	/// the run-specific directory under the system temp dir, their locations are logged.
+	#[arg(long)]
+	keep_temp: bool,
+	/// Forbid any network access: images are never pulled (and should already be present),
+	/// and RPC calls made by the library fail.
+	#[arg(long)]
+	offline: bool,
+	/// Write build metrics (operation durations and counters) as JSON to the given path.
+	#[arg(long)]
+	metrics: Option<PathBuf>,
+	/// Write JSON list of files written by generators (`{ path, bytes, sha256 }`, plus
+	/// `previousSha256` for reconciled files) to the given path.
+	#[arg(long)]
+	output_manifest: Option<PathBuf>,
+	/// After generators are run, check output directories with `git status`, reporting whether
+	/// the generated files differ from the committed ones.
+	///
+	/// Available values: report (default), fail-on-dirty.
+	/// With fail-on-dirty the run fails if any of the outputs has changed, so CI may enforce
+	/// that committed outputs are regenerated when inputs change.
+	/// Output directories outside of git work tree are skipped with a warning.
+	#[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "report")]
+	git_check: Option<GitCheck>,
+	/// Fail if any warning was emitted during the run, useful in CI to keep configs pure.
+	#[arg(long)]
+	fail_on_warn: bool,
+	/// Platform reported by `bdk.hostInfo()`, in form of `os/arch` (i.e `linux/arm64`), detected
+	/// from the host if not set.
+	#[arg(long)]
+	platform: Option<String>,
+	/// Maximum number of containers run at once by the spec builder, defaults to the number of CPUs.
+	#[arg(long)]
+	concurrency: Option<usize>,
+	/// Export tracing spans to the OpenTelemetry collector, using OTLP over gRPC
+	/// (i.e `http://localhost:4317`).
+	#[arg(long)]
+	otel_endpoint: Option<String>,
+	/// Permit `bdk.ensureKeys` to return secret SURIs, when requested with `exposeSecrets: true`.
+	/// DANGEROUS: secrets may end up in the generated files.
+	#[arg(long)]
+	allow_secret_export: bool,
+	/// Only generate nodes with names matching the glob (`*` and `?` wildcards are supported),
+	/// specs are only built for them too.
+	///
+	/// The filter is passed to the library as `_config.nodeFilter` of node emitting generators,
+	/// and is checked with `bdk.nodeSelected(name)`; address book and discovery still reference
+	/// the full node set.
+	/// Filtered run produces a partial environment, which only makes sense on top of
+	/// the outputs of a full run.
+	#[arg(long, value_name = "GLOB")]
+	node_filter: Option<String>,
+	/// Derive every generated secret from the given seed, so the outputs are reproducible.
+	/// INSECURE: only intended for tests, never use for the real networks.
+	#[arg(long, value_name = "SEED", global = true)]
+	insecure_deterministic_rng: Option<u64>,
+	/// Print accepted `--generator` values and exit.
+	#[arg(long)]
+	list_generators: bool,
+	/// Print accepted `--secret` values and exit.
+	#[arg(long)]
+	list_secret_backends: bool,
+	/// Print accepted `--spec` values and exit.
+	#[arg(long)]
+	list_spec_backends: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
 	/// Print version
 	Version,
+	/// Manage keys directly in the configured `--secret` backend
+	Keys {
+		#[command(subcommand)]
+		command: KeysCommand,
+	},
+	/// Print the evaluated config (after all config modules, before generators) as JSON.
+	///
+	/// Spec builder returns placeholder specs, secrets are only stored in memory, and generators
+	/// are disabled, so nothing is built or written.
+	Render { modules: Vec<String> },
+	/// Check that the configured `--spec` and `--secret` backends work, and exit non-zero if any
+	/// of them doesn't.
+	Doctor,
 }
 
 pub fn apply_tla_opt(s: State, args: &GcHashMap<IStr, TlaArg>, val: Val) -> Result<Val> {
@@ -270,8 +945,35 @@ pub fn apply_tla_opt(s: State, args: &GcHashMap<IStr, TlaArg>, val: Val) -> Resu
 		return Ok(val);
 	};
 	let params = func.params();
-	if params.iter().any(|p| p.name().is_anonymous()) {
-		bail!("only named params supported");
+	if let Some(idx) = params.iter().position(|p| p.name().is_anonymous()) {
+		// Most of the functions are anonymous, name alone doesn't help to find the offending one
+		let defined_at = match &func {
+			FuncVal::Normal(desc) => {
+				let ExprLocation(source, offset, _) = &desc.body.1;
+				let [location] = source.map_source_locations(&[*offset]);
+				format!(" (body at {}:{})", source.source_path(), location.line)
+			}
+			_ => String::new(),
+		};
+		bail!(
+			"only named params supported, but parameter #{} of function {:?}{} is anonymous (destructured?)\n\
+			name it, and destructure in the body instead: `function(prev) local [a, b] = prev; ...`",
+			idx + 1,
+			func.name(),
+			defined_at,
+		);
+	}
+	for reserved in ["prev", "final"] {
+		if !args.contains_key(reserved) {
+			continue;
+		}
+		if params
+			.iter()
+			.any(|p| p.name().as_str() == Some(reserved) && p.has_default())
+		{
+			// Parameter with default value is unlikely to expect baedeker-provided value
+			warn!("function parameter `{reserved}` has a default value, but it is shadowed by the value provided by baedeker, rename it if this is unintended");
+		}
 	}
 	let mut new_args = GcHashMap::new();
 	for (name, val) in args.iter() {
@@ -299,29 +1001,84 @@ pub fn apply_tla_opt(s: State, args: &GcHashMap<IStr, TlaArg>, val: Val) -> Resu
 	)
 }
 
-fn main_jrsonnet(opts: Opts) -> Result<()> {
+/// With `render`, only config modules are evaluated, and the resulting config is printed
+fn main_jrsonnet(
+	opts: Opts,
+	warnings: Option<WarnCollector>,
+	rng: KeyRng,
+	render: bool,
+) -> Result<()> {
+	if opts.offline {
+		info!("offline mode is active, network access is disabled");
+		offline::enable();
+	}
+	if opts.keep_temp {
+		let dir = env::temp_dir().join(format!(
+			"bdk-run-{}-{:08x}",
+			std::process::id(),
+			rand::random::<u32>()
+		));
+		spec_builder::keep_temp(dir)?;
+	}
+	if opts.strict_output_escape {
+		STRICT_OUTPUT_ESCAPE.store(true, Ordering::Relaxed);
+	}
+	if let Some(filter) = &opts.node_filter {
+		warn!("node filter {filter:?} is set, only matching nodes are generated, the resulting environment is partial");
+	}
+	let metrics = Rc::new(Metrics::default());
+
+	let generators = opts
+		.generator
+		.into_iter()
+		.map(Generator::value)
+		.collect::<Vec<_>>();
+	let output_roots = generators
+		.iter()
+		.filter_map(|g| g.output_root())
+		.collect::<Vec<_>>();
+	for root in &output_roots {
+		probe_output_dir(root)?;
+	}
+	let output_root = output_roots.first().cloned();
+
 	let state = State::default();
 	state.set_import_resolver(opts.import.import_resolver());
+	// Shared between all backends, as they are all running containers on the same host
+	let limiter = Arc::new(ContainerLimiter::new(opts.concurrency.unwrap_or_else(
+		|| thread::available_parallelism().map_or(1, NonZeroUsize::get),
+	)));
 	state.set_context_initializer((
 		jrsonnet_stdlib::ContextInitializer::new(state.clone(), PathResolver::new_cwd_fallback()),
 		chainql_core::CqlContextInitializer::default(),
 		library::BdkContextInitializer {
-			spec_builder: Rc::new(opts.spec),
-			secrets: Rc::new(opts.secret),
+			spec_builders: Rc::new(SpecBuilders::new(opts.spec.into_iter().map(|spec| {
+				let builder: Rc<dyn SpecBuilder> = Rc::new(LimitedSpecBuilder {
+					inner: spec.backend,
+					limiter: limiter.clone(),
+				});
+				(spec.name, builder)
+			}))),
+			spec_cache: Rc::new(SpecCache::new(output_root.as_deref(), opts.force)),
+			secrets: Rc::new(opts.secret.unwrap_or_default()),
+			metrics: metrics.clone(),
+			output_root,
+			platform: opts.platform,
+			rng,
+			allow_secret_export: opts.allow_secret_export,
+			node_filter: opts.node_filter.clone(),
 		},
 	));
 
-	let generators = opts
-		.generator
-		.into_iter()
-		.map(Generator::value)
-		.collect::<Vec<_>>();
-
 	let mut tla = opts.tla.tla_opts()?;
-	if tla.contains_key("prev") || tla.contains_key("final") {
-		bail!("TLA should not contain prev/final")
+	for reserved in ["prev", "final"] {
+		if tla.contains_key(reserved) {
+			bail!("TLA should not contain prev/final, but `{reserved}` was passed (via --tla-str/--tla-code or their -file variants)")
+		}
 	}
 
+	// Errors without recorded class are coming from the evaluation from now on
+	failure::set_default(FailureClass::Config);
 	let config = {
 		let final_config = <Pending<Val>>::new();
 
@@ -330,13 +1087,17 @@ fn main_jrsonnet(opts: Opts) -> Result<()> {
 		tla.insert("final".into(), TlaArg::Lazy(final_config.clone().into()));
 
 		let mut modules = opts.modules.clone();
-		modules.push("lib:baedeker-library/inputs/base.libsonnet".to_owned());
+		if !opts.no_base {
+			modules.push(BASE_MODULE.to_owned());
+		}
 
+		let total = modules.len();
 		let mut modules = modules.iter();
 
 		let config = modules
 			.next()
 			.ok_or_else(|| runtime_error!("at least one module should be specified"))?;
+		info!("evaluating module 1/{total}: {config}");
 		let config = state.import(config)?;
 		let mut initial_modules = vec![];
 
@@ -362,8 +1123,8 @@ fn main_jrsonnet(opts: Opts) -> Result<()> {
 				.with_description(|| format!("<config array[{}]", i + 1))?;
 		}
 
-		for module in modules {
-			debug!("module: {module:?}");
+		for (i, module) in modules.enumerate() {
+			info!("evaluating module {}/{total}: {module}", i + 2);
 			let module = if let Some(module) = module.strip_prefix("lib:") {
 				state
 					.import_from(
@@ -384,9 +1145,16 @@ fn main_jrsonnet(opts: Opts) -> Result<()> {
 		// let config = config.as_obj().expect("checked to be obj");
 		// let config = Val::Obj(config_mixin.extend_from(config));
 
+		info!("evaluated {total} config modules");
+
 		final_config.fill(config.clone());
 		config
 	};
+	check_library_version(&config)?;
+	if render {
+		println!("{}", config.manifest(JsonFormat::cli(2, true))?);
+		return Ok(());
+	}
 
 	let config = {
 		let mut libraries = opts.input_modules.clone();
@@ -402,8 +1170,9 @@ fn main_jrsonnet(opts: Opts) -> Result<()> {
 		tla.insert("final".into(), TlaArg::Lazy(final_config.clone().into()));
 		tla.insert("prev".into(), TlaArg::Val(config.clone()));
 
-		for module in libraries {
-			debug!("input module: {module:?}");
+		let total = libraries.len();
+		for (i, module) in libraries.into_iter().enumerate() {
+			info!("evaluating input module {}/{total}: {module}", i + 1);
 			let module = if let Some(module) = module.strip_prefix("lib:") {
 				state
 					.import_from(
@@ -417,10 +1186,28 @@ fn main_jrsonnet(opts: Opts) -> Result<()> {
 			tla.insert("prev".into(), TlaArg::Val(config.clone()));
 			config = apply_tla_opt(state.clone(), &tla, module)?;
 		}
+		info!("evaluated {total} input modules");
 
 		for generator in &generators {
-			let Some(generator_config) = generator.config()? else {
-				continue;
+			let node_filter = opts
+				.node_filter
+				.as_deref()
+				.filter(|_| generator.node_filtered());
+			let generator_config = match (generator.config()?, node_filter) {
+				(None, None) => continue,
+				(Some(config), None) => config,
+				(config, Some(filter)) => {
+					let mut filter_mixin = ObjValueBuilder::new();
+					filter_mixin
+						.field("nodeFilter")
+						.value(Val::Str(filter.into()));
+					let filter_mixin = filter_mixin.build();
+					Val::Obj(match config {
+						Some(Val::Obj(config)) => filter_mixin.extend_from(config),
+						Some(_) => bail!("generator config should be an object"),
+						None => filter_mixin,
+					})
+				}
 			};
 			let mut config_mixin = ObjValueBuilder::new();
 			config_mixin
@@ -447,47 +1234,231 @@ fn main_jrsonnet(opts: Opts) -> Result<()> {
 		config
 	};
 
+	failure::set_default(FailureClass::Generic);
 	let config = config.as_obj().expect("checked to be obj");
 	let output = config.get("_output".into())?.ok_or_else(|| {
 		runtime_error!("missing output key, have you imported any of the generators?")
 	})?;
 	let output = ObjValue::from_untyped(output)?;
+	let written = OutputManifest::default();
 	for generator in generators {
 		let attr = generator.output_attribute();
 		let data = output.get(attr.as_str().into())?.ok_or_else(|| {
-			runtime_error!("missing generator output: {attr}, make sure your library is updated.")
+			let available = output
+				.fields(false)
+				.iter()
+				.map(|f| f.to_string())
+				.collect::<Vec<_>>()
+				.join(", ");
+			runtime_error!("missing generator output: {attr}, make sure your library is updated. Available outputs: {available}")
+		})?;
+		for path in generator.required_config() {
+			if val_utils::get_path(&data, &path)?.is_none() {
+				bail!("generator {attr}: missing required config key {path}, make sure your library is updated.");
+			}
+		}
+		// Generators force lazy config fields, so evaluation errors surface here
+		failure::set_default(FailureClass::Config);
+		metrics.time(&format!("generator {attr}"), || {
+			generator.process(data, &written)
 		})?;
-		generator.process(data)?;
+		failure::set_default(FailureClass::Generic);
+	}
+
+	if let Some(path) = &opts.output_manifest {
+		let manifest = serde_json::to_string_pretty(&written.to_json())
+			.map_err(|e| runtime_error!("json: {e}"))?;
+		write(path, manifest)
+			.map_err(|e| failure::io_error(format!("failed to write output manifest: {e}")))?;
+	}
+
+	if let Some(mode) = opts.git_check {
+		if output_roots.is_empty() {
+			warn!("git check: none of the generators writes to the output directory");
+		}
+		let dirty = git_check::run(output_roots.iter().map(PathBuf::as_path))
+			.map_err(|e| runtime_error!("git check: {e}"))?;
+		if dirty && mode == GitCheck::FailOnDirty {
+			failure::record(FailureClass::Generic);
+			bail!("git check: generated outputs differ from the committed ones, regenerate and commit them");
+		}
+	}
+
+	metrics.log_summary();
+	if let Some(path) = &opts.metrics {
+		let metrics = serde_json::to_string_pretty(&metrics.to_json())
+			.map_err(|e| runtime_error!("json: {e}"))?;
+		write(path, metrics)
+			.map_err(|e| failure::io_error(format!("failed to write metrics: {e}")))?;
+	}
+
+	if let Some(warnings) = warnings {
+		let warnings = warnings.take();
+		if !warnings.is_empty() {
+			bail!(
+				"--fail-on-warn is set, and {} warning(s) were emitted:\n{}",
+				warnings.len(),
+				warnings.join("\n"),
+			);
+		}
 	}
 
 	Ok(())
 }
 
 fn main_sync() {
-	tracing_subscriber::fmt()
-		.without_time()
-		.with_env_filter(EnvFilter::from_default_env())
-		.init();
+	let mut opts = Opts::parse();
 
-	let opts = Opts::parse();
+	let warnings = opts.fail_on_warn.then(WarnCollector::default);
+	let otel = match opts.otel_endpoint.as_deref().map(Otel::new).transpose() {
+		Ok(otel) => otel,
+		Err(e) => {
+			eprintln!("failed to initialize otel exporter: {e}");
+			std::process::exit(FailureClass::Generic.exit_code());
+		}
+	};
+	tracing_subscriber::registry()
+		.with(
+			tracing_subscriber::fmt::layer()
+				.without_time()
+				.with_filter(EnvFilter::from_default_env()),
+		)
+		.with(warnings.clone())
+		.with(otel.as_ref().map(Otel::layer))
+		.init();
 	let trace_format = opts.trace.trace_format();
+	let exit = |code: i32| {
+		if let Some(otel) = &otel {
+			otel.shutdown();
+		}
+		if code != 0 {
+			std::process::exit(code);
+		}
+	};
 
 	if let Some(Commands::Version) = opts.command {
 		println!("{} {}", env!("CARGO_BIN_NAME"), env!("CARGO_PKG_VERSION"));
 		return;
 	}
+	if opts.list_generators || opts.list_secret_backends || opts.list_spec_backends {
+		for (list, forms) in [
+			(opts.list_generators, Generator::FORMS),
+			(opts.list_secret_backends, SecretBackend::FORMS),
+			(opts.list_spec_backends, SpecBackend::FORMS),
+		] {
+			if list {
+				for form in forms {
+					println!("{form}");
+				}
+			}
+		}
+		return;
+	}
+	if let Some(Commands::Doctor) = opts.command {
+		if opts.offline {
+			offline::enable();
+		}
+		if !doctor::run(&opts.spec, &opts.secret.unwrap_or_default()) {
+			exit(FailureClass::Generic.exit_code());
+		}
+		exit(0);
+		return;
+	}
+	// Shared by every key generation path, so that the whole run is reproducible with the fixed seed
+	let rng = match opts.insecure_deterministic_rng {
+		Some(seed) => {
+			warn!(
+				"using deterministic RNG with seed {seed}, generated secrets are INSECURE \
+				 and should only be used for tests"
+			);
+			KeyRng::insecure_deterministic(seed)
+		}
+		None => KeyRng::os(),
+	};
+	if let Some(Commands::Keys { command }) = &opts.command {
+		if let Err(e) = command.run(&opts.secret.clone().unwrap_or_default(), &rng) {
+			error!("keystore: {e}");
+			exit(FailureClass::Keystore.exit_code());
+		}
+		exit(0);
+		return;
+	}
+
+	let render = if let Some(Commands::Render { modules }) = opts.command.take() {
+		opts.modules = modules;
+		// Backend names are kept, so that builtins selecting them still work
+		if opts.spec.is_empty() {
+			opts.spec.push(NamedSpecBackend {
+				name: None,
+				backend: SpecBackend::Stub,
+			});
+		}
+		for spec in &mut opts.spec {
+			spec.backend = SpecBackend::Stub;
+		}
+		opts.secret = Some(opts.secret.take().unwrap_or_default().into_dry_run());
+		opts.generator.clear();
+		true
+	} else {
+		false
+	};
 
-	match main_jrsonnet(opts) {
-		Ok(_) => {}
+	match main_jrsonnet(opts, warnings, rng, render) {
+		Ok(_) => exit(0),
 		Err(e) => {
 			let v = trace_format.format(&e).unwrap();
 			error!("{v}");
-			std::process::exit(1);
+			exit(failure::evaluation_exit_code());
 		}
 	}
 }
 
+/// On SIGINT/SIGTERM, stop containers started by baedeker before exiting
+async fn handle_signals() {
+	let Ok(mut terminate) = signal(SignalKind::terminate()) else {
+		warn!("failed to install SIGTERM handler");
+		return;
+	};
+	let code = tokio::select! {
+		_ = tokio::signal::ctrl_c() => 130,
+		_ = terminate.recv() => 143,
+	};
+	warn!("interrupted, cleaning up");
+	docker::stop_running();
+	std::process::exit(code);
+}
+
 #[tokio::main]
 async fn main() {
+	Handle::current().spawn(handle_signals());
 	Handle::current().spawn_blocking(main_sync).await.expect("baedeker should not panic, this is a bug, report to https://github.com/UniqueNetwork/baedeker/issues");
 }
+
+#[cfg(test)]
+mod tests {
+	use jrsonnet_evaluator::{gc::GcHashMap, State};
+
+	use super::{apply_tla_opt, Generator};
+	use crate::options::sample_forms;
+
+	#[test]
+	fn listed_generator_forms_parse() {
+		for form in Generator::FORMS {
+			for sample in sample_forms(form) {
+				sample
+					.parse::<Generator>()
+					.unwrap_or_else(|e| panic!("{sample}: {e}"));
+			}
+		}
+	}
+
+	#[test]
+	fn anonymous_param_location() {
+		let s = State::default();
+		let func = s
+			.evaluate_snippet("module.jsonnet", "function([a, b])\n\n\ta")
+			.unwrap();
+		let e = apply_tla_opt(s, &GcHashMap::new(), func).unwrap_err();
+		assert!(e.to_string().contains("(body at module.jsonnet:3)"), "{e}");
+	}
+}
@@ -0,0 +1,81 @@
+use std::{
+	cell::RefCell,
+	collections::BTreeMap,
+	time::{Duration, Instant},
+};
+
+use serde_json::{json, Value};
+use tracing::{debug_span, info};
+
+#[derive(Default, Clone, Copy)]
+struct Timing {
+	count: u64,
+	total: Duration,
+}
+
+/// Per-run build metrics, shared between builtins and the main loop
+#[derive(Default)]
+pub struct Metrics {
+	timings: RefCell<BTreeMap<String, Timing>>,
+	counters: RefCell<BTreeMap<String, u64>>,
+}
+impl Metrics {
+	/// Run operation, accounting its duration under the provided name
+	pub fn time<T>(&self, operation: &str, f: impl FnOnce() -> T) -> T {
+		let start = Instant::now();
+		let out = debug_span!("operation", name = operation).in_scope(f);
+		let elapsed = start.elapsed();
+		let mut timings = self.timings.borrow_mut();
+		let timing = timings.entry(operation.to_owned()).or_default();
+		timing.count += 1;
+		timing.total += elapsed;
+		out
+	}
+
+	pub fn increment(&self, counter: &str) {
+		*self
+			.counters
+			.borrow_mut()
+			.entry(counter.to_owned())
+			.or_default() += 1;
+	}
+
+	pub fn log_summary(&self) {
+		let timings = self.timings.borrow();
+		let counters = self.counters.borrow();
+		if timings.is_empty() && counters.is_empty() {
+			return;
+		}
+		info!("build summary:");
+		for (operation, timing) in timings.iter() {
+			info!(
+				"  {operation}: {} time(s), {:.2?} total",
+				timing.count, timing.total
+			);
+		}
+		for (counter, value) in counters.iter() {
+			info!("  {counter}: {value}");
+		}
+	}
+
+	pub fn to_json(&self) -> Value {
+		let timings = self
+			.timings
+			.borrow()
+			.iter()
+			.map(|(operation, timing)| {
+				(
+					operation.clone(),
+					json!({
+						"count": timing.count,
+						"totalSecs": timing.total.as_secs_f64(),
+					}),
+				)
+			})
+			.collect::<serde_json::Map<_, _>>();
+		json!({
+			"timings": timings,
+			"counters": self.counters.borrow().clone(),
+		})
+	}
+}
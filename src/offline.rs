@@ -0,0 +1,21 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by `--offline`, forbids image pulls and any other network access
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+	OFFLINE.store(true, Ordering::Relaxed);
+}
+
+pub fn is_offline() -> bool {
+	OFFLINE.load(Ordering::Relaxed)
+}
+
+/// `docker run --pull` policy, `never` in offline mode
+pub fn pull_policy(wanted: &'static str) -> &'static str {
+	if is_offline() {
+		"never"
+	} else {
+		wanted
+	}
+}
@@ -0,0 +1,101 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+/// Parsed option string in form of `value,key=value,key2=value2`
+pub struct Options<'s> {
+	pub value: &'s str,
+	options: BTreeMap<&'s str, &'s str>,
+}
+impl<'s> Options<'s> {
+	pub fn parse(s: &'s str) -> Result<Self, String> {
+		let mut parts = s.split(',');
+		let value = parts
+			.next()
+			.expect("split always returns at least one part");
+		let mut options = BTreeMap::new();
+		for part in parts {
+			let Some((key, value)) = part.split_once('=') else {
+				return Err(format!("option should be in form of key=value: {part:?}"));
+			};
+			if options.insert(key, value).is_some() {
+				return Err(format!("duplicate option: {key:?}"));
+			}
+		}
+		Ok(Self { value, options })
+	}
+
+	pub fn take(&mut self, key: &str) -> Option<&'s str> {
+		self.options.remove(key)
+	}
+
+	/// Take all options with the given key prefix, returned keys have the prefix stripped
+	pub fn take_prefixed(&mut self, prefix: &str) -> Vec<(&'s str, &'s str)> {
+		let keys = self
+			.options
+			.keys()
+			.copied()
+			.filter(|k| k.starts_with(prefix))
+			.collect::<Vec<_>>();
+		keys.into_iter()
+			.map(|k| (&k[prefix.len()..], self.options.remove(k).expect("listed")))
+			.collect()
+	}
+
+	pub fn take_parsed<T: FromStr>(&mut self, key: &str) -> Result<Option<T>, String> {
+		self.take(key)
+			.map(|v| {
+				v.parse()
+					.map_err(|_| format!("invalid value for option {key:?}: {v:?}"))
+			})
+			.transpose()
+	}
+
+	/// Ensure there is no unknown options left
+	pub fn finish(self) -> Result<(), String> {
+		if let Some(key) = self.options.keys().next() {
+			return Err(format!("unknown option: {key:?}"));
+		}
+		Ok(())
+	}
+}
+
+/// Option strings matching the form listed in `FORMS`: every optional part is included, and
+/// placeholders are replaced with sample values. Every `a|b` alternative is used by at least one
+/// of the returned strings.
+#[cfg(test)]
+pub fn sample_forms(form: &str) -> Vec<String> {
+	let mut filled = String::new();
+	let mut chars = form.chars();
+	while let Some(c) = chars.next() {
+		match c {
+			'[' | ']' => {}
+			'<' => {
+				let name = chars.by_ref().take_while(|&c| c != '>').collect::<String>();
+				filled.push_str(match name.as_str() {
+					"n" | "secs" => "1",
+					_ => "sample",
+				});
+			}
+			_ => filled.push(c),
+		}
+	}
+	let parts = filled
+		.split(',')
+		.map(|part| match part.split_once('=') {
+			Some((key, values)) => values
+				.split('|')
+				.map(|v| format!("{key}={v}"))
+				.collect::<Vec<_>>(),
+			None => part.split('|').map(str::to_owned).collect::<Vec<_>>(),
+		})
+		.collect::<Vec<_>>();
+	let variants = parts.iter().map(Vec::len).max().unwrap_or(1);
+	(0..variants)
+		.map(|i| {
+			parts
+				.iter()
+				.map(|alternatives| alternatives[i.min(alternatives.len() - 1)].as_str())
+				.collect::<Vec<_>>()
+				.join(",")
+		})
+		.collect()
+}
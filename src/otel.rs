@@ -0,0 +1,52 @@
+use opentelemetry::{trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+	runtime::Tokio,
+	trace::{Config, TracerProvider},
+	Resource,
+};
+use tracing::{warn, Level, Subscriber};
+use tracing_subscriber::{filter::Targets, registry::LookupSpan, Layer};
+
+/// OTLP span export, enabled by `--otel-endpoint`
+pub struct Otel {
+	provider: TracerProvider,
+}
+impl Otel {
+	/// Should be called from within the tokio runtime, exporter is running as a background task
+	pub fn new(endpoint: &str) -> Result<Self, opentelemetry::trace::TraceError> {
+		let provider = opentelemetry_otlp::new_pipeline()
+			.tracing()
+			.with_exporter(
+				opentelemetry_otlp::new_exporter()
+					.tonic()
+					.with_endpoint(endpoint),
+			)
+			.with_trace_config(
+				Config::default().with_resource(Resource::new([KeyValue::new(
+					"service.name",
+					env!("CARGO_BIN_NAME"),
+				)])),
+			)
+			.install_batch(Tokio)?;
+		Ok(Self { provider })
+	}
+
+	pub fn layer<S>(&self) -> impl Layer<S>
+	where
+		S: Subscriber + for<'span> LookupSpan<'span>,
+	{
+		tracing_opentelemetry::layer()
+			.with_tracer(self.provider.tracer(env!("CARGO_BIN_NAME")))
+			// Only our own spans are interesting, also exporter itself emits events,
+			// which shouldn't be exported
+			.with_filter(Targets::new().with_target(env!("CARGO_CRATE_NAME"), Level::DEBUG))
+	}
+
+	/// Flush pending spans, should be called before exit
+	pub fn shutdown(&self) {
+		if let Err(e) = self.provider.shutdown() {
+			warn!("failed to flush otel spans: {e}");
+		}
+	}
+}
@@ -0,0 +1,51 @@
+use std::{cell::RefCell, fs, io, path::Path};
+
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+struct Entry {
+	path: String,
+	bytes: u64,
+	sha256: String,
+	/// Hash of the file before reconciliation
+	previous_sha256: Option<String>,
+}
+
+/// Files, written by the generators during the run
+#[derive(Default)]
+pub struct OutputManifest {
+	entries: RefCell<Vec<Entry>>,
+}
+impl OutputManifest {
+	/// Record file, which was just written, `previous` is the reconciled file content
+	pub fn record(&self, path: &Path, previous: Option<&[u8]>) -> io::Result<()> {
+		let data = fs::read(path)?;
+		self.entries.borrow_mut().push(Entry {
+			path: path.to_string_lossy().into_owned(),
+			bytes: data.len() as u64,
+			sha256: hex::encode(Sha256::digest(&data)),
+			previous_sha256: previous.map(|p| hex::encode(Sha256::digest(p))),
+		});
+		Ok(())
+	}
+
+	pub fn to_json(&self) -> Value {
+		Value::Array(
+			self.entries
+				.borrow()
+				.iter()
+				.map(|e| {
+					let mut entry = json!({
+						"path": e.path,
+						"bytes": e.bytes,
+						"sha256": e.sha256,
+					});
+					if let Some(previous) = &e.previous_sha256 {
+						entry["previousSha256"] = json!(previous);
+					}
+					entry
+				})
+				.collect(),
+		)
+	}
+}
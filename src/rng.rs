@@ -0,0 +1,45 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use bip39::{Language, Mnemonic};
+use libp2p::identity::ed25519;
+use rand::rngs::OsRng;
+use rand::{CryptoRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+/// Random source suitable for key material generation
+pub trait SecureRng: RngCore + CryptoRng {}
+impl<T: RngCore + CryptoRng> SecureRng for T {}
+
+/// Random source used for generated secrets, shared between all builtins of a run
+#[derive(Clone)]
+pub struct KeyRng(Rc<RefCell<dyn SecureRng>>);
+impl KeyRng {
+	pub fn os() -> Self {
+		Self(Rc::new(RefCell::new(OsRng)))
+	}
+	/// Every generated secret is derived from the seed, only usable for tests.
+	pub fn insecure_deterministic(seed: u64) -> Self {
+		Self(Rc::new(RefCell::new(ChaCha20Rng::seed_from_u64(seed))))
+	}
+
+	pub fn bytes<const N: usize>(&self) -> [u8; N] {
+		let mut out = [0; N];
+		self.fill(&mut out);
+		out
+	}
+	pub fn fill(&self, out: &mut [u8]) {
+		self.0.borrow_mut().fill_bytes(out)
+	}
+	pub fn mnemonic(&self, words: usize) -> String {
+		let mut rng = self.0.borrow_mut();
+		Mnemonic::generate_in_with(&mut &mut *rng, Language::English, words)
+			.expect("valid word count")
+			.to_string()
+	}
+	pub fn node_keypair(&self) -> ed25519::Keypair {
+		let secret = ed25519::SecretKey::try_from_bytes(self.bytes::<32>())
+			.expect("any 32 bytes are valid ed25519 secret");
+		secret.into()
+	}
+}
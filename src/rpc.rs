@@ -0,0 +1,83 @@
+use std::{result, time::Duration};
+
+use serde_json::{json, Value};
+use tokio::runtime::Handle;
+
+use crate::failure::{self, FailureClass};
+use crate::offline;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+	#[error("failed to build http client: {0}")]
+	Client(reqwest::Error),
+	#[error("request to {0} failed: {1}")]
+	Request(String, reqwest::Error),
+	#[error("call {0} failed: {1}")]
+	Call(String, Value),
+	#[error("call {0} returned no result")]
+	MissingResult(String),
+	#[error("unexpected response for {0}: {1}")]
+	UnexpectedResponse(String, Value),
+	#[error("offline mode: refusing to connect to {0}")]
+	Offline(String),
+}
+type Result<T, E = Error> = result::Result<T, E>;
+
+impl From<Error> for jrsonnet_evaluator::Error {
+	fn from(value: Error) -> Self {
+		failure::record(FailureClass::Rpc);
+		jrsonnet_evaluator::Error::new(jrsonnet_evaluator::RuntimeError(
+			format!("rpc: {value}").into(),
+		))
+	}
+}
+
+/// Minimal blocking JSON-RPC over HTTP client, driven by the ambient tokio runtime
+pub struct RpcClient {
+	client: reqwest::Client,
+	url: String,
+}
+impl RpcClient {
+	pub fn new(url: &str, timeout: Duration) -> Result<Self> {
+		if offline::is_offline() {
+			return Err(Error::Offline(url.to_owned()));
+		}
+		let client = reqwest::Client::builder()
+			.timeout(timeout)
+			.connect_timeout(timeout)
+			.build()
+			.map_err(Error::Client)?;
+		Ok(Self {
+			client,
+			url: url.to_owned(),
+		})
+	}
+
+	pub fn call(&self, method: &str, params: Value) -> Result<Value> {
+		let request = json!({
+			"jsonrpc": "2.0",
+			"id": 1,
+			"method": method,
+			"params": params,
+		});
+		let response: Value = Handle::current()
+			.block_on(async {
+				self.client
+					.post(&self.url)
+					.json(&request)
+					.send()
+					.await?
+					.error_for_status()?
+					.json()
+					.await
+			})
+			.map_err(|e| Error::Request(self.url.clone(), e))?;
+		if let Some(error) = response.get("error") {
+			return Err(Error::Call(method.to_owned(), error.clone()));
+		}
+		response
+			.get("result")
+			.cloned()
+			.ok_or_else(|| Error::MissingResult(method.to_owned()))
+	}
+}
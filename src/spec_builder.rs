@@ -1,42 +1,64 @@
 use std::{
-	fs::{self, metadata, read_dir},
-	io::Write,
+	collections::{BTreeMap, BTreeSet},
+	fs::{self, metadata, read_dir, File},
+	io::{BufReader, Read, Write},
 	os::unix::fs::PermissionsExt,
-	path::PathBuf,
-	process::{Command, Stdio},
+	path::{Path, PathBuf},
+	process::{Command, ExitStatus, Stdio},
+	rc::Rc,
 	result,
 	str::FromStr,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc, Mutex, OnceLock,
+	},
 };
 
+use flate2::read::GzDecoder;
 use jrsonnet_evaluator::{
 	bail,
 	function::FuncVal,
-	typed::{ComplexValType, Either2, Typed},
+	typed::{ComplexValType, Either3, Typed},
 	Either, ObjValue, ObjValueBuilder, Val,
 };
 use jrsonnet_gcmodule::Trace;
-use tempfile::Builder;
-use tracing::info;
+use sha2::{Digest, Sha256};
+use tar::Archive;
+use tempfile::{Builder, NamedTempFile, TempDir};
+use tracing::{debug, info, warn};
 
-use crate::docker::EMPTY_IMAGE;
+use crate::docker::{remote_docker_host, RunningContainer, EMPTY_IMAGE};
+use crate::failure::{self, FailureClass};
+use crate::limiter::ContainerLimiter;
+use crate::offline;
+use crate::options::Options;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
 	#[error("io: {0}")]
 	Io(#[from] std::io::Error),
-	#[error("docker finished with non-zero exit code; spec dumped to {0:?}\nCommand was: {1}")]
-	DockerCommandFailed(PathBuf, String),
+	#[error("docker finished with non-zero exit code{}\nCommand was: {2}", kept_files(.0, .1))]
+	DockerCommandFailed(Option<PathBuf>, Option<PathBuf>, String),
 	#[error("json: {0}")]
 	Json(#[from] serde_json::Error),
 	#[error("binary is not set")]
 	BinaryNotSet,
 	#[error("invalid parameter: {0}")]
 	InvalidParameter(&'static str),
+	#[error("binary {0:?} not found in archive {1:?}")]
+	ArchiveEntryNotFound(String, String),
+	#[error("DOCKER_HOST points to the remote daemon ({0}), but bind mounts only work with the local one; use --spec docker,remote=true")]
+	RemoteDaemon(String),
+	#[error("failed to pin image {0:?}: {1}")]
+	ImagePin(String, &'static str),
+	#[error("unknown spec backend {0:?}, configured: {1}")]
+	UnknownBackend(String, String),
 }
 type Result<T, E = Error> = result::Result<T, E>;
 
 impl From<Error> for jrsonnet_evaluator::Error {
 	fn from(value: Error) -> Self {
+		failure::record(FailureClass::SpecBuild);
 		jrsonnet_evaluator::Error::new(jrsonnet_evaluator::RuntimeError(
 			format!("spec builder: {value}").into(),
 		))
@@ -73,64 +95,590 @@ pub fn docker_mounts() -> Result<Vec<String>> {
 	Ok(out)
 }
 
+/// Node stdout, stored in the temporary file to avoid buffering big specs in memory
+pub struct NodeOutput(NamedTempFile);
+impl NodeOutput {
+	/// Stream-parse output as json
+	pub fn parse(self) -> Result<Val> {
+		let reader = BufReader::new(self.0.reopen()?);
+		Ok(serde_json::from_reader(reader)?)
+	}
+	pub fn is_empty(&self) -> Result<bool> {
+		Ok(self.0.as_file().metadata()?.len() == 0)
+	}
+	pub fn into_bytes(self) -> Result<Vec<u8>> {
+		Ok(fs::read(self.0.path())?)
+	}
+}
+
+/// Outputs of the previous runs, stored in the output directory and keyed by the build input
+/// fingerprint, so that unchanged specs are not rebuilt
+pub struct SpecCache {
+	dir: Option<PathBuf>,
+	/// Rebuild everything, cache is still updated
+	force: bool,
+}
+impl SpecCache {
+	pub const DIR: &'static str = ".bdk-spec-cache";
+
+	pub fn new(output_root: Option<&Path>, force: bool) -> Self {
+		Self {
+			dir: output_root.map(|root| root.join(Self::DIR)),
+			force,
+		}
+	}
+
+	/// Fingerprint of the build step, `None` if inputs can't be reliably identified
+	pub fn key(
+		&self,
+		step: &str,
+		builder: &dyn SpecBuilder,
+		bin: &FileLocation,
+		inputs: &[&[u8]],
+	) -> Result<Option<String>> {
+		if self.dir.is_none() {
+			return Ok(None);
+		}
+		// Local image may be stale, and only the build itself pulls the fresh one
+		if builder.pulls_on_every_run(bin) {
+			debug!("image is pulled on every build, not using spec cache");
+			return Ok(None);
+		}
+		let Some(bin) = bin.fingerprint()? else {
+			return Ok(None);
+		};
+		let mut hasher = Sha256::new();
+		for part in [step.as_bytes(), bin.as_bytes()]
+			.into_iter()
+			.chain(inputs.iter().copied())
+		{
+			hasher.update((part.len() as u64).to_le_bytes());
+			hasher.update(part);
+		}
+		Ok(Some(hex::encode(hasher.finalize())))
+	}
+
+	/// Reuse the output of the previous run with the same fingerprint, or build and store it
+	pub fn get_or_build(
+		&self,
+		key: Option<String>,
+		build: impl FnOnce() -> Result<NodeOutput>,
+	) -> Result<NodeOutput> {
+		let (Some(dir), Some(key)) = (&self.dir, key) else {
+			return build();
+		};
+		let path = dir.join(format!("{key}.json"));
+		if !self.force && path.is_file() {
+			info!("inputs are unchanged, reusing output of the previous run ({key})");
+			let mut builder = Builder::new();
+			builder.prefix("bdk-output");
+			let file = builder.tempfile()?;
+			fs::copy(&path, file.path())?;
+			return Ok(NodeOutput(file));
+		}
+		let output = build()?;
+		fs::create_dir_all(dir)?;
+		// Copied under the temporary name first, so that interrupted run doesn't leave partial entry
+		let partial = dir.join(format!("{key}.json.partial"));
+		fs::copy(output.0.path(), &partial)?;
+		fs::rename(&partial, &path)?;
+		Ok(output)
+	}
+}
+
 pub trait SpecBuilder {
-	fn build_genesis(&self, bin: &FileLocation, chain: Option<String>) -> Result<Vec<u8>>;
+	fn build_genesis(
+		&self,
+		bin: &FileLocation,
+		chain: Option<String>,
+		keystore: Option<&KeystoreMount>,
+		timeout: BuildTimeout,
+	) -> Result<NodeOutput>;
+	/// Build raw spec directly from the chain, skipping the intermediate genesis step
+	fn build_genesis_raw(
+		&self,
+		bin: &FileLocation,
+		chain: Option<String>,
+		keystore: Option<&KeystoreMount>,
+		timeout: BuildTimeout,
+	) -> Result<NodeOutput>;
 	fn build_raw(
 		&self,
 		bin: &FileLocation,
 		spec_file_prefix: Option<String>,
 		spec: String,
-	) -> Result<Vec<u8>>;
+		timeout: BuildTimeout,
+	) -> Result<NodeOutput>;
+	/// Run `export-genesis-state` against the provided raw spec
+	fn build_genesis_state(
+		&self,
+		bin: &FileLocation,
+		spec_file_prefix: Option<String>,
+		raw_spec: String,
+	) -> Result<NodeOutput>;
+	/// Run `export-genesis-wasm` against the provided raw spec
+	fn build_genesis_wasm(
+		&self,
+		bin: &FileLocation,
+		spec_file_prefix: Option<String>,
+		raw_spec: String,
+	) -> Result<NodeOutput>;
+	/// Binary is refreshed on every build, so its local fingerprint can't identify it
+	fn pulls_on_every_run(&self, _bin: &FileLocation) -> bool {
+		false
+	}
 }
 
-#[derive(Clone)]
-pub struct DockerSpecBuilder;
+/// Resources, which should live until container is finished
+struct CommandGuard {
+	_extracted: Option<TempDir>,
+	_container: RunningContainer,
+}
+
+/// Set by `--keep-temp`, intermediate files are copied there
+static KEEP_TEMP: OnceLock<PathBuf> = OnceLock::new();
+/// Order of the kept files, so that stages of the same run can be told apart
+static KEPT_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Preserve intermediate files of every build in the given directory
+pub fn keep_temp(dir: PathBuf) -> Result<()> {
+	fs::create_dir_all(&dir)?;
+	info!("intermediate files are kept in {}", dir.display());
+	KEEP_TEMP
+		.set(dir)
+		.map_err(|_| Error::InvalidParameter("keep temp directory is already set"))
+}
+
+/// Copy the temporary file into the keep directory, if enabled
+fn keep_file(stage: &str, file: &Path) -> Result<()> {
+	let Some(dir) = KEEP_TEMP.get() else {
+		return Ok(());
+	};
+	let n = KEPT_FILES.fetch_add(1, Ordering::Relaxed);
+	let dest = dir.join(format!("{n:03}-{stage}.json"));
+	fs::copy(file, &dest)?;
+	info!("kept {stage} at {}", dest.display());
+	Ok(())
+}
+
+/// Keep temporary file on disk after drop, for inspection of the failed command
+fn retain(file: NamedTempFile) -> PathBuf {
+	let path = file.into_temp_path();
+	let buf = path.to_path_buf();
+	std::mem::forget(path);
+	buf
+}
+
+fn kept_files(spec: &Option<PathBuf>, stdout: &Option<PathBuf>) -> String {
+	let mut out = String::new();
+	if let Some(spec) = spec {
+		out.push_str(&format!("; spec dumped to {spec:?}"));
+	}
+	if let Some(stdout) = stdout {
+		out.push_str(&format!("; stdout kept at {stdout:?}"));
+	}
+	out
+}
+
+/// Directory inside of the container, under which the spec file is mounted for `run_with_spec`
+const SPEC_MOUNT_DIR: &str = "/tmp/bdk-spec";
+const SPEC_FILE_NAME: &str = "spec.json";
+
+enum SpecMountSource<'a> {
+	/// Spec file on the host
+	Bind(&'a str),
+	/// Volume, containing the spec as [`SPEC_FILE_NAME`]
+	Volume(&'a str),
+}
+
+/// `--mount` argument for the spec, and the `--chain` argument pointing to it inside of the
+/// container. Both are derived from the same constants, so they can't diverge
+// FIXME: Moonbeam wants the spec json file to be named after runtime
+fn spec_mount(source: SpecMountSource<'_>) -> (String, String) {
+	let chain = format!("{SPEC_MOUNT_DIR}/{SPEC_FILE_NAME}");
+	let mount = match source {
+		SpecMountSource::Bind(path) => format!("type=bind,source={path},target={chain},readonly"),
+		SpecMountSource::Volume(volume) => {
+			format!("type=volume,source={volume},target={SPEC_MOUNT_DIR},readonly")
+		}
+	};
+	(mount, chain)
+}
+
+/// Directory inside of the container, under which binary extracted from the archive is mounted
+const ARCHIVE_BIN_DIR: &str = "/tmp/bdk-bin";
+
+fn bind_mount(command: &mut Command, source: &str, target: &str) {
+	command.arg("--mount").arg(format!(
+		"type=bind,source={source},target={target},readonly"
+	));
+}
+
+/// Removes docker object on drop
+struct DockerCleanup(&'static [&'static str], String);
+impl Drop for DockerCleanup {
+	fn drop(&mut self) {
+		let _ = Command::new("docker")
+			.args(self.0)
+			.arg(&self.1)
+			.stdout(Stdio::null())
+			.stderr(Stdio::null())
+			.status();
+	}
+}
+
+fn docker_checked(args: &[&str]) -> Result<()> {
+	let mut command = Command::new("docker");
+	command
+		.args(args)
+		.stdin(Stdio::null())
+		.stdout(Stdio::null())
+		.stderr(Stdio::inherit());
+	let command_str = format!("{command:?}");
+	if !command.status()?.success() {
+		return Err(Error::DockerCommandFailed(None, None, command_str));
+	}
+	Ok(())
+}
+
+/// Used when neither the spec source nor the backend set the timeout
+pub const DEFAULT_TIMEOUT_SECS: u32 = 25;
+
+/// Environment of build containers, unless overridden by the backend `env_<NAME>` options
+pub const DEFAULT_CONTAINER_ENV: &[(&str, &str)] = &[
+	// Wasm compilation logs are too noisy, github actions can't even handle them
+	("RUST_LOG", "debug,wasmtime_cranelift=info"),
+	("RUST_BACKTRACE", "full"),
+	("COLORBT_SHOW_HIDDEN", "1"),
+];
+
+/// Build container environment from the backend options: `env=replace` starts from the empty
+/// environment instead of [`DEFAULT_CONTAINER_ENV`], `env_<NAME>=<value>` sets the variable, and
+/// removes it if the value is empty.
+///
+/// Options are comma separated, so `;` in values is replaced with `,` (i.e for `RUST_LOG`).
+fn container_env(
+	mode: Option<&str>,
+	overrides: Vec<(&str, &str)>,
+) -> result::Result<Option<Vec<(String, String)>>, String> {
+	let mut env = match mode {
+		None if overrides.is_empty() => return Ok(None),
+		None | Some("merge") => DEFAULT_CONTAINER_ENV
+			.iter()
+			.map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+			.collect::<BTreeMap<_, _>>(),
+		Some("replace") => BTreeMap::new(),
+		Some(other) => {
+			return Err(format!(
+				"unknown env mode {other:?}, expected merge or replace"
+			))
+		}
+	};
+	for (name, value) in overrides {
+		if name.is_empty() || name.contains('=') {
+			return Err(format!("invalid container env variable name: {name:?}"));
+		}
+		if value.is_empty() {
+			env.remove(name);
+		} else {
+			env.insert(name.to_owned(), value.replace(';', ","));
+		}
+	}
+	Ok(Some(env.into_iter().collect()))
+}
+
+/// Signal sent by `timeout` to the build container
+#[derive(Clone, Copy, Default, Debug)]
+pub enum TimeoutSignal {
+	#[default]
+	Int,
+	Term,
+	Kill,
+}
+impl TimeoutSignal {
+	fn as_str(self) -> &'static str {
+		match self {
+			Self::Int => "INT",
+			Self::Term => "TERM",
+			Self::Kill => "KILL",
+		}
+	}
+}
+impl FromStr for TimeoutSignal {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self> {
+		let s = s.to_ascii_uppercase();
+		Ok(match s.strip_prefix("SIG").unwrap_or(&s) {
+			"INT" => Self::Int,
+			"TERM" => Self::Term,
+			"KILL" => Self::Kill,
+			_ => {
+				return Err(Error::InvalidParameter(
+					"timeout signal should be one of INT, TERM, KILL",
+				))
+			}
+		})
+	}
+}
+
+/// `docker run --pull` policy for binaries distributed as docker images
+#[derive(Clone, Copy, Debug)]
+pub enum PullPolicy {
+	Always,
+	Missing,
+	Never,
+}
+impl PullPolicy {
+	/// Without explicit policy, only digest-pinned images are pulled: tag may point to any
+	/// image, and the locally present one is used instead
+	fn infer(image: &str) -> Self {
+		if image.contains('@') {
+			Self::Missing
+		} else {
+			Self::Never
+		}
+	}
+	fn as_str(self) -> &'static str {
+		match self {
+			Self::Always => "always",
+			Self::Missing => "missing",
+			Self::Never => "never",
+		}
+	}
+}
+impl FromStr for PullPolicy {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self> {
+		Ok(match s {
+			"always" => Self::Always,
+			"missing" => Self::Missing,
+			"never" => Self::Never,
+			_ => {
+				return Err(Error::InvalidParameter(
+					"pull policy should be one of always, missing, never",
+				))
+			}
+		})
+	}
+}
+
+/// Per-build override of the backend timeout, unset fields fall back to the backend defaults
+#[derive(Clone, Copy, Default, Debug)]
+pub struct BuildTimeout {
+	pub secs: Option<u32>,
+	pub signal: Option<TimeoutSignal>,
+}
+impl BuildTimeout {
+	pub fn new(secs: Option<u32>, signal: Option<&str>) -> Result<Self> {
+		if secs == Some(0) {
+			return Err(Error::InvalidParameter("timeoutSecs should be positive"));
+		}
+		Ok(Self {
+			secs,
+			signal: signal.map(str::parse).transpose()?,
+		})
+	}
+}
+
+#[derive(Clone, Default)]
+pub struct DockerSpecBuilder {
+	/// Daemon is not local, pass files using volumes instead of bind mounts
+	pub remote: bool,
+	/// Directory for temporary spec files, system temp dir if unset
+	pub tmpdir: Option<PathBuf>,
+	/// Path inside of the container, to which node writes the built spec instead of stdout
+	pub spec_output: Option<String>,
+	/// Container timeout, [`DEFAULT_TIMEOUT_SECS`] if unset
+	pub timeout_secs: Option<u32>,
+	/// Pull policy for images without their own `pull`, inferred from the image reference if unset
+	pub pull: Option<PullPolicy>,
+	/// Build container environment, [`DEFAULT_CONTAINER_ENV`] if unset
+	pub env: Option<Vec<(String, String)>>,
+}
 impl DockerSpecBuilder {
+	/// Extract binary from the archive into the temporary directory
+	fn extract_binary(&self, archive: &ArchiveLocation) -> Result<(TempDir, String)> {
+		let mut builder = Builder::new();
+		builder.prefix("bdk-bin");
+		let dir = match &self.tmpdir {
+			Some(tmpdir) => builder.tempdir_in(tmpdir)?,
+			None => builder.tempdir()?,
+		};
+
+		let file = File::open(&archive.archive)?;
+		let reader: Box<dyn Read> =
+			if archive.archive.ends_with(".gz") || archive.archive.ends_with(".tgz") {
+				Box::new(GzDecoder::new(file))
+			} else {
+				Box::new(file)
+			};
+		let wanted = Path::new(archive.path.trim_start_matches('/'));
+		let name = wanted
+			.file_name()
+			.and_then(|n| n.to_str())
+			.ok_or(Error::InvalidParameter(
+				"archive binary path should point to file",
+			))?
+			.to_owned();
+
+		let mut tar = Archive::new(reader);
+		for entry in tar.entries()? {
+			let mut entry = entry?;
+			let matches = {
+				let path = entry.path()?;
+				path.strip_prefix(".").unwrap_or(&path) == wanted
+			};
+			if !matches {
+				continue;
+			}
+			if !entry.header().entry_type().is_file() {
+				return Err(Error::InvalidParameter(
+					"archive binary path should point to regular file",
+				));
+			}
+			let dest = dir.path().join(&name);
+			entry.unpack(&dest)?;
+			fs::set_permissions(&dest, fs::Permissions::from_mode(0o755))?;
+			return Ok((dir, name));
+		}
+		Err(Error::ArchiveEntryNotFound(
+			archive.path.clone(),
+			archive.archive.clone(),
+		))
+	}
+
+	/// Docker arguments for the keystore bind mount, if requested
+	fn keystore_mount(
+		&self,
+		keystore: Option<&KeystoreMount>,
+	) -> Result<impl FnOnce(&mut Command)> {
+		let mount = match keystore {
+			Some(keystore) => {
+				if self.remote {
+					return Err(Error::InvalidParameter(
+						"keystore can't be mounted with remote docker daemon",
+					));
+				}
+				if let Some(host) = remote_docker_host() {
+					return Err(Error::RemoteDaemon(host));
+				}
+				warn!(
+					"mounting keystore {} into the build container, resulting spec depends on the local secrets",
+					keystore.source
+				);
+				Some((
+					keystore.source.clone(),
+					keystore
+						.target
+						.clone()
+						.unwrap_or_else(|| DEFAULT_KEYSTORE_TARGET.to_owned()),
+				))
+			}
+			None => None,
+		};
+		Ok(move |command: &mut Command| {
+			if let Some((source, target)) = mount {
+				bind_mount(command, &source, &target);
+			}
+		})
+	}
+
+	/// Returned guard holds the binary extracted from the archive and container registration,
+	/// and should be kept until command finishes
 	fn base_command(
+		&self,
 		bin: &FileLocation,
+		timeout: BuildTimeout,
 		extra_docker: impl FnOnce(&mut Command),
-	) -> Result<Command> {
+	) -> Result<(Command, CommandGuard)> {
 		// FIXME: Needs a timeout in case if ENTRYPOINT is bad, and starts the chain when it should perform what we need
 		// to, i.e build-spec. Unfortunately, it can't be done by docker itself: https://github.com/moby/moby/issues/1905
 		//
 		// Run command in a different thread (since we're use blocking APIs), and in 25 seconds force-stop the container?
 		//
 		// FIXME: Temporary solution was implemented using timeout command, it is not portable, but it will send SIGINT
-		// in 25 seconds, and docker will cleanup the container itself due to --rm.
+		// in 25 seconds (unless overridden), and docker will cleanup the container itself due to --rm.
+		let secs = timeout
+			.secs
+			.or(self.timeout_secs)
+			.unwrap_or(DEFAULT_TIMEOUT_SECS)
+			.to_string();
+		let container = RunningContainer::register();
 		let mut command = Command::new("timeout");
 		command
-			.args(["-s", "INT", "25"])
+			.args([
+				"-s",
+				timeout.signal.unwrap_or_default().as_str(),
+				secs.as_str(),
+			])
 			.arg("docker")
 			.arg("run")
 			.arg("--rm")
-			.args([
-				"-e",
-				// Wasm compilation logs are too noisy, github actions can't even handle them
-				"RUST_LOG=debug,wasmtime_cranelift=info",
-				"-e",
-				"RUST_BACKTRACE=full",
-				"-e",
-				"COLORBT_SHOW_HIDDEN=1",
-			]);
-		if let Some(image) = &bin.docker_image {
-			// Digest is known, nothing wrong will happen if we try to pull this image
-			if image.contains('@') {
-				command.args(["--pull", "missing"]);
-			} else {
-				command.args(["--pull", "never"]);
+			.args(["--name", container.name()]);
+		match &self.env {
+			Some(env) => {
+				for (name, value) in env {
+					command.arg("-e").arg(format!("{name}={value}"));
+				}
+			}
+			None => {
+				for (name, value) in DEFAULT_CONTAINER_ENV {
+					command.arg("-e").arg(format!("{name}={value}"));
+				}
+			}
+		}
+		let mut extracted = None;
+		if let Some(archive) = &bin.archive {
+			if self.remote {
+				return Err(Error::InvalidParameter(
+					"archived binaries can't be used with remote docker daemon",
+				));
+			}
+			if let Some(host) = remote_docker_host() {
+				return Err(Error::RemoteDaemon(host));
+			}
+			let (dir, name) = self.extract_binary(archive)?;
+			command.args(["--pull", offline::pull_policy("missing")]);
+			// Same as for local binaries, dynamically linked libraries are taken from the host
+			for mount in docker_mounts()? {
+				bind_mount(&mut command, &format!("/{mount}"), &format!("/{mount}"));
 			}
+			let dir_str = dir
+				.path()
+				.to_str()
+				.expect("no reason for tempdir to be non-utf8");
+			bind_mount(&mut command, dir_str, ARCHIVE_BIN_DIR);
+			extra_docker(&mut command);
+			command.arg(EMPTY_IMAGE);
+			command.arg(format!("{ARCHIVE_BIN_DIR}/{name}"));
+			extracted = Some(dir);
+		} else if let Some(image) = &bin.docker_image {
+			if let Some(tar) = &bin.image_tar {
+				ensure_image_loaded(image, tar)?;
+			}
+			let pull = offline::pull_policy(self.pull_policy(bin, image).as_str());
+			debug!("pull policy for {image}: {pull}");
+			command.args(["--pull", pull]);
 			extra_docker(&mut command);
 			if let Some(docker) = &bin.docker {
 				command.args(["--entrypoint", docker.as_str()]);
 			}
 			command.arg(image);
 		} else {
+			if self.remote {
+				return Err(Error::InvalidParameter(
+					"local binaries can't be used with remote docker daemon",
+				));
+			}
+			if let Some(host) = remote_docker_host() {
+				return Err(Error::RemoteDaemon(host));
+			}
 			// Digest is explicitly set
-			command.args(["--pull", "missing"]);
+			command.args(["--pull", offline::pull_policy("missing")]);
 			for mount in docker_mounts()? {
-				command.arg("--mount").arg(format!(
-					"type=bind,source=/{mount},target=/{mount},readonly"
-				));
+				bind_mount(&mut command, &format!("/{mount}"), &format!("/{mount}"));
 			}
 			extra_docker(&mut command);
 			command.arg(EMPTY_IMAGE);
@@ -142,36 +690,154 @@ impl DockerSpecBuilder {
 		}
 		command.stdin(Stdio::null());
 		command.stderr(Stdio::inherit());
-		Ok(command)
+		Ok((
+			command,
+			CommandGuard {
+				_extracted: extracted,
+				_container: container,
+			},
+		))
 	}
-}
-impl SpecBuilder for DockerSpecBuilder {
-	fn build_genesis(&self, bin: &FileLocation, chain: Option<String>) -> Result<Vec<u8>> {
-		let mut command = Self::base_command(bin, |_c| {})?;
-		command.args(["build-spec", "--base-path", "/tmp/node"]);
+
+	fn tempfile(&self, builder: &Builder) -> Result<NamedTempFile> {
+		Ok(match &self.tmpdir {
+			Some(dir) => builder.tempfile_in(dir)?,
+			None => builder.tempfile()?,
+		})
+	}
+
+	/// Check that the daemon is reachable and can run containers the same way spec builds do
+	pub fn self_test(&self) -> Result<()> {
+		let container = RunningContainer::register();
+		let mut command = Command::new("docker");
+		command
+			.args(["run", "--rm", "--name", container.name()])
+			.args(["--pull", offline::pull_policy("missing")]);
+		if self.remote {
+			// Nothing can be mounted, only check that the daemon runs containers at all
+			command.args(["--entrypoint", "/none", EMPTY_IMAGE]);
+		} else {
+			if let Some(host) = remote_docker_host() {
+				return Err(Error::RemoteDaemon(host));
+			}
+			// Same as for local binaries, host binary is run in the empty image
+			let true_bin = ["/usr/bin/true", "/bin/true"]
+				.into_iter()
+				.find(|p| Path::new(p).exists())
+				.ok_or(Error::InvalidParameter("no `true` binary found on host"))?;
+			for mount in docker_mounts()? {
+				bind_mount(&mut command, &format!("/{mount}"), &format!("/{mount}"));
+			}
+			command.args([EMPTY_IMAGE, true_bin]);
+		}
+		let command_str = format!("{command:?}");
+		let output = command.stdin(Stdio::null()).output()?;
+		let stderr = String::from_utf8_lossy(&output.stderr);
+		// Image has no binaries, so failure to start `/none` means container was created fine
+		let remote_ok = self.remote && stderr.contains("/none");
+		if !output.status.success() && !remote_ok {
+			return Err(Error::DockerCommandFailed(None, None, command_str));
+		}
+		Ok(())
+	}
+
+	fn pull_policy(&self, bin: &FileLocation, image: &str) -> PullPolicy {
+		bin.pull
+			.or(self.pull)
+			.unwrap_or_else(|| PullPolicy::infer(image))
+	}
+
+	/// Run command, redirecting its stdout to the temporary file
+	fn run_captured(&self, command: &mut Command) -> Result<(ExitStatus, NodeOutput)> {
+		let mut builder = Builder::new();
+		builder.prefix("bdk-output");
+		let stdout = self.tempfile(&builder)?;
+		command.stdout(stdout.reopen()?);
+		let status = command.status()?;
+		Ok((status, NodeOutput(stdout)))
+	}
+
+	/// Run `build-spec` from the chain, capturing spec from stdout, or from the `spec_output` file
+	/// if node printed nothing
+	fn run_build_spec(
+		&self,
+		bin: &FileLocation,
+		chain: Option<String>,
+		keystore: Option<&KeystoreMount>,
+		timeout: BuildTimeout,
+		args: &[&str],
+	) -> Result<NodeOutput> {
+		let spec_output = match &self.spec_output {
+			Some(target) => {
+				if self.remote {
+					return Err(Error::InvalidParameter(
+						"spec_output can't be used with remote docker daemon",
+					));
+				}
+				if let Some(host) = remote_docker_host() {
+					return Err(Error::RemoteDaemon(host));
+				}
+				let mut builder = Builder::new();
+				builder
+					.prefix("bdk-spec-output")
+					// Node is not necessarily running as the same user
+					.permissions(fs::Permissions::from_mode(0o666));
+				Some((self.tempfile(&builder)?, target))
+			}
+			None => None,
+		};
+		let keystore = self.keystore_mount(keystore)?;
+		let (mut command, _guard) = self.base_command(bin, timeout, |command| {
+			keystore(command);
+			if let Some((file, target)) = &spec_output {
+				let source = file.path().to_str().expect("tempfile path is utf-8");
+				command
+					.arg("--mount")
+					.arg(format!("type=bind,source={source},target={target}"));
+			}
+		})?;
+		command.args(args);
 		if let Some(chain) = chain {
 			command.args(["--chain", &chain]);
 		}
 		let command_str = format!("{command:?}");
-		let output = command.output()?;
-		if !output.status.success() {
-			return Err(Error::DockerCommandFailed(PathBuf::default(), command_str));
+		let (status, output) = self.run_captured(&mut command)?;
+		let stage = args.first().copied().unwrap_or("build");
+		keep_file(&format!("{stage}-stdout"), output.0.path())?;
+		if let Some((file, _)) = &spec_output {
+			keep_file(&format!("{stage}-spec-output"), file.path())?;
+		}
+		if !status.success() {
+			return Err(Error::DockerCommandFailed(
+				None,
+				Some(retain(output.0)),
+				command_str,
+			));
 		}
-		Ok(output.stdout)
+		if let Some((file, target)) = spec_output {
+			if output.is_empty()? {
+				info!("node printed nothing, reading spec from {target}");
+				return Ok(NodeOutput(file));
+			}
+		}
+		Ok(output)
 	}
 
-	fn build_raw(
+	/// Run node command with the spec file mounted into the container, and passed as `--chain`
+	fn run_with_spec(
 		&self,
 		bin: &FileLocation,
 		spec_file_prefix: Option<String>,
 		spec: String,
-	) -> Result<Vec<u8>> {
+		timeout: BuildTimeout,
+		args: &[&str],
+	) -> Result<NodeOutput> {
 		let mut tempfile = Builder::new();
 		tempfile.permissions(fs::Permissions::from_mode(0o644));
 		if let Some(prefix) = &spec_file_prefix {
 			tempfile.prefix(prefix);
 		}
-		let mut spec_json = tempfile.tempfile()?;
+		let mut spec_json = self.tempfile(&tempfile)?;
 		spec_json.write_all(spec.as_bytes())?;
 		spec_json.flush()?;
 		let spec_path = spec_json
@@ -179,29 +845,149 @@ impl SpecBuilder for DockerSpecBuilder {
 			.to_str()
 			.expect("no reason for tempfile to be non-utf8");
 
-		let mut command = Self::base_command(bin, |c| {
-			c.arg("--mount").arg(format!(
-				// FIXME: Moonbeam wants the spec json file to be named after runtime
-				"type=bind,source={spec_path},target=/tmp/spec.json,readonly"
-			));
+		// Remote daemon can't see our files, spec is uploaded to the volume instead
+		let mut _cleanup = None;
+		let (mount, chain) = if self.remote {
+			let volume = format!("bdk-spec-{:016x}", rand::random::<u64>());
+			docker_checked(&["volume", "create", &volume])?;
+			_cleanup = Some(DockerCleanup(&["volume", "rm", "-f"], volume.clone()));
+
+			let uploader = format!("{volume}-upload");
+			docker_checked(&[
+				"container",
+				"create",
+				"--name",
+				&uploader,
+				"--mount",
+				&format!("type=volume,source={volume},target=/spec"),
+				EMPTY_IMAGE,
+				// Container is never started, but command is required
+				"/none",
+			])?;
+			let _uploader_cleanup = DockerCleanup(&["rm", "-f"], uploader.clone());
+			docker_checked(&[
+				"cp",
+				spec_path,
+				&format!("{uploader}:/spec/{SPEC_FILE_NAME}"),
+			])?;
+
+			spec_mount(SpecMountSource::Volume(&volume))
+		} else {
+			if let Some(host) = remote_docker_host() {
+				return Err(Error::RemoteDaemon(host));
+			}
+			spec_mount(SpecMountSource::Bind(spec_path))
+		};
+		debug!("spec is mounted into the container as {chain}");
+
+		let (mut command, _guard) = self.base_command(bin, timeout, |c| {
+			c.arg("--mount").arg(mount);
 		})?;
 		command
-			.args(["build-spec", "--raw", "--base-path", "/tmp/node"])
-			.args(["--chain", "/tmp/spec.json"]);
+			.args(args)
+			.args(["--base-path", "/tmp/node"])
+			.args(["--chain", &chain]);
 		let command_str = format!("{command:?}");
-		let output = command.output()?;
-		if !output.status.success() {
+		let (status, output) = self.run_captured(&mut command)?;
+		let stage = args.first().copied().unwrap_or("build");
+		keep_file(&format!("{stage}-input"), spec_json.path())?;
+		keep_file(&format!("{stage}-stdout"), output.0.path())?;
+		if !status.success() {
 			return Err(Error::DockerCommandFailed(
-				{
-					let path = spec_json.into_temp_path();
-					let buf = path.to_path_buf();
-					std::mem::forget(path);
-					buf
-				},
+				Some(retain(spec_json)),
+				Some(retain(output.0)),
 				command_str,
 			));
 		}
-		Ok(output.stdout)
+		Ok(output)
+	}
+}
+impl SpecBuilder for DockerSpecBuilder {
+	fn build_genesis(
+		&self,
+		bin: &FileLocation,
+		chain: Option<String>,
+		keystore: Option<&KeystoreMount>,
+		timeout: BuildTimeout,
+	) -> Result<NodeOutput> {
+		self.run_build_spec(
+			bin,
+			chain,
+			keystore,
+			timeout,
+			&["build-spec", "--base-path", "/tmp/node"],
+		)
+	}
+
+	fn build_genesis_raw(
+		&self,
+		bin: &FileLocation,
+		chain: Option<String>,
+		keystore: Option<&KeystoreMount>,
+		timeout: BuildTimeout,
+	) -> Result<NodeOutput> {
+		self.run_build_spec(
+			bin,
+			chain,
+			keystore,
+			timeout,
+			&["build-spec", "--raw", "--base-path", "/tmp/node"],
+		)
+	}
+
+	fn build_raw(
+		&self,
+		bin: &FileLocation,
+		spec_file_prefix: Option<String>,
+		spec: String,
+		timeout: BuildTimeout,
+	) -> Result<NodeOutput> {
+		self.run_with_spec(
+			bin,
+			spec_file_prefix,
+			spec,
+			timeout,
+			&["build-spec", "--raw"],
+		)
+	}
+
+	fn build_genesis_state(
+		&self,
+		bin: &FileLocation,
+		spec_file_prefix: Option<String>,
+		raw_spec: String,
+	) -> Result<NodeOutput> {
+		self.run_with_spec(
+			bin,
+			spec_file_prefix,
+			raw_spec,
+			BuildTimeout::default(),
+			&["export-genesis-state"],
+		)
+	}
+
+	fn build_genesis_wasm(
+		&self,
+		bin: &FileLocation,
+		spec_file_prefix: Option<String>,
+		raw_spec: String,
+	) -> Result<NodeOutput> {
+		self.run_with_spec(
+			bin,
+			spec_file_prefix,
+			raw_spec,
+			BuildTimeout::default(),
+			&["export-genesis-wasm"],
+		)
+	}
+
+	fn pulls_on_every_run(&self, bin: &FileLocation) -> bool {
+		if bin.archive.is_some() || offline::is_offline() {
+			return false;
+		}
+		bin.docker_image
+			.as_deref()
+			.is_some_and(|image| matches!(self.pull_policy(bin, image), PullPolicy::Always))
 	}
 }
 
@@ -213,7 +999,32 @@ pub struct GenesisSpecSource {
 	pub spec_file_prefix: Option<String>,
 	#[typed(rename = "modifyRaw")]
 	pub modify_raw: Option<FuncVal>,
+	/// Build raw spec in the same container as genesis, only possible when `modify` is not set
+	#[typed(rename = "singleContainer")]
+	pub single_container: Option<bool>,
+	/// Make node keystore available to `build-spec`, for runtimes deriving genesis from it
+	#[typed(rename = "keystoreMount")]
+	pub keystore_mount: Option<KeystoreMount>,
+	/// Override of the backend container timeout for builds of this spec
+	#[typed(rename = "timeoutSecs")]
+	pub timeout_secs: Option<u32>,
+	/// Signal sent on timeout, one of INT, TERM, KILL
+	#[typed(rename = "timeoutSignal")]
+	pub timeout_signal: Option<String>,
+}
+impl GenesisSpecSource {
+	pub fn timeout(&self) -> Result<BuildTimeout> {
+		BuildTimeout::new(self.timeout_secs, self.timeout_signal.as_deref())
+	}
 }
+/// Read-only bind mount of the local keystore directory (`localKeystoreDir` of `bdk.ensureKeys`)
+#[derive(Typed, Trace, Clone)]
+pub struct KeystoreMount {
+	pub source: String,
+	/// Path inside of the build container, defaults to [`DEFAULT_KEYSTORE_TARGET`]
+	pub target: Option<String>,
+}
+pub const DEFAULT_KEYSTORE_TARGET: &str = "/tmp/bdk-keystore";
 #[derive(Typed, Trace, Clone)]
 pub struct RawSpecSource {
 	pub raw_spec: Val,
@@ -224,12 +1035,29 @@ pub struct FromScratchGenesisSpecSource {
 	pub spec_file_prefix: Option<String>,
 	#[typed(rename = "modifyRaw")]
 	pub modify_raw: Option<FuncVal>,
+	#[typed(rename = "timeoutSecs")]
+	pub timeout_secs: Option<u32>,
+	#[typed(rename = "timeoutSignal")]
+	pub timeout_signal: Option<String>,
+}
+impl FromScratchGenesisSpecSource {
+	pub fn timeout(&self) -> Result<BuildTimeout> {
+		BuildTimeout::new(self.timeout_secs, self.timeout_signal.as_deref())
+	}
+}
+/// Previously built raw spec, no node binary is run for it
+#[derive(Typed, Trace, Clone)]
+pub struct ExistingRawFileSpecSource {
+	pub path: String,
+	#[typed(rename = "modifyRaw")]
+	pub modify_raw: Option<FuncVal>,
 }
 #[derive(Trace, Clone)]
 pub enum SpecSource {
 	Genesis(GenesisSpecSource),
 	Raw(RawSpecSource),
 	FromScratchGenesis(FromScratchGenesisSpecSource),
+	ExistingRawFile(ExistingRawFileSpecSource),
 }
 const _: () = {
 	use jrsonnet_evaluator::Result;
@@ -246,6 +1074,9 @@ const _: () = {
 				SpecSource::FromScratchGenesis(g) => out
 					.field("FromScratchGenesis")
 					.value(FromScratchGenesisSpecSource::into_untyped(g)?),
+				SpecSource::ExistingRawFile(f) => out
+					.field("ExistingRawFile")
+					.value(ExistingRawFileSpecSource::into_untyped(f)?),
 			}
 			Ok(Val::Obj(out.build()))
 		}
@@ -268,17 +1099,169 @@ const _: () = {
 						obj.get("FromScratchGenesis".into())?.unwrap(),
 					)?)
 				}
+				"ExistingRawFile" => {
+					Self::ExistingRawFile(ExistingRawFileSpecSource::from_untyped(
+						obj.get("ExistingRawFile".into())?.unwrap(),
+					)?)
+				}
 				v => bail!("unknown enum value: {:?}", v),
 			})
 		}
 	}
 };
 
+/// Image references, pinned during this run, keyed by the original tag
+static PINNED_IMAGES: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/// Resolve image tag to the `image:tag@sha256:...` reference of the locally present image
+fn pin_image_ref(image: &str) -> Result<String> {
+	if image.contains('@') {
+		return Ok(image.to_owned());
+	}
+	if let Some(pinned) = PINNED_IMAGES.lock().expect("not poisoned").get(image) {
+		return Ok(pinned.clone());
+	}
+	let output = Command::new("docker")
+		.args([
+			"image",
+			"inspect",
+			"--format",
+			"{{join .RepoDigests \"\\n\"}}",
+		])
+		.arg(image)
+		.stderr(Stdio::inherit())
+		.output()?;
+	if !output.status.success() {
+		return Err(Error::ImagePin(
+			image.to_owned(),
+			"docker image inspect failed, is the image pulled?",
+		));
+	}
+	let digests = String::from_utf8_lossy(&output.stdout);
+	let Some((_, digest)) = digests.lines().find_map(|d| d.rsplit_once('@')) else {
+		return Err(Error::ImagePin(
+			image.to_owned(),
+			"image has no repository digest, locally built images can't be pinned",
+		));
+	};
+	let pinned = format!("{image}@{digest}");
+	PINNED_IMAGES
+		.lock()
+		.expect("not poisoned")
+		.insert(image.to_owned(), pinned.clone());
+	Ok(pinned)
+}
+
+/// Image tarballs, which were already loaded during this run
+static LOADED_TARS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Make image available to the daemon by loading it from the `docker save` tarball, unless it is
+/// already present
+fn ensure_image_loaded(image: &str, tar: &str) -> Result<()> {
+	let mut loaded = LOADED_TARS.lock().expect("not poisoned");
+	if loaded.contains(tar) {
+		return Ok(());
+	}
+	let present = Command::new("docker")
+		.args(["image", "inspect", "--format", "{{.Id}}"])
+		.arg(image)
+		.stdout(Stdio::null())
+		.stderr(Stdio::null())
+		.status()?
+		.success();
+	if !present {
+		info!("loading image {image} from {tar}");
+		let status = Command::new("docker")
+			.args(["load", "-i"])
+			.arg(tar)
+			.stdout(Stdio::null())
+			.status()?;
+		if !status.success() {
+			return Err(Error::DockerCommandFailed(
+				None,
+				None,
+				format!("docker load -i {tar}"),
+			));
+		}
+	}
+	loaded.insert(tar.to_owned());
+	Ok(())
+}
+
+/// Binary, packed inside of the tar archive (optionally gzip-compressed)
+#[derive(Clone, Trace, Typed)]
+pub struct ArchiveLocation {
+	archive: String,
+	/// Path of the binary inside of the archive
+	path: String,
+}
+
 #[derive(Clone, Trace)]
 pub struct FileLocation {
 	local: Option<String>,
 	docker_image: Option<String>,
 	docker: Option<String>,
+	archive: Option<ArchiveLocation>,
+	/// `docker save` tarball, from which `docker_image` is loaded if missing
+	image_tar: Option<String>,
+	/// Overrides pull policy of the backend for `docker_image`
+	#[trace(skip)]
+	pull: Option<PullPolicy>,
+}
+impl FileLocation {
+	/// Identifies binary contents: local files by size and mtime, images by id.
+	/// `None` if binary can't be identified without running it, i.e image is not yet present.
+	fn fingerprint(&self) -> Result<Option<String>> {
+		fn file(path: &str) -> Result<String> {
+			let meta = fs::metadata(path)?;
+			let mtime = meta
+				.modified()?
+				.duration_since(std::time::UNIX_EPOCH)
+				.unwrap_or_default();
+			Ok(format!("{path}:{}:{}", meta.len(), mtime.as_nanos()))
+		}
+		if let Some(archive) = &self.archive {
+			return Ok(Some(format!(
+				"archive:{}:{}",
+				file(&archive.archive)?,
+				archive.path
+			)));
+		}
+		if let Some(image) = &self.docker_image {
+			if let Some(tar) = &self.image_tar {
+				ensure_image_loaded(image, tar)?;
+			}
+			let output = Command::new("docker")
+				.args(["image", "inspect", "--format", "{{.Id}}"])
+				.arg(image)
+				.stderr(Stdio::null())
+				.output()?;
+			if !output.status.success() {
+				return Ok(None);
+			}
+			let id = String::from_utf8_lossy(&output.stdout);
+			return Ok(Some(format!(
+				"image:{}:{}",
+				id.trim(),
+				self.docker.as_deref().unwrap_or_default()
+			)));
+		}
+		match &self.local {
+			Some(local) => Ok(Some(format!("local:{}", file(local)?))),
+			None => Err(Error::BinaryNotSet),
+		}
+	}
+
+	/// Replace docker image tag with the digest-pinned reference
+	pub fn pin_image(mut self) -> Result<Self> {
+		if let Some(image) = &self.docker_image {
+			if let Some(tar) = &self.image_tar {
+				ensure_image_loaded(image, tar)?;
+			}
+			self.docker_image = Some(pin_image_ref(image)?);
+		}
+		Ok(self)
+	}
 }
 const _: () = {
 	use jrsonnet_evaluator::Result;
@@ -288,18 +1271,27 @@ const _: () = {
 		docker: Option<String>,
 		#[typed(rename = "dockerImage")]
 		docker_image: String,
+		#[typed(rename = "imageTar")]
+		image_tar: Option<String>,
+		/// `always`, `missing` or `never`
+		pull: Option<String>,
 	}
-	type Eith = Either!(String, FileLocationLocal);
+	type Eith = Either!(String, FileLocationLocal, ArchiveLocation);
 	impl Typed for FileLocation {
 		const TYPE: &'static ComplexValType = Eith::TYPE;
 
 		fn into_untyped(typed: Self) -> Result<Val> {
+			if let Some(archive) = typed.archive {
+				return ArchiveLocation::into_untyped(archive);
+			}
 			match (typed.local, typed.docker, typed.docker_image) {
 				(None, docker, Some(docker_image)) => {
 					FileLocationLocal::into_untyped(FileLocationLocal {
 						local: None,
 						docker,
 						docker_image,
+						image_tar: typed.image_tar,
+						pull: typed.pull.map(|p| p.as_str().to_owned()),
 					})
 				}
 				(Some(local), None, None) => Ok(Val::Str(local.into())),
@@ -308,6 +1300,8 @@ const _: () = {
 						local: Some(local),
 						docker,
 						docker_image,
+						image_tar: typed.image_tar,
+						pull: typed.pull.map(|p| p.as_str().to_owned()),
 					})
 				}
 				_ => unreachable!("either docker or local location should be set"),
@@ -316,15 +1310,29 @@ const _: () = {
 
 		fn from_untyped(untyped: Val) -> Result<Self> {
 			Ok(match Eith::from_untyped(untyped)? {
-				Either2::A(path) => FileLocation {
+				Either3::A(path) => FileLocation {
 					local: Some(path),
 					docker: None,
 					docker_image: None,
+					archive: None,
+					image_tar: None,
+					pull: None,
 				},
-				Either2::B(found) => FileLocation {
+				Either3::B(found) => FileLocation {
 					local: found.local,
 					docker: found.docker,
 					docker_image: Some(found.docker_image),
+					archive: None,
+					image_tar: found.image_tar,
+					pull: found.pull.as_deref().map(str::parse).transpose()?,
+				},
+				Either3::C(archive) => FileLocation {
+					local: None,
+					docker: None,
+					docker_image: None,
+					archive: Some(archive),
+					image_tar: None,
+					pull: None,
 				},
 			})
 		}
@@ -334,24 +1342,88 @@ const _: () = {
 #[derive(Default, Clone)]
 pub enum SpecBackend {
 	Docker(DockerSpecBuilder),
+	/// Returns placeholder outputs without running anything, used by `bdk render`
+	Stub,
 	#[default]
 	Unset,
 }
 impl FromStr for SpecBackend {
-	type Err = &'static str;
+	type Err = String;
 
 	fn from_str(s: &str) -> result::Result<Self, Self::Err> {
-		Ok(match s {
-			"docker" => Self::Docker(DockerSpecBuilder),
-			_ => Self::Unset,
-		})
+		let mut opts = Options::parse(s)?;
+		let backend = match opts.value {
+			"docker" => Self::Docker(DockerSpecBuilder {
+				remote: opts.take_parsed("remote")?.unwrap_or(false),
+				tmpdir: opts.take("tmpdir").map(PathBuf::from),
+				spec_output: opts.take("spec_output").map(str::to_owned),
+				timeout_secs: match opts.take_parsed::<u32>("timeout")? {
+					Some(0) => return Err("timeout should be positive".to_owned()),
+					v => v,
+				},
+				pull: opts
+					.take("pull")
+					.map(str::parse)
+					.transpose()
+					.map_err(|e: Error| e.to_string())?,
+				env: {
+					let mode = opts.take("env");
+					container_env(mode, opts.take_prefixed("env_"))?
+				},
+			}),
+			other => {
+				return Err(format!(
+					"unknown spec backend: {other:?}, available:\n  {}",
+					Self::FORMS.join("\n  ")
+				))
+			}
+		};
+		opts.finish()?;
+		Ok(backend)
+	}
+}
+impl SpecBackend {
+	/// Forms accepted by [`FromStr`], printed by `--list-spec-backends`
+	pub const FORMS: &'static [&'static str] = &[
+		"docker[,remote=true][,tmpdir=<path>][,spec_output=<container path>][,timeout=<secs>][,pull=always|missing|never][,env=merge|replace][,env_<NAME>=<value>]",
+		"<name>=<any of the above>",
+	];
+
+	pub fn self_test(&self) -> Result<()> {
+		match self {
+			SpecBackend::Docker(d) => d.self_test(),
+			SpecBackend::Stub => Ok(()),
+			SpecBackend::Unset => Err(Error::InvalidParameter("spec backend is not set")),
+		}
 	}
 }
 impl SpecBuilder for SpecBackend {
-	fn build_genesis(&self, bin: &FileLocation, chain: Option<String>) -> Result<Vec<u8>> {
+	fn build_genesis(
+		&self,
+		bin: &FileLocation,
+		chain: Option<String>,
+		keystore: Option<&KeystoreMount>,
+		timeout: BuildTimeout,
+	) -> Result<NodeOutput> {
 		info!("building genesis, chain={chain:?}");
 		match self {
-			SpecBackend::Docker(d) => d.build_genesis(bin, chain),
+			SpecBackend::Docker(d) => d.build_genesis(bin, chain, keystore, timeout),
+			SpecBackend::Stub => stub_spec(None, false),
+			SpecBackend::Unset => Err(Error::InvalidParameter("spec backend is not set")),
+		}
+	}
+
+	fn build_genesis_raw(
+		&self,
+		bin: &FileLocation,
+		chain: Option<String>,
+		keystore: Option<&KeystoreMount>,
+		timeout: BuildTimeout,
+	) -> Result<NodeOutput> {
+		info!("building genesis and raw in single container, chain={chain:?}");
+		match self {
+			SpecBackend::Docker(d) => d.build_genesis_raw(bin, chain, keystore, timeout),
+			SpecBackend::Stub => stub_spec(None, true),
 			SpecBackend::Unset => Err(Error::InvalidParameter("spec backend is not set")),
 		}
 	}
@@ -361,11 +1433,258 @@ impl SpecBuilder for SpecBackend {
 		bin: &FileLocation,
 		spec_file_prefix: Option<String>,
 		spec: String,
-	) -> Result<Vec<u8>> {
+		timeout: BuildTimeout,
+	) -> Result<NodeOutput> {
 		info!("building raw");
 		match self {
-			SpecBackend::Docker(d) => d.build_raw(bin, spec_file_prefix, spec),
+			SpecBackend::Docker(d) => d.build_raw(bin, spec_file_prefix, spec, timeout),
+			SpecBackend::Stub => stub_spec(Some(&spec), true),
+			SpecBackend::Unset => Err(Error::InvalidParameter("spec backend is not set")),
+		}
+	}
+
+	fn build_genesis_state(
+		&self,
+		bin: &FileLocation,
+		spec_file_prefix: Option<String>,
+		raw_spec: String,
+	) -> Result<NodeOutput> {
+		info!("exporting genesis state");
+		match self {
+			SpecBackend::Docker(d) => d.build_genesis_state(bin, spec_file_prefix, raw_spec),
+			SpecBackend::Stub => stub_output(b"0x"),
+			SpecBackend::Unset => Err(Error::InvalidParameter("spec backend is not set")),
+		}
+	}
+
+	fn build_genesis_wasm(
+		&self,
+		bin: &FileLocation,
+		spec_file_prefix: Option<String>,
+		raw_spec: String,
+	) -> Result<NodeOutput> {
+		info!("exporting genesis wasm");
+		match self {
+			SpecBackend::Docker(d) => d.build_genesis_wasm(bin, spec_file_prefix, raw_spec),
+			SpecBackend::Stub => stub_output(b"0x"),
 			SpecBackend::Unset => Err(Error::InvalidParameter("spec backend is not set")),
 		}
 	}
+
+	fn pulls_on_every_run(&self, bin: &FileLocation) -> bool {
+		match self {
+			SpecBackend::Docker(d) => d.pulls_on_every_run(bin),
+			SpecBackend::Stub | SpecBackend::Unset => false,
+		}
+	}
+}
+
+fn stub_output(data: &[u8]) -> Result<NodeOutput> {
+	let mut file = NamedTempFile::new()?;
+	file.write_all(data)?;
+	Ok(NodeOutput(file))
+}
+
+/// Placeholder spec with empty genesis, raw spec keeps everything but the genesis of the input
+fn stub_spec(input: Option<&str>, raw: bool) -> Result<NodeOutput> {
+	let mut spec: serde_json::Value = match input {
+		Some(input) => serde_json::from_str(input)?,
+		None => serde_json::json!({
+			"name": "bdk render placeholder",
+			"id": "placeholder",
+			"chainType": "Local",
+			"bootNodes": [],
+			"properties": {},
+		}),
+	};
+	let Some(fields) = spec.as_object_mut() else {
+		return Err(Error::InvalidParameter("spec should be an object"));
+	};
+	let genesis = if raw {
+		serde_json::json!({ "raw": { "top": {}, "childrenDefault": {} } })
+	} else {
+		serde_json::json!({ "runtime": {} })
+	};
+	fields.insert("genesis".to_owned(), genesis);
+	stub_output(&serde_json::to_vec(&spec)?)
+}
+
+/// Spec backend, optionally named with the `<name>=` prefix, i.e `relay=docker,remote=true`
+#[derive(Clone)]
+pub struct NamedSpecBackend {
+	pub name: Option<String>,
+	pub backend: SpecBackend,
+}
+impl FromStr for NamedSpecBackend {
+	type Err = String;
+
+	fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+		let head = s.split_once(',').map_or(s, |(head, _)| head);
+		if let Some((name, _)) = head.split_once('=') {
+			if name.is_empty() {
+				return Err("spec backend name should not be empty".to_owned());
+			}
+			Ok(Self {
+				name: Some(name.to_owned()),
+				backend: s[name.len() + 1..].parse()?,
+			})
+		} else {
+			Ok(Self {
+				name: None,
+				backend: s.parse()?,
+			})
+		}
+	}
+}
+
+/// Configured spec backends, selected by the `backend` argument of spec builtins
+pub struct SpecBuilders {
+	named: BTreeMap<String, Rc<dyn SpecBuilder>>,
+	/// Unnamed backend, or the only configured one
+	default: Option<Rc<dyn SpecBuilder>>,
+}
+impl SpecBuilders {
+	pub fn new(backends: impl IntoIterator<Item = (Option<String>, Rc<dyn SpecBuilder>)>) -> Self {
+		let mut named = BTreeMap::new();
+		let mut default = None;
+		for (name, backend) in backends {
+			match name {
+				Some(name) => {
+					named.insert(name, backend);
+				}
+				None => default = Some(backend),
+			}
+		}
+		if default.is_none() && named.len() == 1 {
+			default = named.values().next().cloned();
+		}
+		Self { named, default }
+	}
+
+	pub fn get(&self, name: Option<&str>) -> Result<Rc<dyn SpecBuilder>> {
+		match name {
+			Some(name) => self.named.get(name).cloned().ok_or_else(|| {
+				Error::UnknownBackend(
+					name.to_owned(),
+					self.named.keys().cloned().collect::<Vec<_>>().join(", "),
+				)
+			}),
+			None if self.named.len() > 1 && self.default.is_none() => Err(Error::InvalidParameter(
+				"multiple spec backends are configured, select one with the backend argument",
+			)),
+			None => Ok(self
+				.default
+				.clone()
+				.unwrap_or_else(|| Rc::new(SpecBackend::Unset))),
+		}
+	}
+}
+
+/// Spec builder, which doesn't run more than the allowed number of containers at once
+pub struct LimitedSpecBuilder<B> {
+	pub inner: B,
+	pub limiter: Arc<ContainerLimiter>,
+}
+impl<B: SpecBuilder> SpecBuilder for LimitedSpecBuilder<B> {
+	fn build_genesis(
+		&self,
+		bin: &FileLocation,
+		chain: Option<String>,
+		keystore: Option<&KeystoreMount>,
+		timeout: BuildTimeout,
+	) -> Result<NodeOutput> {
+		let _permit = self.limiter.acquire();
+		self.inner.build_genesis(bin, chain, keystore, timeout)
+	}
+
+	fn build_genesis_raw(
+		&self,
+		bin: &FileLocation,
+		chain: Option<String>,
+		keystore: Option<&KeystoreMount>,
+		timeout: BuildTimeout,
+	) -> Result<NodeOutput> {
+		let _permit = self.limiter.acquire();
+		self.inner.build_genesis_raw(bin, chain, keystore, timeout)
+	}
+
+	fn build_raw(
+		&self,
+		bin: &FileLocation,
+		spec_file_prefix: Option<String>,
+		spec: String,
+		timeout: BuildTimeout,
+	) -> Result<NodeOutput> {
+		let _permit = self.limiter.acquire();
+		self.inner.build_raw(bin, spec_file_prefix, spec, timeout)
+	}
+
+	fn build_genesis_state(
+		&self,
+		bin: &FileLocation,
+		spec_file_prefix: Option<String>,
+		raw_spec: String,
+	) -> Result<NodeOutput> {
+		let _permit = self.limiter.acquire();
+		self.inner
+			.build_genesis_state(bin, spec_file_prefix, raw_spec)
+	}
+
+	fn build_genesis_wasm(
+		&self,
+		bin: &FileLocation,
+		spec_file_prefix: Option<String>,
+		raw_spec: String,
+	) -> Result<NodeOutput> {
+		let _permit = self.limiter.acquire();
+		self.inner
+			.build_genesis_wasm(bin, spec_file_prefix, raw_spec)
+	}
+
+	fn pulls_on_every_run(&self, bin: &FileLocation) -> bool {
+		self.inner.pulls_on_every_run(bin)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{spec_mount, NamedSpecBackend, SpecBackend, SpecMountSource, SPEC_FILE_NAME};
+	use crate::options::sample_forms;
+
+	fn mount_target(mount: &str) -> &str {
+		mount
+			.split(',')
+			.find_map(|part| part.strip_prefix("target="))
+			.expect("mount has target")
+	}
+
+	#[test]
+	fn bind_mount_matches_chain() {
+		let (mount, chain) = spec_mount(SpecMountSource::Bind("/tmp/host/spec1234.json"));
+		assert_eq!(mount_target(&mount), chain);
+	}
+
+	#[test]
+	fn volume_mount_matches_chain() {
+		let (mount, chain) = spec_mount(SpecMountSource::Volume("bdk-spec-0"));
+		assert_eq!(format!("{}/{SPEC_FILE_NAME}", mount_target(&mount)), chain);
+	}
+
+	#[test]
+	fn listed_forms_parse() {
+		let (named, forms) = SpecBackend::FORMS.split_last().unwrap();
+		assert_eq!(*named, "<name>=<any of the above>");
+		for form in forms {
+			for sample in sample_forms(form) {
+				sample
+					.parse::<SpecBackend>()
+					.unwrap_or_else(|e| panic!("{sample}: {e}"));
+				let named = format!("relay={sample}");
+				let parsed = named
+					.parse::<NamedSpecBackend>()
+					.unwrap_or_else(|e| panic!("{named}: {e}"));
+				assert_eq!(parsed.name.as_deref(), Some("relay"));
+			}
+		}
+	}
 }
@@ -0,0 +1,86 @@
+use jrsonnet_evaluator::{bail, Result, Val};
+
+/// Resolve dotted path (i.e `nodes.alice.spec`) in the value.
+///
+/// Returns `None` if any of the path fields is missing.
+pub fn get_path(val: &Val, path: &str) -> Result<Option<Val>> {
+	let mut current = val.clone();
+	for field in path.split('.').filter(|f| !f.is_empty()) {
+		let Val::Obj(obj) = current else {
+			bail!(
+				"{path}: expected object at {field:?}, got {}",
+				current.value_type()
+			);
+		};
+		match obj.get(field.into())? {
+			Some(v) => current = v,
+			None => return Ok(None),
+		}
+	}
+	Ok(Some(current))
+}
+
+/// Resolve dotted path, where `*` matches every visible field of an object.
+///
+/// Returns pairs of resolved concrete path and value, missing paths are skipped.
+pub fn glob_path(val: &Val, path: &str) -> Result<Vec<(String, Val)>> {
+	let mut current = vec![(String::new(), val.clone())];
+	for field in path.split('.').filter(|f| !f.is_empty()) {
+		let mut next = Vec::new();
+		for (prefix, val) in current {
+			let Val::Obj(obj) = val else {
+				bail!(
+					"{path}: expected object at {prefix:?}, got {}",
+					val.value_type()
+				);
+			};
+			let join = |name: &str| {
+				if prefix.is_empty() {
+					name.to_owned()
+				} else {
+					format!("{prefix}.{name}")
+				}
+			};
+			if field == "*" {
+				for (name, value) in obj.iter(false) {
+					next.push((join(&name), value?));
+				}
+			} else if let Some(value) = obj.get(field.into())? {
+				next.push((join(field), value));
+			}
+		}
+		current = next;
+	}
+	Ok(current)
+}
+
+/// Match name against shell-like pattern, where `*` matches any (possibly empty) sequence of
+/// characters, and `?` matches any single character.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+	let pattern = pattern.chars().collect::<Vec<_>>();
+	let name = name.chars().collect::<Vec<_>>();
+	let (mut p, mut n) = (0, 0);
+	// Position of the last `*` in the pattern, and of the name char it is currently matched up to
+	let mut backtrack = None;
+	while n < name.len() {
+		match pattern.get(p) {
+			Some(&'*') => {
+				backtrack = Some((p, n));
+				p += 1;
+			}
+			Some(&c) if c == '?' || c == name[n] => {
+				p += 1;
+				n += 1;
+			}
+			_ => {
+				let Some((star, matched)) = backtrack else {
+					return false;
+				};
+				p = star + 1;
+				n = matched + 1;
+				backtrack = Some((star, n));
+			}
+		}
+	}
+	pattern[p..].iter().all(|&c| c == '*')
+}
@@ -0,0 +1,108 @@
+//! PolkadotJS encrypted JSON wallet format (v3)
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chainql_core::address::{address_seed, SignatureSchema};
+use crypto_secretbox::{
+	aead::{generic_array::GenericArray, Aead, KeyInit},
+	XSalsa20Poly1305,
+};
+use serde_json::{json, Value};
+use sp_core::{crypto::Ss58AddressFormat, ecdsa, ed25519, sr25519, Pair};
+
+use crate::keystore::{Error, Result};
+
+const PKCS8_HEADER: [u8; 16] = [48, 83, 2, 1, 1, 48, 5, 6, 3, 43, 101, 112, 4, 34, 4, 32];
+const PKCS8_DIVIDER: [u8; 5] = [161, 35, 3, 33, 0];
+
+/// Default PolkadotJS scrypt parameters, N = 2^15
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+fn pkcs8(secret: &[u8], public: &[u8]) -> Vec<u8> {
+	let mut out =
+		Vec::with_capacity(PKCS8_HEADER.len() + secret.len() + PKCS8_DIVIDER.len() + public.len());
+	out.extend_from_slice(&PKCS8_HEADER);
+	out.extend_from_slice(secret);
+	out.extend_from_slice(&PKCS8_DIVIDER);
+	out.extend_from_slice(public);
+	out
+}
+
+/// Returns PKCS8-encoded keypair, and PolkadotJS name of the scheme
+fn encode_pair(schema: SignatureSchema, suri: &str) -> Result<(Vec<u8>, &'static str)> {
+	Ok(match schema {
+		SignatureSchema::Sr25519 => {
+			let pair = sr25519::Pair::from_string(suri, None)?;
+			let keypair: &schnorrkel::Keypair = pair.as_ref();
+			let public = pair.public();
+			(
+				pkcs8(&keypair.secret.to_ed25519_bytes(), public.as_ref()),
+				"sr25519",
+			)
+		}
+		SignatureSchema::Ed25519 => {
+			let pair = ed25519::Pair::from_string(suri, None)?;
+			let public = pair.public();
+			let public: &[u8] = public.as_ref();
+			// Secret key in tweetnacl format: seed followed by public key
+			let mut secret = pair.seed().to_vec();
+			secret.extend_from_slice(public);
+			(pkcs8(&secret, public), "ed25519")
+		}
+		SignatureSchema::Ecdsa => {
+			let pair = ecdsa::Pair::from_string(suri, None)?;
+			let public = pair.public();
+			(pkcs8(&pair.seed(), public.as_ref()), "ecdsa")
+		}
+		#[allow(unreachable_patterns)]
+		_ => {
+			return Err(Error::InvalidParameter(
+				"unsupported wallet scheme for export",
+			))
+		}
+	})
+}
+
+/// Encrypt wallet with the password, producing PolkadotJS-importable JSON
+pub fn encode_wallet(
+	name: &str,
+	schema: SignatureSchema,
+	suri: &str,
+	password: &str,
+	format: Ss58AddressFormat,
+) -> Result<Value> {
+	let (pkcs8, content) = encode_pair(schema, suri)?;
+
+	let salt: [u8; 32] = rand::random();
+	let params = scrypt::Params::new(SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P, 32)
+		.expect("constant params are valid");
+	let mut key = [0; 32];
+	scrypt::scrypt(password.as_bytes(), &salt, &params, &mut key).expect("output length is valid");
+
+	let nonce: [u8; 24] = rand::random();
+	let cipher = XSalsa20Poly1305::new(GenericArray::from_slice(&key));
+	let encrypted = cipher
+		.encrypt(GenericArray::from_slice(&nonce), pkcs8.as_slice())
+		.map_err(|_| Error::InvalidParameter("wallet encryption failed"))?;
+
+	let mut encoded = salt.to_vec();
+	encoded.extend_from_slice(&(1u32 << SCRYPT_LOG_N).to_le_bytes());
+	encoded.extend_from_slice(&SCRYPT_P.to_le_bytes());
+	encoded.extend_from_slice(&SCRYPT_R.to_le_bytes());
+	encoded.extend_from_slice(&nonce);
+	encoded.extend_from_slice(&encrypted);
+
+	Ok(json!({
+		"encoded": STANDARD.encode(encoded),
+		"encoding": {
+			"content": ["pkcs8", content],
+			"type": ["scrypt", "xsalsa20-poly1305"],
+			"version": "3",
+		},
+		"address": address_seed(schema, suri, format)?,
+		"meta": {
+			"name": name,
+		},
+	}))
+}
@@ -0,0 +1,55 @@
+use std::{
+	fmt::Debug,
+	sync::{Arc, Mutex},
+};
+
+use tracing::{
+	field::{Field, Visit},
+	Event, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+/// Tracing layer, which records every emitted warning
+#[derive(Default, Clone)]
+pub struct WarnCollector {
+	warnings: Arc<Mutex<Vec<String>>>,
+}
+impl WarnCollector {
+	pub fn take(&self) -> Vec<String> {
+		std::mem::take(&mut *self.warnings.lock().expect("not poisoned"))
+	}
+}
+
+#[derive(Default)]
+struct MessageVisitor {
+	message: String,
+	fields: Vec<String>,
+}
+impl Visit for MessageVisitor {
+	fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+		if field.name() == "message" {
+			self.message = format!("{value:?}");
+		} else {
+			self.fields.push(format!("{}={value:?}", field.name()));
+		}
+	}
+}
+
+impl<S: Subscriber> Layer<S> for WarnCollector {
+	fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+		if *event.metadata().level() != Level::WARN {
+			return;
+		}
+		let mut visitor = MessageVisitor::default();
+		event.record(&mut visitor);
+		let mut message = visitor.message;
+		for field in visitor.fields {
+			message.push(' ');
+			message.push_str(&field);
+		}
+		self.warnings
+			.lock()
+			.expect("not poisoned")
+			.push(format!("{}: {message}", event.metadata().target()));
+	}
+}